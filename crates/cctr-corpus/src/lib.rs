@@ -37,8 +37,27 @@
 //!
 //! File-level skips go at the top of the file before any tests.
 //! Test-level skips go after the test name, before the closing `===`.
+//!
+//! ## Fenced Commands
+//!
+//! A command can be wrapped in `%command` / `%end` so that lines looking like
+//! separators (`---`, `===`) are taken verbatim as part of the command:
+//!
+//! ```text
+//! ===
+//! count characters in a separator
+//! ===
+//! %command
+//! echo "---" | wc -c
+//! %end
+//! ---
+//! 4
+//! ```
 
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
 use thiserror::Error;
 use winnow::combinator::{alt, opt, repeat};
 use winnow::error::ContextError;
@@ -47,7 +66,7 @@ use winnow::token::{take_till, take_while};
 
 // ============ Data Types ============
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum VarType {
     Number,
     String,
@@ -55,12 +74,39 @@ pub enum VarType {
     JsonBool,
     JsonArray,
     JsonObject,
+    /// An ISO-8601 date or datetime, captured as seconds since the Unix epoch
+    Date,
+    /// A UUID in the canonical 8-4-4-4-12 hex form
+    Uuid,
+    /// Any other annotation, e.g. `{{ v: semver }}`. Matched with a type
+    /// registered on the matcher, or duck-typed if none is registered.
+    Custom(String),
+}
+
+impl VarType {
+    /// The annotation that selects this type, as in `{{ x: json array }}`
+    pub fn name(&self) -> &str {
+        match self {
+            VarType::Number => "number",
+            VarType::String => "string",
+            VarType::JsonString => "json string",
+            VarType::JsonBool => "json bool",
+            VarType::JsonArray => "json array",
+            VarType::JsonObject => "json object",
+            VarType::Date => "date",
+            VarType::Uuid => "uuid",
+            VarType::Custom(name) => name,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct VariableDecl {
     pub name: String,
     pub var_type: Option<VarType>,
+    /// Regex the variable must match, from an annotation like
+    /// `{{ ip: /\d+(\.\d+){3}/ }}`. The captured text is duck-typed.
+    pub custom_regex: Option<String>,
 }
 
 /// Skip directive - unconditional or conditional (with shell command)
@@ -96,10 +142,139 @@ pub enum Shell {
     Cmd,
 }
 
+/// How the expected output is compared against the actual output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Line-for-line comparison with `{{ }}` captures (default)
+    #[default]
+    Pattern,
+    /// Expected and actual output parse to equal JSON values
+    Json,
+    /// Expected JSON is a recursive subset of the actual JSON
+    JsonSubset,
+    /// Expected output is a regex that must match the whole actual output
+    Regex,
+}
+
+/// Tolerances for comparing numbers in the output, from `%numeric-tolerance`
+/// (absolute) and `%numeric-rel-tolerance` (relative to the larger magnitude)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NumericTolerance {
+    pub absolute: Option<f64>,
+    pub relative: Option<f64>,
+}
+
+impl NumericTolerance {
+    pub fn is_set(&self) -> bool {
+        self.absolute.is_some() || self.relative.is_some()
+    }
+}
+
+/// Expected number of output lines, from `%expect-line-count`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCount {
+    pub op: LineCountOp,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCountOp {
+    Eq,
+    AtLeast,
+    AtMost,
+}
+
+impl LineCount {
+    pub fn matches(&self, lines: usize) -> bool {
+        match self.op {
+            LineCountOp::Eq => lines == self.count,
+            LineCountOp::AtLeast => lines >= self.count,
+            LineCountOp::AtMost => lines <= self.count,
+        }
+    }
+}
+
+impl std::fmt::Display for LineCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.op {
+            LineCountOp::Eq => "==",
+            LineCountOp::AtLeast => ">=",
+            LineCountOp::AtMost => "<=",
+        };
+        write!(f, "{} {}", op, self.count)
+    }
+}
+
+/// Acceptable exit codes, from `%exit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedExit {
+    /// `%exit 2`
+    Code(i32),
+    /// `%exit nonzero`
+    NonZero,
+    /// `%exit 1-3`, inclusive
+    Range(i32, i32),
+    /// `%exit 1,2,4`
+    OneOf(Vec<i32>),
+}
+
+impl ExpectedExit {
+    pub fn matches(&self, code: i32) -> bool {
+        match self {
+            ExpectedExit::Code(expected) => code == *expected,
+            ExpectedExit::NonZero => code != 0,
+            ExpectedExit::Range(low, high) => (*low..=*high).contains(&code),
+            ExpectedExit::OneOf(codes) => codes.contains(&code),
+        }
+    }
+}
+
+impl std::fmt::Display for ExpectedExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectedExit::Code(code) => write!(f, "{}", code),
+            ExpectedExit::NonZero => write!(f, "nonzero"),
+            ExpectedExit::Range(low, high) => write!(f, "{}-{}", low, high),
+            ExpectedExit::OneOf(codes) => {
+                let codes: Vec<String> = codes.iter().map(i32::to_string).collect();
+                write!(f, "one of {}", codes.join(", "))
+            }
+        }
+    }
+}
+
+/// Required order of output lines, from `%expect-sorted`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+impl SortOrder {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortOrder::Ascending => "ascending",
+            SortOrder::Descending => "descending",
+        }
+    }
+}
+
+/// Expected output used instead of the default block on some platforms, from
+/// an extra `---` block whose first line is `%platform`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformOutput {
+    pub platforms: Vec<Platform>,
+    pub expected_output: String,
+    pub variables: Vec<VariableDecl>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TestCase {
     pub name: String,
     pub command: String,
+    /// Variables used as `{{ name }}` in the command that some test in the
+    /// file captures; their values from earlier tests are substituted in
+    pub command_variables: Vec<String>,
     pub expected_output: String,
     pub file_path: PathBuf,
     pub start_line: usize,
@@ -109,11 +284,73 @@ pub struct TestCase {
     pub skip: Option<SkipDirective>,
     /// If true and this test fails, skip remaining tests in the file
     pub require: bool,
+    /// If any test in the file sets `%only`, the others are skipped
+    pub only: bool,
+    /// Comparison mode set with `%match`
+    pub match_mode: MatchMode,
+    /// Env files from `%env-file`, relative to the corpus file
+    pub env_files: Vec<String>,
+    /// Variables from `%env KEY=value`, applied after `env_files`
+    pub env: Vec<(String, String)>,
+    pub numeric_tolerance: NumericTolerance,
+    /// Test-level `%shell`, overriding the file-level shell for this test
+    pub shell: Option<Shell>,
+    /// Number of extra attempts for a failing test, from `%retry N`
+    pub retry: u32,
+    /// Only retry when the failing output matches this regex, from `%retry-on /regex/`
+    pub retry_on: Option<String>,
+    /// ASCII-only character classes in the output pattern, from `%ascii`
+    pub ascii: bool,
+    /// Arguments appended to the command, from `%args`
+    pub args: Vec<String>,
+    /// Assertion on the number of output lines, from `%expect-line-count`
+    pub line_count: Option<LineCount>,
+    /// Required order of output lines, from `%expect-sorted`
+    pub sort_order: Option<SortOrder>,
+    /// Fail if any output line appears more than once, from `%expect-unique`
+    pub expect_unique: bool,
+    /// Compare output lines ignoring their order, from `%unordered`
+    pub unordered: bool,
+    /// Ignore trailing whitespace on each line of literal output, from `%trim`
+    pub trim: bool,
+    /// Match the expected output anywhere in the output instead of against
+    /// all of it, from `%contains`
+    pub contains: bool,
+    /// Substrings the output must not contain, from `%not`
+    pub not_contains: Vec<String>,
+    /// Lowercased tags from `%tags` on the test and the file, for `--tags`
+    pub tags: Vec<String>,
+    /// Acceptable exit codes, from `%exit`. Without it, only tests with no
+    /// expected output check the exit code, which must then be 0.
+    pub exit: Option<ExpectedExit>,
+    /// File (relative to the working directory) holding the exit code to
+    /// use instead of the command's own, from `%exit-file`
+    pub exit_file: Option<String>,
+    /// Platform-specific expected output blocks; the first one matching the
+    /// current platform replaces `expected_output` and `variables`
+    pub platform_outputs: Vec<PlatformOutput>,
+    /// Literal expected stderr from a `%stderr` block. When set, stdout and
+    /// stderr are captured separately and `expected_output` is stdout only.
+    pub expected_stderr: Option<String>,
+    /// Golden regex file named by `%expect-matches-file`, whose regex has
+    /// been loaded into `expected_output` with `match_mode` set to `Regex`
+    pub expect_matches_file: Option<String>,
+    /// Text written to the command's stdin, from the `%stdin` block
+    pub stdin: Option<String>,
+    /// Kill the command and fail the test after this long, from `%timeout`
+    /// on the test or, failing that, the file
+    pub timeout: Option<Duration>,
 }
 
 impl TestCase {
+    /// Names of the variables captured by the default expected output or by
+    /// any of the platform-specific blocks
     pub fn variable_names(&self) -> Vec<&str> {
-        self.variables.iter().map(|v| v.name.as_str()).collect()
+        self.variables
+            .iter()
+            .chain(self.platform_outputs.iter().flat_map(|o| &o.variables))
+            .map(|v| v.name.as_str())
+            .collect()
     }
 }
 
@@ -122,6 +359,14 @@ pub struct CorpusFile {
     pub file_skip: Option<SkipDirective>,
     pub file_shell: Option<Shell>,
     pub file_platform: Vec<Platform>,
+    /// Env files from file-level `%env-file`, relative to the corpus file
+    pub file_env_files: Vec<String>,
+    /// Variables from file-level `%env KEY=value`, applied after `file_env_files`
+    pub file_env: Vec<(String, String)>,
+    /// File-level `%timeout`, the default for tests without their own
+    pub file_timeout: Option<Duration>,
+    /// Lowercased tags from file-level `%tags`, also included in each test's tags
+    pub file_tags: Vec<String>,
     pub tests: Vec<TestCase>,
 }
 
@@ -133,21 +378,78 @@ pub enum ParseError {
     Parse { line: usize, message: String },
 }
 
+// ============ Tracing ============
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Turn `[TRACE]` debug lines on stderr on or off (`--trace`)
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether tracing is on, via `set_trace` or a non-empty `CCTR_TRACE`
+pub fn trace_enabled() -> bool {
+    static FROM_ENV: OnceLock<bool> = OnceLock::new();
+    TRACE.load(Ordering::Relaxed)
+        || *FROM_ENV.get_or_init(|| std::env::var("CCTR_TRACE").is_ok_and(|v| !v.is_empty()))
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if trace_enabled() {
+            eprintln!("[TRACE parse] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// The directive name at the start of `input`, for tracing
+fn directive_name(input: &str) -> &str {
+    input.split_whitespace().next().unwrap_or("")
+}
+
 // ============ Public API ============
 
+/// Parse a corpus file from disk. Unlike `parse_content`, this resolves
+/// `%include` directives relative to the file.
 pub fn parse_file(path: &Path) -> Result<CorpusFile, ParseError> {
+    let mut include_stack = vec![path.canonicalize()?];
+    parse_included_file(path, &mut include_stack)
+}
+
+fn parse_included_file(
+    path: &Path,
+    include_stack: &mut Vec<PathBuf>,
+) -> Result<CorpusFile, ParseError> {
     let content = std::fs::read_to_string(path)?;
-    parse_content(&content, path)
+    parse(&content, path, Some(include_stack))
 }
 
 pub fn parse_content(content: &str, path: &Path) -> Result<CorpusFile, ParseError> {
-    let mut state = ParseState::new(content, path);
+    parse(content, path, None)
+}
+
+fn parse(
+    content: &str,
+    path: &Path,
+    include_stack: Option<&mut Vec<PathBuf>>,
+) -> Result<CorpusFile, ParseError> {
+    trace!(
+        "{}: parsing {} lines",
+        path.display(),
+        content.lines().count()
+    );
+    let mut state = ParseState::new(content, path, include_stack);
     match corpus_file(&mut state) {
         Ok(file) => {
             // Validate shell/platform compatibility
-            if let Some(shell) = file.file_shell {
-                if !file.file_platform.is_empty() {
-                    validate_shell_platform(shell, &file.file_platform)?;
+            if !file.file_platform.is_empty() {
+                if let Some(shell) = file.file_shell {
+                    validate_shell_platform(shell, &file.file_platform, 1)?;
+                }
+                for test in &file.tests {
+                    if let Some(shell) = test.shell {
+                        validate_shell_platform(shell, &file.file_platform, test.start_line)?;
+                    }
                 }
             }
             Ok(file)
@@ -162,7 +464,11 @@ pub fn parse_content(content: &str, path: &Path) -> Result<CorpusFile, ParseErro
 }
 
 /// Validate that the shell is compatible with the specified platforms
-fn validate_shell_platform(shell: Shell, platforms: &[Platform]) -> Result<(), ParseError> {
+fn validate_shell_platform(
+    shell: Shell,
+    platforms: &[Platform],
+    line: usize,
+) -> Result<(), ParseError> {
     let is_windows_shell = matches!(shell, Shell::PowerShell | Shell::Cmd);
 
     let has_windows = platforms.contains(&Platform::Windows);
@@ -173,7 +479,7 @@ fn validate_shell_platform(shell: Shell, platforms: &[Platform]) -> Result<(), P
     // Windows-only shells can't run on Unix platforms
     if is_windows_shell && has_unix && !has_windows {
         return Err(ParseError::Parse {
-            line: 1,
+            line,
             message: format!(
                 "shell '{:?}' is not compatible with platforms {:?}",
                 shell, platforms
@@ -184,7 +490,7 @@ fn validate_shell_platform(shell: Shell, platforms: &[Platform]) -> Result<(), P
     // Unix-only shells (sh, zsh) can't run on Windows
     if matches!(shell, Shell::Sh | Shell::Zsh) && has_windows && !has_unix {
         return Err(ParseError::Parse {
-            line: 1,
+            line,
             message: format!(
                 "shell '{:?}' is not compatible with platforms {:?}",
                 shell, platforms
@@ -195,7 +501,7 @@ fn validate_shell_platform(shell: Shell, platforms: &[Platform]) -> Result<(), P
     // cmd is Windows-only
     if shell == Shell::Cmd && has_unix && !has_windows {
         return Err(ParseError::Parse {
-            line: 1,
+            line,
             message: format!(
                 "shell 'cmd' is only available on Windows, but platforms are {:?}",
                 platforms
@@ -214,16 +520,20 @@ struct ParseState<'a> {
     current_line: usize,
     delimiter_len: usize,
     error_message: Option<String>,
+    /// Canonical paths of the files being included, outermost first.
+    /// None when parsing content with no file to resolve `%include` against.
+    include_stack: Option<&'a mut Vec<PathBuf>>,
 }
 
 impl<'a> ParseState<'a> {
-    fn new(input: &'a str, path: &'a Path) -> Self {
+    fn new(input: &'a str, path: &'a Path, include_stack: Option<&'a mut Vec<PathBuf>>) -> Self {
         Self {
             input,
             path,
             current_line: 1,
             delimiter_len: 3,
             error_message: None,
+            include_stack,
         }
     }
 }
@@ -238,7 +548,10 @@ fn parse_type_annotation(type_str: &str) -> Option<VarType> {
         "json bool" => Some(VarType::JsonBool),
         "json array" => Some(VarType::JsonArray),
         "json object" => Some(VarType::JsonObject),
-        _ => None,
+        "date" => Some(VarType::Date),
+        "uuid" => Some(VarType::Uuid),
+        "" => None,
+        other => Some(VarType::Custom(other.to_string())),
     }
 }
 
@@ -251,6 +564,10 @@ const RESERVED_KEYWORDS: &[&str] = &[
     "not",
     "in",
     "forall",
+    "where",
+    "if",
+    "then",
+    "else",
     "contains",
     "startswith",
     "endswith",
@@ -259,33 +576,72 @@ const RESERVED_KEYWORDS: &[&str] = &[
     "type",
     "keys",
     "values",
+    "merge",
     "sum",
+    "avg",
+    "mean",
     "min",
     "max",
     "abs",
+    "int",
+    "float",
+    "sqrt",
+    "pow",
+    "format_number",
     "unique",
+    "flatten",
+    "range",
+    "map",
+    "filter",
+    "union",
+    "intersection",
+    "difference",
+    "sort",
+    "reverse",
+    "all",
+    "any",
+    "slice",
     "lower",
     "upper",
+    "strip",
+    "trim",
+    "trimstart",
+    "trimend",
+    "strip_prefix",
+    "strip_suffix",
+    "replace",
+    "split",
+    "join",
     "number",
     "string",
     "bool",
     "array",
     "object",
     "env",
+    "json_pointer",
+    "captures",
+    "match",
+    "has_field",
+    "version_cmp",
+    "version_lt",
+    "ieq",
+    "icontains",
+    "istartswith",
+    "iendswith",
 ];
 
 fn is_reserved_keyword(name: &str) -> bool {
     RESERVED_KEYWORDS.contains(&name)
 }
 
-fn parse_placeholder(content: &str) -> Result<(String, Option<VarType>), String> {
+fn parse_placeholder(content: &str) -> Result<VariableDecl, String> {
     let content = content.trim();
-    let (name, var_type) = if let Some(colon_pos) = content.find(':') {
-        let name = content[..colon_pos].trim().to_string();
-        let type_str = content[colon_pos + 1..].trim();
-        (name, parse_type_annotation(type_str))
-    } else {
-        (content.to_string(), None)
+    let (name, type_str) = match content.find(':') {
+        Some(colon_pos) => (
+            content[..colon_pos].trim().to_string(),
+            content[colon_pos + 1..].trim(),
+        ),
+        None => (content.to_string(), ""),
     };
 
     if is_reserved_keyword(&name) {
@@ -295,7 +651,49 @@ fn parse_placeholder(content: &str) -> Result<(String, Option<VarType>), String>
         ));
     }
 
-    Ok((name, var_type))
+    // A `/regex/` annotation is kept verbatim instead of naming a type
+    if let Some(pattern) = type_str
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+    {
+        regex::Regex::new(pattern)
+            .map_err(|e| format!("invalid regex for variable '{}': {}", name, e))?;
+        return Ok(VariableDecl {
+            name,
+            var_type: None,
+            custom_regex: Some(pattern.to_string()),
+        });
+    }
+
+    Ok(VariableDecl {
+        name,
+        var_type: parse_type_annotation(type_str),
+        custom_regex: None,
+    })
+}
+
+/// Names used as `{{ name }}` placeholders in a command
+fn command_placeholders(command: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut remaining = command;
+
+    while let Some(start) = remaining.find("{{") {
+        let Some(end) = remaining[start..].find("}}") else {
+            break;
+        };
+        let name = remaining[start + 2..start + end].trim();
+        let is_identifier = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_identifier && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        remaining = &remaining[start + end + 2..];
+    }
+
+    names
 }
 
 fn extract_variables_from_expected(expected: &str) -> Result<Vec<VariableDecl>, String> {
@@ -306,9 +704,9 @@ fn extract_variables_from_expected(expected: &str) -> Result<Vec<VariableDecl>,
     while let Some(start) = remaining.find("{{") {
         if let Some(end) = remaining[start..].find("}}") {
             let content = &remaining[start + 2..start + end];
-            let (name, var_type) = parse_placeholder(content)?;
-            if !name.is_empty() && seen.insert(name.clone()) {
-                variables.push(VariableDecl { name, var_type });
+            let var = parse_placeholder(content)?;
+            if !var.name.is_empty() && seen.insert(var.name.clone()) {
+                variables.push(var);
             }
             remaining = &remaining[start + end + 2..];
         } else {
@@ -385,6 +783,44 @@ fn skip_blank_lines(input: &mut &str) -> ModalResult<()> {
         .parse_next(input)
 }
 
+fn is_comment_line(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+fn comment_line(input: &mut &str) -> ModalResult<()> {
+    (take_while(0.., ' '), '#', line_content, opt_newline)
+        .map(|_| ())
+        .parse_next(input)
+}
+
+/// Skip blank lines and `#` comments, which are allowed between tests and
+/// among directives but never inside command or expected output blocks.
+/// Returns the number of lines skipped.
+fn skip_blank_and_comment_lines(input: &mut &str) -> ModalResult<usize> {
+    repeat(0.., alt((blank_line, comment_line)))
+        .map(|lines: Vec<()>| lines.len())
+        .parse_next(input)
+}
+
+/// Whether `input`, starting at a blank line, is only blank lines and at
+/// least one `#` comment up to the next test's header. Those comments sit
+/// between tests; anywhere else a `#` line is literal block content.
+fn comments_before_header(input: &str, delimiter_len: usize) -> bool {
+    let mut saw_comment = false;
+    for line in input.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if is_comment_line(line) {
+            saw_comment = true;
+            continue;
+        }
+        return saw_comment && trimmed.len() == delimiter_len && trimmed.chars().all(|c| c == '=');
+    }
+    false
+}
+
 fn is_any_separator_line(line: &str) -> bool {
     let trimmed = line.trim();
     (trimmed.len() >= 3 && trimmed.chars().all(|c| c == '='))
@@ -485,6 +921,382 @@ fn shell_directive(input: &mut &str) -> ModalResult<Shell> {
     Ok(shell)
 }
 
+// ============ Env File Directive Parser ============
+
+fn env_file_directive(input: &mut &str) -> ModalResult<String> {
+    "%env-file".parse_next(input)?;
+    let _ = take_while(1.., ' ').parse_next(input)?;
+    let path = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let path = path.trim();
+    if path.is_empty() {
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+    Ok(path.to_string())
+}
+
+fn parse_env_file_directive(
+    input: &mut &str,
+    error_message: &mut Option<String>,
+) -> Result<String, winnow::error::ErrMode<ContextError>> {
+    env_file_directive.parse_next(input).inspect_err(|_| {
+        *error_message = Some("%env-file requires a path".to_string());
+    })
+}
+
+/// `%env KEY=value`; the value is the rest of the line, trimmed
+fn env_directive(input: &mut &str) -> ModalResult<(String, String)> {
+    "%env".parse_next(input)?;
+    let _ = take_while(1.., ' ').parse_next(input)?;
+    let assignment = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let Some((key, value)) = assignment.split_once('=') else {
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    };
+    let key = key.trim();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+fn parse_env_directive(
+    input: &mut &str,
+    error_message: &mut Option<String>,
+) -> Result<(String, String), winnow::error::ErrMode<ContextError>> {
+    env_directive.parse_next(input).inspect_err(|_| {
+        *error_message = Some("%env requires KEY=value".to_string());
+    })
+}
+
+// ============ Numeric Tolerance Directive Parser ============
+
+/// Parse `%numeric-tolerance <n>` or `%numeric-rel-tolerance <n>`, returning
+/// the directive name and the value if it is a valid non-negative number.
+fn tolerance_directive<'a>(input: &mut &'a str) -> ModalResult<(&'a str, Option<f64>)> {
+    let name = alt(("%numeric-tolerance", "%numeric-rel-tolerance")).parse_next(input)?;
+    let value = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let value = value.trim().parse::<f64>().ok().filter(|v| *v >= 0.0);
+    Ok((name, value))
+}
+
+// ============ Ascii Directive Parser ============
+
+fn ascii_directive(input: &mut &str) -> ModalResult<()> {
+    "%ascii".parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(())
+}
+
+// ============ Args Directive Parser ============
+
+/// Split a line into words, honoring single and double quotes and backslash
+/// escapes like a shell would. Returns None on an unterminated quote.
+fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => current.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => current.push(chars.next()?),
+                        c => current.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                current.push(chars.next()?);
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_word {
+        args.push(current);
+    }
+    Some(args)
+}
+
+fn args_directive(input: &mut &str) -> ModalResult<Option<Vec<String>>> {
+    "%args".parse_next(input)?;
+    let line = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(split_args(line))
+}
+
+// ============ Retry Directive Parsers ============
+
+fn retry_directive(input: &mut &str) -> ModalResult<Option<u32>> {
+    "%retry".parse_next(input)?;
+    let count = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(count.trim().parse().ok())
+}
+
+/// `%timeout <seconds>`; fractional seconds are allowed
+fn timeout_directive(input: &mut &str) -> ModalResult<Option<Duration>> {
+    "%timeout".parse_next(input)?;
+    let seconds = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(seconds
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite() && *s > 0.0)
+        .map(Duration::from_secs_f64))
+}
+
+fn retry_on_directive(input: &mut &str) -> ModalResult<Option<String>> {
+    "%retry-on".parse_next(input)?;
+    let pattern = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(pattern
+        .trim()
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix('/'))
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_string()))
+}
+
+/// `%expect-line-count N`, optionally with a comparator: `== N`, `>= N` or `<= N`
+fn expect_line_count_directive(input: &mut &str) -> ModalResult<Option<LineCount>> {
+    "%expect-line-count".parse_next(input)?;
+    let spec = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+
+    let spec = spec.trim();
+    let (op, count) = if let Some(rest) = spec.strip_prefix(">=") {
+        (LineCountOp::AtLeast, rest)
+    } else if let Some(rest) = spec.strip_prefix("<=") {
+        (LineCountOp::AtMost, rest)
+    } else if let Some(rest) = spec.strip_prefix("==") {
+        (LineCountOp::Eq, rest)
+    } else {
+        (LineCountOp::Eq, spec)
+    };
+    Ok(count
+        .trim()
+        .parse()
+        .ok()
+        .map(|count| LineCount { op, count }))
+}
+
+/// `%expect-sorted`, optionally followed by `asc` (the default) or `desc`
+fn expect_sorted_directive(input: &mut &str) -> ModalResult<Option<SortOrder>> {
+    "%expect-sorted".parse_next(input)?;
+    let order = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(match order.trim() {
+        "" | "asc" => Some(SortOrder::Ascending),
+        "desc" => Some(SortOrder::Descending),
+        _ => None,
+    })
+}
+
+fn expect_unique_directive(input: &mut &str) -> ModalResult<()> {
+    "%expect-unique".parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(())
+}
+
+/// `%not <substring>`; the substring is the rest of the line, trimmed
+fn not_directive(input: &mut &str) -> ModalResult<Option<String>> {
+    "%not".parse_next(input)?;
+    let substring = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let substring = substring.trim();
+    Ok((!substring.is_empty()).then(|| substring.to_string()))
+}
+
+/// `%tags a, b, c`; tags are lowercased so matching is case-insensitive
+fn tags_directive(input: &mut &str) -> ModalResult<Vec<String>> {
+    "%tags".parse_next(input)?;
+    let tags = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(tags
+        .split(',')
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect())
+}
+
+fn parse_tags_directive(
+    input: &mut &str,
+    tags: &mut Vec<String>,
+    error_message: &mut Option<String>,
+) -> Result<(), winnow::error::ErrMode<ContextError>> {
+    let parsed = tags_directive.parse_next(input)?;
+    if parsed.is_empty() {
+        *error_message = Some("%tags requires at least one tag".to_string());
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+    for tag in parsed {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(())
+}
+
+/// Parse `%exit N`, `%exit nonzero`, `%exit LOW-HIGH` or `%exit A,B,...`,
+/// returning None if the value isn't one of those
+fn exit_directive(input: &mut &str) -> ModalResult<Option<ExpectedExit>> {
+    "%exit".parse_next(input)?;
+    let value = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let value = value.trim();
+    let code = |s: &str| s.trim().parse::<i32>().ok();
+
+    if value.eq_ignore_ascii_case("nonzero") {
+        return Ok(Some(ExpectedExit::NonZero));
+    }
+    if let Some(code) = code(value) {
+        return Ok(Some(ExpectedExit::Code(code)));
+    }
+    if value.contains(',') {
+        let codes: Option<Vec<i32>> = value.split(',').map(code).collect();
+        return Ok(codes.map(ExpectedExit::OneOf));
+    }
+    // Split at the first `-` after the start, so a negative lower bound works
+    let range = value
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '-')
+        .and_then(|(i, _)| Some((code(&value[..i])?, code(&value[i + 1..])?)))
+        .filter(|(low, high)| low <= high);
+    Ok(range.map(|(low, high)| ExpectedExit::Range(low, high)))
+}
+
+fn exit_file_directive(input: &mut &str) -> ModalResult<Option<String>> {
+    "%exit-file".parse_next(input)?;
+    let path = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let path = path.trim();
+    Ok((!path.is_empty()).then(|| path.to_string()))
+}
+
+fn include_directive(input: &mut &str) -> ModalResult<Option<String>> {
+    "%include".parse_next(input)?;
+    let path = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let path = path.trim();
+    Ok((!path.is_empty()).then(|| path.to_string()))
+}
+
+/// Parse the file `path`, relative to the corpus file, and return its tests
+fn include_tests(
+    corpus_path: &Path,
+    path: &str,
+    include_stack: Option<&mut Vec<PathBuf>>,
+) -> Result<Vec<TestCase>, String> {
+    let Some(include_stack) = include_stack else {
+        return Err("%include is only supported in corpus files read from disk".to_string());
+    };
+    let full_path = corpus_path.parent().unwrap_or(Path::new(".")).join(path);
+    let canonical = full_path
+        .canonicalize()
+        .map_err(|e| format!("failed to read included file {}: {}", path, e))?;
+    if include_stack.contains(&canonical) {
+        let cycle: Vec<String> = include_stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(format!("cyclic %include: {}", cycle.join(" -> ")));
+    }
+
+    include_stack.push(canonical);
+    let result = parse_included_file(&full_path, include_stack);
+    include_stack.pop();
+    match result {
+        Ok(file) => Ok(file.tests),
+        Err(ParseError::Parse { message, .. }) if message.starts_with("cyclic %include") => {
+            Err(message)
+        }
+        Err(e) => Err(format!("in included file {}: {}", path, e)),
+    }
+}
+
+fn expect_matches_file_directive(input: &mut &str) -> ModalResult<Option<String>> {
+    "%expect-matches-file".parse_next(input)?;
+    let path = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    let path = path.trim();
+    Ok((!path.is_empty()).then(|| path.to_string()))
+}
+
+/// Load a golden regex written as `/regex/flags` (flags from `imsxU`, all
+/// optional) from `path`, relative to the corpus file, as an inline-flagged
+/// pattern like `(?i)regex`
+fn load_golden_regex(corpus_path: &Path, path: &str) -> Result<String, String> {
+    let full_path = corpus_path.parent().unwrap_or(Path::new(".")).join(path);
+    let content = std::fs::read_to_string(&full_path)
+        .map_err(|e| format!("failed to read %expect-matches-file '{}': {}", path, e))?;
+    let (pattern, flags) = content
+        .trim()
+        .strip_prefix('/')
+        .and_then(|rest| rest.rsplit_once('/'))
+        .filter(|(pattern, flags)| {
+            !pattern.is_empty() && flags.chars().all(|c| "imsxU".contains(c))
+        })
+        .ok_or_else(|| {
+            format!(
+                "%expect-matches-file '{}' must contain /regex/ optionally followed by flags (i, m, s, x, U)",
+                path
+            )
+        })?;
+    let pattern = if flags.is_empty() {
+        pattern.to_string()
+    } else {
+        format!("(?{}){}", flags, pattern)
+    };
+    regex::Regex::new(&pattern)
+        .map_err(|e| format!("invalid regex in %expect-matches-file '{}': {}", path, e))?;
+    Ok(pattern)
+}
+
+// ============ Match Directive Parser ============
+
+fn match_directive(input: &mut &str) -> ModalResult<Option<MatchMode>> {
+    "%match".parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    let mode = line_content.parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(match mode.trim() {
+        "json" => Some(MatchMode::Json),
+        "json-subset" => Some(MatchMode::JsonSubset),
+        "regex" => Some(MatchMode::Regex),
+        _ => None,
+    })
+}
+
 // ============ Test Case Parser ============
 
 fn description_line(input: &mut &str) -> ModalResult<String> {
@@ -509,6 +1321,9 @@ fn read_block_until_separator(input: &mut &str, delimiter_len: usize) -> String
         if is_any_separator_line(peek_line) && trimmed.len() == delimiter_len {
             break;
         }
+        if trimmed.is_empty() && comments_before_header(input, delimiter_len) {
+            break;
+        }
 
         let line = line_content.parse_next(input).unwrap_or("");
         opt_newline.parse_next(input).ok();
@@ -522,6 +1337,32 @@ fn read_block_until_separator(input: &mut &str, delimiter_len: usize) -> String
     lines.join("\n")
 }
 
+/// Read a fenced command block opened by `%command` and closed by `%end`.
+/// Every line in between is taken verbatim, including lines that look like separators.
+/// Returns the command and the number of lines consumed (including both fence lines),
+/// or None if the closing `%end` is missing.
+fn read_fenced_command(input: &mut &str) -> Option<(String, usize)> {
+    let mut remaining = *input;
+    let _ = line_content.parse_next(&mut remaining).ok()?;
+    opt_newline.parse_next(&mut remaining).ok()?;
+
+    let mut lines = Vec::new();
+    loop {
+        if remaining.is_empty() {
+            return None;
+        }
+        let line = line_content.parse_next(&mut remaining).ok()?;
+        opt_newline.parse_next(&mut remaining).ok()?;
+        if line.trim() == "%end" {
+            break;
+        }
+        lines.push(line);
+    }
+
+    *input = remaining;
+    Some((lines.join("\n"), lines.len() + 2))
+}
+
 fn constraint_line(input: &mut &str) -> ModalResult<String> {
     let _ = take_while(0.., ' ').parse_next(input)?;
     let _ = opt('*').parse_next(input)?;
@@ -531,13 +1372,37 @@ fn constraint_line(input: &mut &str) -> ModalResult<String> {
     opt_newline.parse_next(input)?;
 
     let trimmed = content.trim();
-    if trimmed.is_empty() || trimmed == "where" {
+    if trimmed.is_empty() || trimmed == "where" || trimmed.starts_with('#') {
         Err(winnow::error::ErrMode::Backtrack(ContextError::new()))
     } else {
         Ok(trimmed.to_string())
     }
 }
 
+/// A `---` block after the expected output whose first line is `%platform`
+fn platform_output_block(
+    input: &mut &str,
+    delimiter_len: usize,
+) -> ModalResult<(Vec<Platform>, String)> {
+    dash_sep_exact(input, delimiter_len)?;
+    opt_newline.parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    let platforms = platform_directive.parse_next(input)?;
+    let expected = read_block_until_separator(input, delimiter_len);
+    Ok((platforms, expected))
+}
+
+/// A `---` block after the expected output whose first line is `%stderr`
+fn stderr_output_block(input: &mut &str, delimiter_len: usize) -> ModalResult<String> {
+    dash_sep_exact(input, delimiter_len)?;
+    opt_newline.parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    "%stderr".parse_next(input)?;
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    opt_newline.parse_next(input)?;
+    Ok(read_block_until_separator(input, delimiter_len))
+}
+
 fn where_section(input: &mut &str, delimiter_len: usize) -> ModalResult<Vec<String>> {
     dash_sep_exact(input, delimiter_len)?;
     opt_newline.parse_next(input)?;
@@ -555,7 +1420,7 @@ fn where_section(input: &mut &str, delimiter_len: usize) -> ModalResult<Vec<Stri
 fn test_case(state: &mut ParseState) -> Result<TestCase, winnow::error::ErrMode<ContextError>> {
     let input = &mut state.input;
 
-    skip_blank_lines.parse_next(input)?;
+    state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
 
     let start_line = state.current_line;
 
@@ -566,14 +1431,53 @@ fn test_case(state: &mut ParseState) -> Result<TestCase, winnow::error::ErrMode<
 
     let name = description_line.parse_next(input)?;
     state.current_line += 1;
+    trace!(
+        "{}:{}: test {:?}, delimiter length {}",
+        state.path.display(),
+        start_line,
+        name,
+        delimiter_len
+    );
 
-    // Parse test-level directives (%skip and %require allowed at test level)
+    // Parse test-level directives (%skip, %require, %only, %shell, %match, %env-file, %env,
+    // %retry, %ascii, %args, %expect-line-count, %expect-sorted, %expect-unique,
+    // %unordered, %contains, %trim, %not, %tags, %exit, %exit-file, %timeout, %expect-matches-file, %stdin and numeric
+    // tolerances allowed at test level)
     let mut skip = None;
     let mut require = false;
+    let mut only = false;
+    let mut match_mode = MatchMode::default();
+    let mut env_files = Vec::new();
+    let mut env = Vec::new();
+    let mut numeric_tolerance = NumericTolerance::default();
+    let mut shell = None;
+    let mut retry = 0;
+    let mut retry_on = None;
+    let mut ascii = false;
+    let mut args = Vec::new();
+    let mut line_count = None;
+    let mut sort_order = None;
+    let mut expect_unique = false;
+    let mut unordered = false;
+    let mut contains = false;
+    let mut trim = false;
+    let mut not_contains = Vec::new();
+    let mut tags = Vec::new();
+    let mut exit = None;
+    let mut exit_file = None;
+    let mut timeout = None;
+    let mut golden_regex = None;
+    let mut stdin = None;
 
     loop {
         let _ = take_while(0.., ' ').parse_next(input)?;
-        if input.starts_with("%skip") && skip.is_none() {
+        let directive = directive_name(input);
+        let directive_line = state.current_line;
+        if input.starts_with('#') {
+            comment_line.parse_next(input)?;
+            state.current_line += 1;
+            continue;
+        } else if input.starts_with("%skip") && skip.is_none() {
             skip = Some(skip_directive.parse_next(input)?);
             state.current_line += 1;
         } else if input.starts_with("%require") {
@@ -582,22 +1486,192 @@ fn test_case(state: &mut ParseState) -> Result<TestCase, winnow::error::ErrMode<
             let _ = opt('\n').parse_next(input)?;
             require = true;
             state.current_line += 1;
-        } else {
-            break;
-        }
-    }
-
-    // Check for directives that are only allowed at file level
-    let _ = take_while(0.., ' ').parse_next(input)?;
-    if input.starts_with("%platform") {
-        state.error_message =
-            Some("%platform is only allowed at file level, not inside test headers".to_string());
-        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
-    }
-    if input.starts_with("%shell") {
-        state.error_message =
-            Some("%shell is only allowed at file level, not inside test headers".to_string());
-        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+        } else if input.starts_with("%only") {
+            "%only".parse_next(input)?;
+            let _ = take_while(0.., ' ').parse_next(input)?;
+            let _ = opt('\n').parse_next(input)?;
+            only = true;
+            state.current_line += 1;
+        } else if input.starts_with("%ascii") {
+            ascii_directive.parse_next(input)?;
+            ascii = true;
+            state.current_line += 1;
+        } else if input.starts_with("%args") {
+            let Some(parsed) = args_directive.parse_next(input)? else {
+                state.error_message = Some("unterminated quote in %args".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            args.extend(parsed);
+            state.current_line += 1;
+        } else if input.starts_with("%expect-line-count") {
+            let Some(parsed) = expect_line_count_directive.parse_next(input)? else {
+                state.error_message = Some(
+                    "%expect-line-count requires a line count, optionally preceded by ==, >= or <="
+                        .to_string(),
+                );
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            line_count = Some(parsed);
+            state.current_line += 1;
+        } else if input.starts_with("%expect-sorted") {
+            let Some(order) = expect_sorted_directive.parse_next(input)? else {
+                state.error_message =
+                    Some("unknown %expect-sorted order: expected 'asc' or 'desc'".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            sort_order = Some(order);
+            state.current_line += 1;
+        } else if input.starts_with("%stdin") {
+            // The block runs to the header separator, so %stdin comes last
+            "%stdin".parse_next(input)?;
+            let _ = take_while(0.., ' ').parse_next(input)?;
+            opt_newline.parse_next(input)?;
+            let before = *input;
+            let block = read_block_until_separator(input, delimiter_len);
+            let consumed = &before[..before.len() - input.len()];
+            state.current_line += 1 + consumed.matches('\n').count();
+            stdin = Some(block);
+        } else if input.starts_with("%expect-matches-file") {
+            let Some(path) = expect_matches_file_directive.parse_next(input)? else {
+                state.error_message = Some("%expect-matches-file requires a file path".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            match load_golden_regex(state.path, &path) {
+                Ok(pattern) => golden_regex = Some((path, pattern)),
+                Err(e) => {
+                    state.error_message = Some(e);
+                    return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+                }
+            }
+            state.current_line += 1;
+        } else if input.starts_with("%expect-unique") {
+            expect_unique_directive.parse_next(input)?;
+            expect_unique = true;
+            state.current_line += 1;
+        } else if input.starts_with("%unordered") {
+            "%unordered".parse_next(input)?;
+            let _ = take_while(0.., ' ').parse_next(input)?;
+            opt_newline.parse_next(input)?;
+            unordered = true;
+            state.current_line += 1;
+        } else if input.starts_with("%contains") {
+            "%contains".parse_next(input)?;
+            let _ = take_while(0.., ' ').parse_next(input)?;
+            opt_newline.parse_next(input)?;
+            contains = true;
+            state.current_line += 1;
+        } else if input.starts_with("%trim") {
+            "%trim".parse_next(input)?;
+            let _ = take_while(0.., ' ').parse_next(input)?;
+            opt_newline.parse_next(input)?;
+            trim = true;
+            state.current_line += 1;
+        } else if input.starts_with("%not") {
+            let Some(substring) = not_directive.parse_next(input)? else {
+                state.error_message = Some("%not requires a substring".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            not_contains.push(substring);
+            state.current_line += 1;
+        } else if input.starts_with("%tags") {
+            parse_tags_directive(input, &mut tags, &mut state.error_message)?;
+            state.current_line += 1;
+        } else if directive == "%exit" {
+            let Some(parsed) = exit_directive.parse_next(input)? else {
+                state.error_message = Some(
+                    "%exit requires an exit code, 'nonzero', a range like 1-3 or a list like 1,2,4"
+                        .to_string(),
+                );
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            exit = Some(parsed);
+            state.current_line += 1;
+        } else if input.starts_with("%exit-file") {
+            let Some(path) = exit_file_directive.parse_next(input)? else {
+                state.error_message = Some("%exit-file requires a file path".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            exit_file = Some(path);
+            state.current_line += 1;
+        } else if input.starts_with("%timeout") {
+            let Some(parsed) = timeout_directive.parse_next(input)? else {
+                state.error_message =
+                    Some("%timeout requires a positive number of seconds".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            timeout = Some(parsed);
+            state.current_line += 1;
+        } else if input.starts_with("%retry-on") {
+            let Some(pattern) = retry_on_directive.parse_next(input)? else {
+                state.error_message = Some("%retry-on requires a /regex/ pattern".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            retry_on = Some(pattern);
+            state.current_line += 1;
+        } else if input.starts_with("%retry") {
+            let Some(count) = retry_directive.parse_next(input)? else {
+                state.error_message =
+                    Some("%retry requires a non-negative number of retries".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            retry = count;
+            state.current_line += 1;
+        } else if input.starts_with("%shell") {
+            if shell.is_some() {
+                state.error_message = Some("duplicate %shell in test header".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            }
+            shell = Some(shell_directive.parse_next(input)?);
+            state.current_line += 1;
+        } else if input.starts_with("%match") {
+            let Some(mode) = match_directive.parse_next(input)? else {
+                state.error_message = Some(
+                    "unknown %match mode: expected 'json', 'json-subset' or 'regex'".to_string(),
+                );
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            match_mode = mode;
+            state.current_line += 1;
+        } else if input.starts_with("%env-file") {
+            env_files.push(parse_env_file_directive(input, &mut state.error_message)?);
+            state.current_line += 1;
+        } else if input.starts_with("%env") {
+            env.push(parse_env_directive(input, &mut state.error_message)?);
+            state.current_line += 1;
+        } else if input.starts_with("%numeric-") {
+            let (name, value) = tolerance_directive.parse_next(input)?;
+            let Some(value) = value else {
+                state.error_message = Some(format!("{} requires a non-negative number", name));
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            if name == "%numeric-tolerance" {
+                numeric_tolerance.absolute = Some(value);
+            } else {
+                numeric_tolerance.relative = Some(value);
+            }
+            state.current_line += 1;
+        } else {
+            break;
+        }
+        trace!(
+            "{}:{}: test-level {}",
+            state.path.display(),
+            directive_line,
+            directive
+        );
+    }
+
+    if retry_on.is_some() && retry == 0 {
+        state.error_message = Some("%retry-on has no effect without %retry N".to_string());
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+
+    // Check for directives that are only allowed at file level
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    if input.starts_with("%platform") {
+        state.error_message =
+            Some("%platform is only allowed at file level, not inside test headers".to_string());
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
     }
 
     if let Some(err) = input
@@ -613,36 +1687,158 @@ fn test_case(state: &mut ParseState) -> Result<TestCase, winnow::error::ErrMode<
     state.current_line += 1;
 
     let command_start = state.current_line;
-    let command = read_block_until_separator(input, delimiter_len);
-    state.current_line = command_start + command.lines().count().max(1);
+    let _ = take_while(0.., ' ').parse_next(input)?;
+    let command = if input.starts_with("%command") {
+        let Some((command, line_count)) = read_fenced_command(input) else {
+            state.error_message =
+                Some("unterminated %command block: expected a closing %end line".to_string());
+            return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+        };
+        state.current_line = command_start + line_count;
+        skip_blank_lines.parse_next(input)?;
+        command
+    } else {
+        let command = read_block_until_separator(input, delimiter_len);
+        state.current_line = command_start + command.lines().count().max(1);
+        command
+    };
+
+    trace!(
+        "{}:{}: command block ends at line {}",
+        state.path.display(),
+        command_start,
+        state.current_line.saturating_sub(1).max(command_start)
+    );
 
     dash_sep_exact(input, delimiter_len)?;
     opt_newline.parse_next(input)?;
     state.current_line += 1;
 
     let expected_start = state.current_line;
+    let before = *input;
     let expected_output = read_block_until_separator(input, delimiter_len);
+    let consumed = &before[..before.len() - input.len()];
     let expected_lines = expected_output.lines().count();
-    state.current_line =
-        expected_start + expected_lines.max(if expected_output.is_empty() { 0 } else { 1 });
+    state.current_line = expected_start
+        + consumed
+            .matches('\n')
+            .count()
+            .max(if expected_output.is_empty() { 0 } else { 1 });
+    trace!(
+        "{}:{}: expected output block, {} lines",
+        state.path.display(),
+        expected_start,
+        expected_lines
+    );
+
+    if expected_output.starts_with("%stderr") {
+        state.error_message = Some(
+            "the first expected output block is stdout; put the %stderr block after it".to_string(),
+        );
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+
+    if expected_output.starts_with("%platform") {
+        state.error_message = Some(
+            "the first expected output block is the default for all platforms; put %platform blocks after it"
+                .to_string(),
+        );
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+
+    let mut platform_outputs = Vec::new();
+    while let Some((platforms, expected)) =
+        opt(|i: &mut &str| platform_output_block(i, delimiter_len)).parse_next(input)?
+    {
+        trace!(
+            "{}:{}: expected output block for {:?}",
+            state.path.display(),
+            state.current_line,
+            platforms
+        );
+        state.current_line += 2 + expected.lines().count();
+        let variables = extract_variables_from_expected(&expected).map_err(|e| {
+            state.error_message = Some(e);
+            winnow::error::ErrMode::Backtrack(ContextError::new())
+        })?;
+        platform_outputs.push(PlatformOutput {
+            platforms,
+            expected_output: expected,
+            variables,
+        });
+    }
+
+    let expected_stderr =
+        opt(|i: &mut &str| stderr_output_block(i, delimiter_len)).parse_next(input)?;
+    if let Some(stderr) = &expected_stderr {
+        trace!(
+            "{}:{}: expected stderr block, {} lines",
+            state.path.display(),
+            state.current_line,
+            stderr.lines().count()
+        );
+        state.current_line += 2 + stderr.lines().count();
+    }
 
     let constraints = opt(|i: &mut &str| where_section(i, delimiter_len))
         .parse_next(input)?
         .unwrap_or_default();
     if !constraints.is_empty() {
+        trace!(
+            "{}:{}: where section, {} constraints",
+            state.path.display(),
+            state.current_line,
+            constraints.len()
+        );
         state.current_line += 2 + constraints.len();
     }
 
-    skip_blank_lines.parse_next(input)?;
+    state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
 
     let end_line = state.current_line;
 
-    let variables = extract_variables_from_expected(&expected_output)
-        .map_err(|_| winnow::error::ErrMode::Backtrack(ContextError::new()))?;
+    let variables = extract_variables_from_expected(&expected_output).map_err(|e| {
+        state.error_message = Some(e);
+        winnow::error::ErrMode::Backtrack(ContextError::new())
+    })?;
+
+    if unordered
+        && (!variables.is_empty() || match_mode != MatchMode::Pattern || golden_regex.is_some())
+    {
+        state.error_message = Some(
+            "%unordered only applies to literal expected output, not {{ variables }} or %match"
+                .to_string(),
+        );
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+
+    if contains && (match_mode != MatchMode::Pattern || golden_regex.is_some()) {
+        state.error_message = Some(
+            "%contains only applies to literal expected output or {{ variables }}, not %match"
+                .to_string(),
+        );
+        return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+    }
+
+    // The golden regex stands in for the expected output
+    let (expected_output, match_mode, expect_matches_file) = match golden_regex {
+        Some((path, pattern)) => {
+            if !expected_output.trim().is_empty() || match_mode != MatchMode::Pattern {
+                state.error_message = Some(
+                    "%expect-matches-file replaces the expected output: leave it empty and don't use %match"
+                        .to_string(),
+                );
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            }
+            (pattern, MatchMode::Regex, Some(path))
+        }
+        None => (expected_output, match_mode, None),
+    };
 
     Ok(TestCase {
         name,
         command,
+        command_variables: Vec::new(),
         expected_output,
         file_path: state.path.to_path_buf(),
         start_line,
@@ -651,39 +1847,119 @@ fn test_case(state: &mut ParseState) -> Result<TestCase, winnow::error::ErrMode<
         constraints,
         skip,
         require,
+        only,
+        match_mode,
+        env_files,
+        env,
+        numeric_tolerance,
+        shell,
+        retry,
+        retry_on,
+        ascii,
+        args,
+        line_count,
+        sort_order,
+        expect_unique,
+        unordered,
+        trim,
+        contains,
+        not_contains,
+        tags,
+        exit,
+        exit_file,
+        platform_outputs,
+        expected_stderr,
+        expect_matches_file,
+        stdin,
+        timeout,
     })
 }
 
 fn corpus_file(state: &mut ParseState) -> Result<CorpusFile, winnow::error::ErrMode<ContextError>> {
     let input = &mut state.input;
 
-    skip_blank_lines.parse_next(input)?;
+    state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
 
     // Parse file-level directives (skip, shell, platform can appear in any order)
     let mut file_skip = None;
     let mut file_shell = None;
     let mut file_platform = Vec::new();
+    let mut file_env_files = Vec::new();
+    let mut file_env = Vec::new();
+    let mut file_ascii = false;
+    let mut file_timeout = None;
+    let mut file_tags = Vec::new();
+    let mut included = Vec::new();
 
     loop {
         let _ = take_while(0.., ' ').parse_next(input)?;
+        let directive = directive_name(input);
+        let directive_line = state.current_line;
         if input.starts_with("%skip") && file_skip.is_none() {
             file_skip = Some(skip_directive.parse_next(input)?);
             state.current_line += 1;
-            skip_blank_lines.parse_next(input)?;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
         } else if input.starts_with("%shell") && file_shell.is_none() {
             file_shell = Some(shell_directive.parse_next(input)?);
             state.current_line += 1;
-            skip_blank_lines.parse_next(input)?;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
         } else if input.starts_with("%platform") && file_platform.is_empty() {
             file_platform = platform_directive.parse_next(input)?;
             state.current_line += 1;
-            skip_blank_lines.parse_next(input)?;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%env-file") {
+            file_env_files.push(parse_env_file_directive(input, &mut state.error_message)?);
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%env") {
+            file_env.push(parse_env_directive(input, &mut state.error_message)?);
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%ascii") {
+            ascii_directive.parse_next(input)?;
+            file_ascii = true;
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%tags") {
+            parse_tags_directive(input, &mut file_tags, &mut state.error_message)?;
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%timeout") {
+            let Some(parsed) = timeout_directive.parse_next(input)? else {
+                state.error_message =
+                    Some("%timeout requires a positive number of seconds".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            file_timeout = Some(parsed);
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
+        } else if input.starts_with("%include") {
+            let Some(path) = include_directive.parse_next(input)? else {
+                state.error_message = Some("%include requires a file path".to_string());
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            };
+            match include_tests(state.path, &path, state.include_stack.as_deref_mut()) {
+                Ok(tests) => included.extend(tests),
+                Err(e) => {
+                    state.error_message = Some(e);
+                    return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+                }
+            }
+            state.current_line += 1;
+            state.current_line += skip_blank_and_comment_lines.parse_next(input)?;
         } else {
             break;
         }
+        trace!(
+            "{}:{}: file-level {}",
+            state.path.display(),
+            directive_line,
+            directive
+        );
     }
 
-    let mut tests = Vec::new();
+    // Included tests run first, with this file's file-level settings
+    let mut tests = included;
 
     while !state.input.is_empty() {
         let peeked = state.input.trim_start();
@@ -695,14 +1971,38 @@ fn corpus_file(state: &mut ParseState) -> Result<CorpusFile, winnow::error::ErrM
             break;
         }
 
-        let tc = test_case(state)?;
-        tests.push(tc);
+        tests.push(test_case(state)?);
+    }
+
+    // Only placeholders naming a variable captured in this file are
+    // substituted, so other `{{ ... }}` text (e.g. Go templates) is left alone
+    let captured: std::collections::HashSet<String> = tests
+        .iter()
+        .flat_map(|tc| tc.variable_names())
+        .map(str::to_string)
+        .collect();
+    for tc in &mut tests {
+        tc.command_variables = command_placeholders(&tc.command)
+            .into_iter()
+            .filter(|name| captured.contains(name))
+            .collect();
+        tc.ascii |= file_ascii;
+        tc.timeout = tc.timeout.or(file_timeout);
+        for tag in &file_tags {
+            if !tc.tags.contains(tag) {
+                tc.tags.push(tag.clone());
+            }
+        }
     }
 
     Ok(CorpusFile {
         file_skip,
         file_shell,
         file_platform,
+        file_env_files,
+        file_env,
+        file_timeout,
+        file_tags,
         tests,
     })
 }
@@ -895,6 +2195,55 @@ where
         assert_eq!(file.tests[0].constraints[0], "len(s) == 0");
     }
 
+    #[test]
+    fn test_date_type_annotation() {
+        let content = "===\ndate\n===\necho x\n---\nat {{ ts: Date }}\n";
+        let file = parse_test(content);
+        let vars = &file.tests[0].variables;
+        assert_eq!(vars[0].var_type, Some(VarType::Date));
+        assert_eq!(vars[0].var_type.as_ref().unwrap().name(), "date");
+    }
+
+    #[test]
+    fn test_regex_type_annotation() {
+        let content = "===\nip\n===\necho x\n---\nhost {{ ip: /\\d+\\.\\d+\\.\\d+\\.\\d+/ }}\n";
+        let file = parse_test(content);
+        let vars = &file.tests[0].variables;
+        assert_eq!(vars[0].name, "ip");
+        assert_eq!(vars[0].var_type, None);
+        assert_eq!(vars[0].custom_regex.as_deref(), Some(r"\d+\.\d+\.\d+\.\d+"));
+
+        let content = "===\nip\n===\necho x\n---\n{{ ip: /[0-9+/ }}\n";
+        let err = parse_content(content, Path::new("<test>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid regex for variable 'ip'"), "{}", err);
+    }
+
+    #[test]
+    fn test_command_variables() {
+        let content = "===\nstart\n===\n./start\n---\nport {{ port: number }}\n\n===\nfetch\n===\ncurl localhost:{{ port }}/{{ path }} -f '{{ .Name }}'\n---\nok\n";
+        let file = parse_test(content);
+        assert!(file.tests[0].command_variables.is_empty());
+        // `path` isn't captured anywhere and `.Name` isn't a variable name
+        assert_eq!(file.tests[1].command_variables, vec!["port"]);
+    }
+
+    #[test]
+    fn test_custom_type_annotation() {
+        let content =
+            "===\ncustom\n===\necho x\n---\n{{ v: SemVer }} {{ id: UUID }} {{ n: number }}\n";
+        let file = parse_test(content);
+        let vars = &file.tests[0].variables;
+        assert_eq!(
+            vars[0].var_type,
+            Some(VarType::Custom("semver".to_string()))
+        );
+        assert_eq!(vars[0].var_type.as_ref().unwrap().name(), "semver");
+        assert_eq!(vars[1].var_type, Some(VarType::Uuid));
+        assert_eq!(vars[2].var_type.as_ref().unwrap().name(), "number");
+    }
+
     #[test]
     fn test_skip_unconditional() {
         let content = r#"===
@@ -1592,4 +2941,967 @@ hello
         assert_eq!(file.tests.len(), 1);
         assert!(!file.tests[0].require);
     }
+
+    #[test]
+    fn test_match_directive() {
+        let content = r#"===
+json subset
+%match json-subset
+===
+echo '{"a": 1, "b": 2}'
+---
+{"a": 1}
+
+===
+plain test
+===
+echo hello
+---
+hello
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests.len(), 2);
+        assert_eq!(file.tests[0].match_mode, MatchMode::JsonSubset);
+        assert_eq!(file.tests[0].expected_output, r#"{"a": 1}"#);
+        assert_eq!(file.tests[1].match_mode, MatchMode::Pattern);
+
+        let file = parse_test("===\nregex\n%match regex\n===\necho v1.2\n---\nv(\\d+)\\.(\\d+)\n");
+        assert_eq!(file.tests[0].match_mode, MatchMode::Regex);
+        assert_eq!(file.tests[0].expected_output, r"v(\d+)\.(\d+)");
+    }
+
+    #[test]
+    fn test_env_file_directives() {
+        let content = r#"%env-file common.env
+%platform unix
+
+===
+with env files
+%env-file local.env
+%env-file extra.env
+===
+echo $FOO
+---
+bar
+
+===
+without env files
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.file_env_files, vec!["common.env"]);
+        assert_eq!(file.file_platform, vec![Platform::Unix]);
+        assert_eq!(file.tests[0].env_files, vec!["local.env", "extra.env"]);
+        assert!(file.tests[1].env_files.is_empty());
+    }
+
+    #[test]
+    fn test_env_directives() {
+        let content = r#"%env LANG=C
+%env GREETING=hello world
+
+===
+with env
+%env LANG=en_US.UTF-8
+%env EMPTY=
+%env URL=http://host/?a=b
+===
+echo $LANG
+---
+en_US.UTF-8
+
+===
+without env
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        assert_eq!(
+            file.file_env,
+            vec![
+                ("LANG".to_string(), "C".to_string()),
+                ("GREETING".to_string(), "hello world".to_string()),
+            ]
+        );
+        assert_eq!(
+            file.tests[0].env,
+            vec![
+                ("LANG".to_string(), "en_US.UTF-8".to_string()),
+                ("EMPTY".to_string(), String::new()),
+                ("URL".to_string(), "http://host/?a=b".to_string()),
+            ]
+        );
+        assert!(file.tests[1].env.is_empty());
+    }
+
+    #[test]
+    fn test_env_requires_assignment() {
+        for bad in ["%env", "%env FOO", "%env =bar", "%env-file-ish"] {
+            let content = format!("===\nbad\n{}\n===\necho hi\n---\nhi\n", bad);
+            let err = parse_content(&content, Path::new("test.txt")).unwrap_err();
+            assert!(err.to_string().contains("%env"), "{}: {}", bad, err);
+        }
+    }
+
+    #[test]
+    fn test_env_file_requires_path() {
+        let content = "===
+no path
+%env-file
+===
+echo hi
+---
+hi
+";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err.to_string().contains("%env-file requires a path"));
+    }
+
+    #[test]
+    fn test_numeric_tolerance_directives() {
+        let content = r#"===
+both tolerances
+%numeric-tolerance 0.5
+%numeric-rel-tolerance 0.001
+===
+echo 1000001
+---
+1000000
+
+===
+no tolerance
+===
+echo 1
+---
+1
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].numeric_tolerance.absolute, Some(0.5));
+        assert_eq!(file.tests[0].numeric_tolerance.relative, Some(0.001));
+        assert!(!file.tests[1].numeric_tolerance.is_set());
+    }
+
+    #[test]
+    fn test_numeric_tolerance_invalid_value() {
+        let content = "===\nbad\n%numeric-rel-tolerance lots\n===\necho 1\n---\n1\n";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("%numeric-rel-tolerance requires a non-negative number"));
+    }
+
+    #[test]
+    fn test_test_level_shell() {
+        let content = r#"%shell bash
+
+===
+posix check
+%shell sh
+===
+echo hello
+---
+hello
+
+===
+uses file shell
+===
+echo world
+---
+world
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.file_shell, Some(Shell::Bash));
+        assert_eq!(file.tests[0].shell, Some(Shell::Sh));
+        assert_eq!(file.tests[1].shell, None);
+    }
+
+    #[test]
+    fn test_test_level_shell_platform_validation() {
+        let content = r#"%platform linux
+
+===
+cmd on linux
+%shell cmd
+===
+echo hello
+---
+hello
+"#;
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err.to_string().contains("not compatible"));
+    }
+
+    #[test]
+    fn test_test_level_shell_duplicate() {
+        let content = "===\ntwo shells\n%shell bash\n%shell sh\n===\necho hi\n---\nhi\n";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(
+            err.to_string().contains("duplicate %shell in test header"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_retry_directives() {
+        let content = r#"===
+flaky network call
+%retry 3
+%retry-on /connection (reset|refused)/
+===
+curl -s localhost:8080
+---
+ok
+
+===
+no retry
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].retry, 3);
+        assert_eq!(
+            file.tests[0].retry_on.as_deref(),
+            Some("connection (reset|refused)")
+        );
+        assert_eq!(file.tests[1].retry, 0);
+        assert_eq!(file.tests[1].retry_on, None);
+    }
+
+    #[test]
+    fn test_retry_on_requires_retry() {
+        let content = "===\nbad\n%retry-on /reset/\n===\necho 1\n---\n1\n";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("%retry-on has no effect without %retry N"));
+
+        let content = "===\nbad\n%retry 2\n%retry-on reset\n===\necho 1\n---\n1\n";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("%retry-on requires a /regex/ pattern"));
+    }
+
+    #[test]
+    fn test_ascii_directive() {
+        let content = r#"===
+ascii test
+%ascii
+===
+echo 1
+---
+{{ n }}
+
+===
+unicode test
+===
+echo 1
+---
+{{ n }}
+"#;
+        let file = parse_test(content);
+        assert!(file.tests[0].ascii);
+        assert!(!file.tests[1].ascii);
+
+        let file = parse_test(&format!("%ascii\n\n{}", content));
+        assert!(file.tests.iter().all(|t| t.ascii));
+    }
+
+    #[test]
+    fn test_args_directive() {
+        let content = r#"===
+with args
+%args --name "two words" 'single $quoted' plain\ space
+===
+./greet.sh
+---
+hello
+"#;
+        let file = parse_test(content);
+        assert_eq!(
+            file.tests[0].args,
+            vec!["--name", "two words", "single $quoted", "plain space"]
+        );
+    }
+
+    #[test]
+    fn test_args_unterminated_quote() {
+        let content = "===\nbad\n%args \"oops\n===\necho 1\n---\n1\n";
+        let err = parse_content(content, Path::new("test.txt")).unwrap_err();
+        assert!(err.to_string().contains("unterminated quote in %args"));
+    }
+
+    #[test]
+    fn test_expect_line_count_directive() {
+        let content = r#"===
+exact
+%expect-line-count 3
+===
+seq 3
+---
+
+===
+at least
+%expect-line-count >= 2
+===
+seq 5
+---
+
+===
+at most
+%expect-line-count <=1
+===
+true
+---
+"#;
+        let file = parse_test(content);
+        let counts: Vec<_> = file.tests.iter().map(|t| t.line_count.unwrap()).collect();
+        assert_eq!(
+            counts,
+            vec![
+                LineCount {
+                    op: LineCountOp::Eq,
+                    count: 3
+                },
+                LineCount {
+                    op: LineCountOp::AtLeast,
+                    count: 2
+                },
+                LineCount {
+                    op: LineCountOp::AtMost,
+                    count: 1
+                },
+            ]
+        );
+        assert!(counts[1].matches(5));
+        assert!(!counts[2].matches(2));
+        assert_eq!(counts[1].to_string(), ">= 2");
+
+        let err = parse_content(
+            "===\nbad\n%expect-line-count > 2\n===\ntrue\n---\n",
+            Path::new("test.txt"),
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("%expect-line-count requires a line count"));
+    }
+
+    #[test]
+    fn test_expect_sorted_directive() {
+        let content = r#"===
+default
+%expect-sorted
+===
+ls
+---
+
+===
+descending
+%expect-sorted desc
+===
+ls -r
+---
+
+===
+unsorted
+===
+ls
+---
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].sort_order, Some(SortOrder::Ascending));
+        assert_eq!(file.tests[1].sort_order, Some(SortOrder::Descending));
+        assert_eq!(file.tests[2].sort_order, None);
+
+        let err = parse_content(
+            "===\nbad\n%expect-sorted numeric\n===\nls\n---\n",
+            Path::new("test.txt"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown %expect-sorted order"));
+    }
+
+    #[test]
+    fn test_expect_unique_directive() {
+        let content = "===\ndedup\n%expect-unique\n===\nsort -u list\n---\n\n===\nplain\n===\ncat list\n---\n";
+        let file = parse_test(content);
+        assert!(file.tests[0].expect_unique);
+        assert!(!file.tests[1].expect_unique);
+    }
+
+    #[test]
+    fn test_platform_output_blocks() {
+        let content = r#"===
+sizes
+===
+du -k file
+---
+{{ size }}	file
+---
+%platform macos
+{{ blocks }} file
+---
+%platform linux, windows
+{{ size }}	./file
+---
+where
+* size > 0
+
+===
+next
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        let test = &file.tests[0];
+        assert_eq!(test.expected_output, "{{ size }}\tfile");
+        assert_eq!(test.platform_outputs.len(), 2);
+        assert_eq!(test.platform_outputs[0].platforms, vec![Platform::MacOS]);
+        assert_eq!(
+            test.platform_outputs[0].expected_output,
+            "{{ blocks }} file"
+        );
+        assert_eq!(test.platform_outputs[0].variables[0].name, "blocks");
+        assert_eq!(
+            test.platform_outputs[1].platforms,
+            vec![Platform::Linux, Platform::Windows]
+        );
+        assert_eq!(test.constraints, vec!["size > 0"]);
+        assert_eq!(test.variable_names(), vec!["size", "blocks", "size"]);
+        assert_eq!(file.tests[1].name, "next");
+        assert!(file.tests[1].platform_outputs.is_empty());
+
+        let err = parse_content(
+            "===\nbad\n===\necho hi\n---\n%platform linux\nhi\n",
+            Path::new("test.txt"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("first expected output block"));
+    }
+
+    #[test]
+    fn test_expect_matches_file_directive() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("version.re"), "/v(\\d+)\\.(\\d+)/\n").unwrap();
+        std::fs::write(dir.path().join("greeting.re"), "/hello .*/i").unwrap();
+        let corpus = dir.path().join("test.txt");
+        let content = "===\nversion\n%expect-matches-file version.re\n===\necho v1.2\n---\n\n===\ngreeting\n%expect-matches-file greeting.re\n===\necho HELLO world\n---\n";
+
+        let file = parse_content(content, &corpus).unwrap();
+        assert_eq!(file.tests[0].match_mode, MatchMode::Regex);
+        assert_eq!(file.tests[0].expected_output, r"v(\d+)\.(\d+)");
+        assert_eq!(
+            file.tests[0].expect_matches_file.as_deref(),
+            Some("version.re")
+        );
+        assert_eq!(file.tests[1].expected_output, "(?i)hello .*");
+    }
+
+    #[test]
+    fn test_expect_matches_file_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("bare.re"), "hello").unwrap();
+        std::fs::write(dir.path().join("flags.re"), "/hello/g").unwrap();
+        std::fs::write(dir.path().join("invalid.re"), "/hello (/").unwrap();
+        std::fs::write(dir.path().join("ok.re"), "/hello/").unwrap();
+        let corpus = dir.path().join("test.txt");
+        let parse_error = |header: &str, expected: &str| {
+            let content = format!("===\nbad\n{}\n===\necho hello\n---\n{}", header, expected);
+            parse_content(&content, &corpus).unwrap_err().to_string()
+        };
+
+        let err = parse_error("%expect-matches-file missing.re", "");
+        assert!(err.contains("line 3"), "{}", err);
+        assert!(err.contains("failed to read %expect-matches-file 'missing.re'"));
+        for file in ["bare.re", "flags.re"] {
+            let err = parse_error(&format!("%expect-matches-file {}", file), "");
+            assert!(err.contains("must contain /regex/"), "{}", err);
+        }
+        let err = parse_error("%expect-matches-file invalid.re", "");
+        assert!(err.contains("invalid regex in %expect-matches-file 'invalid.re'"));
+        let err = parse_error("%expect-matches-file", "");
+        assert!(err.contains("requires a file path"));
+        let err = parse_error("%expect-matches-file ok.re", "hello\n");
+        assert!(err.contains("replaces the expected output"));
+        let err = parse_error("%expect-matches-file ok.re\n%match json", "");
+        assert!(err.contains("replaces the expected output"));
+    }
+
+    #[test]
+    fn test_stdin_directive() {
+        let content = r#"===
+reads stdin
+%retry 1
+%stdin
+hello
+  indented
+
+===
+cat
+---
+hello
+  indented
+
+===
+empty stdin
+%stdin
+===
+wc -c
+---
+0
+
+===
+no stdin
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].stdin.as_deref(), Some("hello\n  indented"));
+        assert_eq!(file.tests[0].retry, 1);
+        assert_eq!(file.tests[0].command, "cat");
+        assert_eq!(file.tests[1].stdin.as_deref(), Some(""));
+        assert_eq!(file.tests[1].command, "wc -c");
+        assert_eq!(file.tests[2].stdin, None);
+    }
+
+    #[test]
+    fn test_tags_directive() {
+        let content = r#"%tags Slow, network
+
+===
+tagged test
+%tags db, NETWORK
+%tags smoke
+===
+true
+---
+
+===
+file tags only
+===
+true
+---
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.file_tags, vec!["slow", "network"]);
+        assert_eq!(file.tests[0].tags, vec!["db", "network", "smoke", "slow"]);
+        assert_eq!(file.tests[1].tags, vec!["slow", "network"]);
+
+        let file = parse_test("===\nuntagged\n===\ntrue\n---\n");
+        assert!(file.file_tags.is_empty());
+        assert!(file.tests[0].tags.is_empty());
+
+        for bad in ["%tags", "%tags  , ,"] {
+            let content = format!("===\nbad\n{}\n===\ntrue\n---\n", bad);
+            let err = parse_content(&content, Path::new("test.txt")).unwrap_err();
+            assert!(err.to_string().contains("%tags requires at least one tag"));
+        }
+    }
+
+    #[test]
+    fn test_timeout_directive() {
+        let content = r#"%timeout 30
+
+===
+own timeout
+%timeout 10
+===
+./slow
+---
+
+===
+file timeout
+===
+./slower
+---
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.file_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(file.tests[0].timeout, Some(Duration::from_secs(10)));
+        assert_eq!(file.tests[1].timeout, Some(Duration::from_secs(30)));
+
+        let file = parse_test("===\nfast\n%timeout 0.5\n===\ntrue\n---\n");
+        assert_eq!(file.file_timeout, None);
+        assert_eq!(file.tests[0].timeout, Some(Duration::from_millis(500)));
+
+        let file = parse_test("===\nno timeout\n===\ntrue\n---\n");
+        assert_eq!(file.tests[0].timeout, None);
+
+        for bad in [
+            "%timeout\n",
+            "%timeout soon\n",
+            "%timeout 0\n",
+            "%timeout -1\n",
+        ] {
+            let content = format!("===\nbad\n{}===\ntrue\n---\n", bad);
+            let err = parse_content(&content, Path::new("test.txt")).unwrap_err();
+            assert!(err.to_string().contains("%timeout requires"), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_exit_file_directive() {
+        let content = "===\nasync\n%exit-file out/status\n===\n./start.sh\n---\n";
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].exit_file.as_deref(), Some("out/status"));
+
+        let err = parse_content(
+            "===\nbad\n%exit-file\n===\ntrue\n---\n",
+            Path::new("test.txt"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("%exit-file requires a file path"));
+    }
+
+    #[test]
+    fn test_exit_directive() {
+        let content = "===\ncode\n%exit 2\n===\nx\n---\n\n\
+                       ===\nnonzero\n%exit nonzero\n===\nx\n---\n\n\
+                       ===\nrange\n%exit 1-3\n===\nx\n---\n\n\
+                       ===\nlist\n%exit 1, 2,4\n===\nx\n---\n\n\
+                       ===\nnegative range\n%exit -2-2\n===\nx\n---\n\n\
+                       ===\nnone\n===\nx\n---\n";
+        let file = parse_test(content);
+        let exits: Vec<_> = file.tests.iter().map(|t| t.exit.clone()).collect();
+        assert_eq!(
+            exits,
+            vec![
+                Some(ExpectedExit::Code(2)),
+                Some(ExpectedExit::NonZero),
+                Some(ExpectedExit::Range(1, 3)),
+                Some(ExpectedExit::OneOf(vec![1, 2, 4])),
+                Some(ExpectedExit::Range(-2, 2)),
+                None,
+            ]
+        );
+        assert_eq!(file.tests[0].exit_file, None);
+
+        let range = ExpectedExit::Range(1, 3);
+        assert!(range.matches(1) && range.matches(3));
+        assert!(!range.matches(0) && !range.matches(4));
+        assert!(!ExpectedExit::NonZero.matches(0));
+        assert_eq!(ExpectedExit::OneOf(vec![1, 2]).to_string(), "one of 1, 2");
+    }
+
+    #[test]
+    fn test_exit_directive_invalid() {
+        for value in ["", "abc", "3-1", "1,x", "1-"] {
+            let content = format!("===\nbad\n%exit {}\n===\ntrue\n---\n", value);
+            let err = parse_content(&content, Path::new("test.txt")).unwrap_err();
+            assert!(
+                err.to_string().contains("%exit requires an exit code"),
+                "{:?}: {}",
+                value,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_directive_unknown_mode() {
+        let content = r#"===
+bad mode
+%match yaml
+===
+echo hello
+---
+hello
+"#;
+        let result = parse_content(content, Path::new("test.txt"));
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("unknown %match mode"));
+    }
+
+    #[test]
+    fn test_fenced_command_with_separator_lines() {
+        let content = r#"===
+fenced command
+===
+%command
+cat <<'EOF'
+---
+===
+EOF
+%end
+---
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests.len(), 1);
+        assert_eq!(file.tests[0].command, "cat <<'EOF'\n---\n===\nEOF");
+        assert_eq!(file.tests[0].expected_output, "");
+    }
+
+    #[test]
+    fn test_fenced_command_followed_by_test() {
+        let content = r#"===
+first
+===
+%command
+echo "---" | wc -c
+%end
+---
+4
+
+===
+second
+===
+echo hi
+---
+hi
+"#;
+        let file = parse_test(content);
+        assert_eq!(file.tests.len(), 2);
+        assert_eq!(file.tests[0].command, "echo \"---\" | wc -c");
+        assert_eq!(file.tests[0].expected_output, "4");
+        assert_eq!(file.tests[1].command, "echo hi");
+    }
+
+    #[test]
+    fn test_fenced_command_unterminated() {
+        let content = r#"===
+unterminated
+===
+%command
+echo hi
+---
+hi
+"#;
+        let result = parse_content(content, Path::new("<test>"));
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("%end"));
+    }
+
+    #[test]
+    fn test_directive_name() {
+        assert_eq!(directive_name("%timeout 5\n==="), "%timeout");
+        assert_eq!(directive_name("%skip\n"), "%skip");
+        assert_eq!(directive_name(""), "");
+    }
+
+    #[test]
+    fn test_comment_between_tests_is_ignored() {
+        let content = r#"# about this file
+%shell bash
+
+===
+first
+# explains the test
+===
+echo one
+---
+one
+
+# note
+
+===
+second
+===
+echo two
+---
+two
+"#;
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert_eq!(file.tests.len(), 2);
+        assert_eq!(file.tests[0].expected_output, "one");
+        assert_eq!(file.tests[1].name, "second");
+        assert_eq!(file.tests[1].start_line, 14);
+    }
+
+    #[test]
+    fn test_hash_is_literal_in_blocks() {
+        let content = r#"===
+hash command
+===
+echo #hi
+---
+# out
+"#;
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert_eq!(file.tests[0].command, "echo #hi");
+        assert_eq!(file.tests[0].expected_output, "# out");
+    }
+
+    #[test]
+    fn test_comment_before_next_test_after_blank_line() {
+        let content =
+            "===\nfirst\n===\necho a\n---\na\n\n# note\n\n===\nsecond\n===\necho b\n---\nb\n";
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].expected_output, "a");
+        assert_eq!(file.tests[1].name, "second");
+        assert_eq!(file.tests[1].start_line, 10);
+
+        let content = "===\nliteral\n===\necho a\n---\na\n# literal\n";
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].expected_output, "a\n# literal");
+    }
+
+    #[test]
+    fn test_expected_output_ending_in_heading() {
+        let content = "===\nmarkdown\n===\ncat README.md\n---\nIntro\n\n# Usage\n";
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].expected_output, "Intro\n\n# Usage");
+
+        let content = "===\nmarkdown\n===\ncat README.md\n---\nIntro\n\n# Usage\n\nRun it\n\n===\nnext\n===\ntrue\n---\n";
+        let file = parse_test(content);
+        assert_eq!(file.tests[0].expected_output, "Intro\n\n# Usage\n\nRun it");
+        assert_eq!(file.tests[1].name, "next");
+    }
+
+    #[test]
+    fn test_include_directive() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        std::fs::write(
+            dir.path().join("shared/_prime.txt"),
+            "===\nprime\n===\necho primed\n---\nprimed\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.txt");
+        std::fs::write(
+            &main,
+            "%include shared/_prime.txt\n%tags smoke\n\n===\nown\n===\necho own\n---\nown\n",
+        )
+        .unwrap();
+
+        let file = parse_file(&main).unwrap();
+        let names: Vec<_> = file.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["prime", "own"]);
+        assert!(file.tests[0].file_path.ends_with("shared/_prime.txt"));
+        assert_eq!(file.tests[0].tags, vec!["smoke"]);
+        assert_eq!(file.tests[1].start_line, 4);
+    }
+
+    #[test]
+    fn test_include_cycle_is_an_error() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let a = dir.path().join("a.txt");
+        std::fs::write(&a, "%include b.txt\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "%include a.txt\n").unwrap();
+
+        let err = parse_file(&a).unwrap_err().to_string();
+        assert!(err.contains("cyclic %include"), "{}", err);
+        assert!(err.contains("a.txt -> "), "{}", err);
+    }
+
+    #[test]
+    fn test_include_requires_a_file() {
+        let err = parse_content("%include other.txt\n", Path::new("<stdin>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("only supported in corpus files read from disk"));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let main = dir.path().join("main.txt");
+        std::fs::write(&main, "%include missing.txt\n").unwrap();
+        let err = parse_file(&main).unwrap_err().to_string();
+        assert!(err.contains("failed to read included file missing.txt"));
+    }
+
+    #[test]
+    fn test_not_directive() {
+        let content = r#"===
+no errors
+%not ERROR
+%not  panicked at 
+===
+./build
+---
+ok
+"#;
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert_eq!(file.tests[0].not_contains, vec!["ERROR", "panicked at"]);
+        assert_eq!(file.tests[0].command, "./build");
+
+        let err = parse_content("===\nt\n%not\n===\ntrue\n---\n", Path::new("<test>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("%not requires a substring"));
+    }
+
+    #[test]
+    fn test_only_directive() {
+        let content = "===\nfocused\n%only\n===\ntrue\n---\n\n===\nother\n===\ntrue\n---\n";
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert!(file.tests[0].only);
+        assert!(!file.tests[1].only);
+    }
+
+    #[test]
+    fn test_unordered_directive() {
+        let content = "===\nlisting\n%unordered\n===\nls\n---\na\nb\n";
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert!(file.tests[0].unordered);
+
+        let content = "===\nlisting\n%unordered\n===\nls\n---\n{{ name }}\n";
+        let err = parse_content(content, Path::new("<test>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("%unordered only applies to literal expected output"));
+    }
+
+    #[test]
+    fn test_trim_directive() {
+        let content = "===\nspaces\n%trim\n===\nls\n---\na\n\n===\nexact\n===\nls\n---\na\n";
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert!(file.tests[0].trim);
+        assert!(!file.tests[1].trim);
+    }
+
+    #[test]
+    fn test_contains_directive() {
+        let content = "===\nbanner\n%contains\n===\n./tool\n---\nready in {{ t }}s\n";
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert!(file.tests[0].contains);
+
+        let content = "===\nbanner\n%contains\n%match json\n===\n./tool\n---\n{}\n";
+        let err = parse_content(content, Path::new("<test>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("%contains only applies to literal expected output"));
+    }
+
+    #[test]
+    fn test_stderr_block() {
+        let content = r#"===
+separate streams
+===
+./tool
+---
+result {{ n }}
+---
+%stderr
+warning: deprecated
+---
+where
+* n > 0
+"#;
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        let test = &file.tests[0];
+        assert_eq!(test.expected_output, "result {{ n }}");
+        assert_eq!(test.expected_stderr.as_deref(), Some("warning: deprecated"));
+        assert_eq!(test.constraints, vec!["n > 0"]);
+
+        let content = "===\nempty stderr\n===\necho hi\n---\nhi\n---\n%stderr\n";
+        let file = parse_content(content, Path::new("<test>")).unwrap();
+        assert_eq!(file.tests[0].expected_stderr.as_deref(), Some(""));
+
+        let content = "===\nno stdout block\n===\necho hi\n---\n%stderr\noops\n";
+        let err = parse_content(content, Path::new("<test>"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("put the %stderr block after it"));
+    }
 }
@@ -154,6 +154,30 @@ pub enum Expr {
         var: String,
         iterable: Box<Expr>,
     },
+    /// `x => body`, only valid as an argument to `map` and `filter`
+    Lambda {
+        param: String,
+        body: Box<Expr>,
+    },
+    /// `count x in iterable where predicate`: the number of elements for
+    /// which the predicate holds
+    Count {
+        predicate: Box<Expr>,
+        var: String,
+        iterable: Box<Expr>,
+    },
+    /// `a < b <= c`: true if every adjacent pair compares true. Each operand
+    /// is evaluated at most once, stopping at the first false comparison.
+    Chain {
+        first: Box<Expr>,
+        rest: Vec<(BinaryOp, Expr)>,
+    },
+    /// `if cond then a else b`; only the chosen branch is evaluated
+    Conditional {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        els: Box<Expr>,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -186,6 +210,7 @@ pub enum BinaryOp {
     NotEndsWith,
     Matches,
     NotMatches,
+    Coalesce,
 }
 
 #[derive(Error, Debug, Clone, PartialEq)]
@@ -215,6 +240,10 @@ pub enum EvalError {
     IndexOutOfBounds { index: i64, len: usize },
     #[error("key not found: {0}")]
     KeyNotFound(String),
+    #[error("json pointer not found: {0}")]
+    PointerNotFound(String),
+    #[error("range of {len} elements exceeds the limit of {max}")]
+    RangeTooLarge { len: i128, max: usize },
 }
 
 // ============ Parser ============
@@ -300,6 +329,16 @@ fn ident(input: &mut &str) -> ModalResult<String> {
     Ok(format!("{}{}", first, rest))
 }
 
+fn lambda(input: &mut &str) -> ModalResult<Expr> {
+    let param = ident.parse_next(input)?;
+    let _ = (multispace0, "=>", multispace0).parse_next(input)?;
+    let body = expr.parse_next(input)?;
+    Ok(Expr::Lambda {
+        param,
+        body: Box::new(body),
+    })
+}
+
 fn var_or_bool_or_func(input: &mut &str) -> ModalResult<Expr> {
     let name = ident.parse_next(input)?;
 
@@ -307,7 +346,7 @@ fn var_or_bool_or_func(input: &mut &str) -> ModalResult<Expr> {
     if input.starts_with('(') {
         '('.parse_next(input)?;
         let _ = multispace0.parse_next(input)?;
-        let args: Vec<Expr> = separated(0.., ws(expr), ws(',')).parse_next(input)?;
+        let args: Vec<Expr> = separated(0.., ws(alt((lambda, expr))), ws(',')).parse_next(input)?;
         let _ = multispace0.parse_next(input)?;
         ')'.parse_next(input)?;
         return Ok(Expr::FuncCall { name, args });
@@ -316,9 +355,9 @@ fn var_or_bool_or_func(input: &mut &str) -> ModalResult<Expr> {
     match name.as_str() {
         "true" => Ok(Expr::Bool(true)),
         "false" => Ok(Expr::Bool(false)),
-        // null is both a value and a type literal - as a standalone value we treat it as Null,
-        // but when used in type comparison (type(x) == null) it matches as a TypeLiteral
-        "null" => Ok(Expr::TypeLiteral(name)),
+        // null is both a value and a type: it evaluates to Null, which
+        // `values_equal` treats as equal to the null type, as in `type(x) == null`
+        "null" => Ok(Expr::Null),
         // Type keywords
         "number" | "string" | "bool" | "array" | "object" => Ok(Expr::TypeLiteral(name)),
         _ => Ok(Expr::Var(name)),
@@ -369,6 +408,14 @@ fn object(input: &mut &str) -> ModalResult<Expr> {
     Ok(Expr::Object(entries))
 }
 
+/// Most elements `range()` will generate, so a huge captured bound fails the
+/// constraint instead of exhausting memory
+const MAX_RANGE_LEN: usize = 1_000_000;
+
+/// Most decimal places `format_number()` will render, so a huge captured
+/// precision fails the constraint instead of building an enormous string
+const MAX_FORMAT_DIGITS: f64 = 100.0;
+
 const TYPE_KEYWORDS: &[&str] = &["number", "string", "bool", "null", "array", "object"];
 
 fn type_literal(input: &mut &str) -> ModalResult<Expr> {
@@ -383,25 +430,61 @@ fn type_literal(input: &mut &str) -> ModalResult<Expr> {
                 continue;
             }
             *input = after;
+            if kw == "null" {
+                return Ok(Expr::Null);
+            }
             return Ok(Expr::TypeLiteral(kw.to_string()));
         }
     }
     Err(winnow::error::ErrMode::Backtrack(ContextError::new()))
 }
 
+thread_local! {
+    /// Smallest remaining input length seen by `atom`, i.e. how far parsing got.
+    /// Used to point parse errors at the offending position.
+    static FURTHEST_REMAINING: std::cell::Cell<usize> = const { std::cell::Cell::new(usize::MAX) };
+}
+
+fn track_furthest(input: &str) {
+    FURTHEST_REMAINING.with(|f| f.set(f.get().min(input.len())));
+}
+
 fn atom(input: &mut &str) -> ModalResult<Expr> {
     let _ = multispace0.parse_next(input)?;
-    alt((
+    track_furthest(input);
+    let result = alt((
         delimited(('(', multispace0), expr, (multispace0, ')')),
         array,
         object,
         string_literal,
         regex_literal,
         number,
+        count_expr,
         var_or_bool_or_func,
         type_literal,
     ))
-    .parse_next(input)
+    .parse_next(input);
+    track_furthest(input);
+    result
+}
+
+/// `count x in iterable where predicate`. The predicate is a single
+/// comparison, never a chain, so in `count x in xs where x > 0 == 2` the
+/// `== 2` compares the count. Predicates using `and`/`or` need parentheses, and then so does the
+/// whole count. `count` on its own is still an ordinary variable name.
+fn count_expr(input: &mut &str) -> ModalResult<Expr> {
+    terminated("count", peek_non_ident).parse_next(input)?;
+    let _ = multispace1.parse_next(input)?;
+    let var = ident.parse_next(input)?;
+    keyword("in").parse_next(input)?;
+    let iterable = arith.parse_next(input)?;
+    keyword("where").parse_next(input)?;
+    let predicate = negatable(input, single_comparison)?;
+    Ok(Expr::Count {
+        predicate: Box::new(predicate),
+        var,
+        iterable: Box::new(iterable),
+    })
 }
 
 fn postfix(input: &mut &str) -> ModalResult<Expr> {
@@ -560,6 +643,14 @@ fn cmp_op(input: &mut &str) -> ModalResult<BinaryOp> {
 }
 
 fn comparison(input: &mut &str) -> ModalResult<Expr> {
+    comparison_chain(input, true)
+}
+
+fn single_comparison(input: &mut &str) -> ModalResult<Expr> {
+    comparison_chain(input, false)
+}
+
+fn comparison_chain(input: &mut &str, chain: bool) -> ModalResult<Expr> {
     let left = arith.parse_next(input)?;
     let _ = multispace0.parse_next(input)?;
 
@@ -568,22 +659,75 @@ fn comparison(input: &mut &str) -> ModalResult<Expr> {
         Some(op) => {
             let _ = multispace0.parse_next(input)?;
             let right = arith.parse_next(input)?;
-            Ok(Expr::BinaryOp {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
+
+            // Further comparisons chain, as in `0 < n < 100`
+            let mut rest = Vec::new();
+            if chain {
+                loop {
+                    let before = *input;
+                    let _ = multispace0.parse_next(input)?;
+                    let Some(next_op) = opt(chain_op).parse_next(input)? else {
+                        *input = before;
+                        break;
+                    };
+                    let _ = multispace0.parse_next(input)?;
+                    rest.push((next_op, arith.parse_next(input)?));
+                }
+            }
+            if rest.is_empty() {
+                return Ok(Expr::BinaryOp {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                });
+            }
+            if !is_chainable(op) {
+                return Err(winnow::error::ErrMode::Backtrack(ContextError::new()));
+            }
+            rest.insert(0, (op, right));
+            Ok(Expr::Chain {
+                first: Box::new(left),
+                rest,
             })
         }
         None => Ok(left),
     }
 }
 
+/// The comparisons that can be chained: equality and ordering
+fn chain_op(input: &mut &str) -> ModalResult<BinaryOp> {
+    alt((
+        "==".value(BinaryOp::Eq),
+        "!=".value(BinaryOp::Ne),
+        "<=".value(BinaryOp::Le),
+        ">=".value(BinaryOp::Ge),
+        "<".value(BinaryOp::Lt),
+        ">".value(BinaryOp::Gt),
+    ))
+    .parse_next(input)
+}
+
+fn is_chainable(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+    )
+}
+
 fn not_expr(input: &mut &str) -> ModalResult<Expr> {
+    negatable(input, comparison)
+}
+
+/// Any number of `not`s followed by a comparison parsed with `comparison`
+fn negatable(
+    input: &mut &str,
+    comparison: fn(&mut &str) -> ModalResult<Expr>,
+) -> ModalResult<Expr> {
     let _ = multispace0.parse_next(input)?;
     let not_kw: Option<&str> = opt(terminated("not", peek_non_ident)).parse_next(input)?;
     if not_kw.is_some() {
         let _ = multispace0.parse_next(input)?;
-        let e = not_expr.parse_next(input)?;
+        let e = negatable(input, comparison)?;
         Ok(Expr::UnaryOp {
             op: UnaryOp::Not,
             expr: Box::new(e),
@@ -629,8 +773,47 @@ fn or_expr(input: &mut &str) -> ModalResult<Expr> {
     .parse_next(input)
 }
 
+fn coalesce_expr(input: &mut &str) -> ModalResult<Expr> {
+    let init = or_expr.parse_next(input)?;
+
+    repeat(0.., preceded((multispace0, "??", multispace0), or_expr))
+        .fold(
+            move || init.clone(),
+            |acc, val| Expr::BinaryOp {
+                op: BinaryOp::Coalesce,
+                left: Box::new(acc),
+                right: Box::new(val),
+            },
+        )
+        .parse_next(input)
+}
+
+fn keyword<'a>(kw: &'static str) -> impl Parser<&'a str, (), ContextError> {
+    (multispace0, kw, peek_non_ident, multispace0).void()
+}
+
+fn conditional_expr(input: &mut &str) -> ModalResult<Expr> {
+    if opt(terminated("if", peek_non_ident))
+        .parse_next(input)?
+        .is_none()
+    {
+        return coalesce_expr(input);
+    }
+    let _ = multispace0.parse_next(input)?;
+    let cond = conditional_expr.parse_next(input)?;
+    keyword("then").parse_next(input)?;
+    let then = conditional_expr.parse_next(input)?;
+    keyword("else").parse_next(input)?;
+    let els = conditional_expr.parse_next(input)?;
+    Ok(Expr::Conditional {
+        cond: Box::new(cond),
+        then: Box::new(then),
+        els: Box::new(els),
+    })
+}
+
 fn forall_expr(input: &mut &str) -> ModalResult<Expr> {
-    let predicate = or_expr.parse_next(input)?;
+    let predicate = conditional_expr.parse_next(input)?;
     let _ = multispace0.parse_next(input)?;
 
     let forall_kw: Option<&str> = opt(terminated("forall", peek_non_ident)).parse_next(input)?;
@@ -640,7 +823,7 @@ fn forall_expr(input: &mut &str) -> ModalResult<Expr> {
         let _ = multispace0.parse_next(input)?;
         terminated("in", peek_non_ident).parse_next(input)?;
         let _ = multispace0.parse_next(input)?;
-        let iterable = or_expr.parse_next(input)?;
+        let iterable = coalesce_expr.parse_next(input)?;
         Ok(Expr::ForAll {
             predicate: Box::new(predicate),
             var,
@@ -658,16 +841,19 @@ fn expr(input: &mut &str) -> ModalResult<Expr> {
 pub fn parse(input: &str) -> Result<Expr, EvalError> {
     let original_input = input.trim();
     let mut input = original_input;
-    match expr.parse_next(&mut input) {
+    FURTHEST_REMAINING.with(|f| f.set(usize::MAX));
+    let result = expr.parse_next(&mut input);
+    let furthest = FURTHEST_REMAINING
+        .with(|f| f.get())
+        .min(input.len())
+        .min(original_input.len());
+    let error_offset = original_input.len() - furthest;
+    match result {
         Ok(e) => {
-            let remaining = input.trim();
-            if remaining.is_empty() {
+            if input.trim().is_empty() {
                 Ok(e)
             } else {
-                Err(EvalError::ParseError(format!(
-                    "unexpected trailing input: '{}'",
-                    remaining
-                )))
+                Err(positioned_parse_error(original_input, error_offset))
             }
         }
         Err(_) => {
@@ -706,22 +892,136 @@ pub fn parse(input: &str) -> Result<Expr, EvalError> {
                         first_word
                     )))
                 } else {
-                    Err(EvalError::ParseError(format!(
-                        "invalid expression syntax in '{}'",
-                        if original_input.len() > 50 {
-                            format!("{}...", &original_input[..50])
-                        } else {
-                            original_input.to_string()
-                        }
-                    )))
+                    Err(positioned_parse_error(original_input, error_offset))
+                }
+            }
+        }
+    }
+}
+
+/// Build a parse error pointing at the (1-based) column where parsing stopped,
+/// with a short snippet of the input at that point.
+fn positioned_parse_error(input: &str, offset: usize) -> EvalError {
+    let column = input[..offset].chars().count() + 1;
+    let rest = &input[offset..];
+    let message = if rest.is_empty() {
+        format!("unexpected end of expression at column {}", column)
+    } else {
+        let snippet: String = rest.chars().take(20).collect();
+        let ellipsis = if rest.chars().count() > 20 { "..." } else { "" };
+        format!(
+            "unexpected '{}{}' at column {}",
+            snippet.trim_end(),
+            ellipsis,
+            column
+        )
+    };
+    let shown = if input.chars().count() > 50 {
+        format!("{}...", input.chars().take(50).collect::<String>())
+    } else {
+        input.to_string()
+    };
+    EvalError::ParseError(format!("{} in '{}'", message, shown))
+}
+
+/// Names of variables referenced by an expression that aren't bound inside it
+/// (by `forall`), in order of first appearance.
+pub fn free_variables(expr: &Expr) -> Vec<String> {
+    fn walk(expr: &Expr, bound: &mut Vec<String>, out: &mut Vec<String>) {
+        match expr {
+            Expr::Var(name) => {
+                if !bound.contains(name) && !out.contains(name) {
+                    out.push(name.clone());
                 }
             }
+            Expr::Number(_)
+            | Expr::String(_)
+            | Expr::Bool(_)
+            | Expr::Null
+            | Expr::TypeLiteral(_) => {}
+            Expr::Array(items) => items.iter().for_each(|e| walk(e, bound, out)),
+            Expr::Object(entries) => entries.iter().for_each(|(_, e)| walk(e, bound, out)),
+            Expr::UnaryOp { expr, .. } | Expr::Property { expr, .. } => walk(expr, bound, out),
+            Expr::BinaryOp { left, right, .. } => {
+                walk(left, bound, out);
+                walk(right, bound, out);
+            }
+            Expr::FuncCall { args, .. } => args.iter().for_each(|e| walk(e, bound, out)),
+            Expr::Index { expr, index } => {
+                walk(expr, bound, out);
+                walk(index, bound, out);
+            }
+            Expr::ForAll {
+                predicate,
+                var,
+                iterable,
+            }
+            | Expr::Count {
+                predicate,
+                var,
+                iterable,
+            } => {
+                walk(iterable, bound, out);
+                bound.push(var.clone());
+                walk(predicate, bound, out);
+                bound.pop();
+            }
+            Expr::Lambda { param, body } => {
+                bound.push(param.clone());
+                walk(body, bound, out);
+                bound.pop();
+            }
+            Expr::Chain { first, rest } => {
+                walk(first, bound, out);
+                rest.iter().for_each(|(_, e)| walk(e, bound, out));
+            }
+            Expr::Conditional { cond, then, els } => {
+                walk(cond, bound, out);
+                walk(then, bound, out);
+                walk(els, bound, out);
+            }
         }
     }
+
+    let mut out = Vec::new();
+    walk(expr, &mut Vec::new(), &mut out);
+    out
 }
 
 // ============ Evaluator ============
 
+/// Resolve `slice` bounds against a length. Negative indices count from the
+/// end like `Expr::Index`; out-of-range bounds clamp instead of erroring.
+fn slice_bounds(len: usize, start: f64, end: f64) -> (usize, usize) {
+    let resolve = |i: f64| -> usize {
+        if i < 0.0 {
+            len.saturating_sub((-i) as usize)
+        } else {
+            (i as usize).min(len)
+        }
+    };
+    let from = resolve(start);
+    let to = resolve(end).max(from);
+    (from, to)
+}
+
+/// The elements `forall` and `count` iterate over: array elements, or the
+/// values of an object
+fn iteration_items(
+    iterable: &Expr,
+    vars: &HashMap<String, Value>,
+) -> Result<Vec<Value>, EvalError> {
+    let iter_val = evaluate(iterable, vars)?;
+    match iter_val {
+        Value::Array(arr) => Ok(arr),
+        Value::Object(obj) => Ok(obj.into_values().collect()),
+        _ => Err(EvalError::TypeError {
+            expected: "array or object",
+            got: iter_val.type_name(),
+        }),
+    }
+}
+
 pub fn evaluate(expr: &Expr, vars: &HashMap<String, Value>) -> Result<Value, EvalError> {
     match expr {
         Expr::Number(n) => Ok(Value::Number(*n)),
@@ -752,6 +1052,17 @@ pub fn evaluate(expr: &Expr, vars: &HashMap<String, Value>) -> Result<Value, Eva
             }
         }
         Expr::BinaryOp { op, left, right } => eval_binary_op(*op, left, right, vars),
+        Expr::Chain { first, rest } => {
+            let mut left = evaluate(first, vars)?;
+            for (op, expr) in rest {
+                let right = evaluate(expr, vars)?;
+                if !apply_binary_op(*op, &left, &right)?.as_bool()? {
+                    return Ok(Value::Bool(false));
+                }
+                left = right;
+            }
+            Ok(Value::Bool(true))
+        }
         Expr::FuncCall { name, args } => eval_func_call(name, args, vars),
         Expr::Index { expr, index } => {
             let base = evaluate(expr, vars)?;
@@ -826,18 +1137,7 @@ pub fn evaluate(expr: &Expr, vars: &HashMap<String, Value>) -> Result<Value, Eva
             var,
             iterable,
         } => {
-            let iter_val = evaluate(iterable, vars)?;
-            let items = match &iter_val {
-                Value::Array(arr) => arr.clone(),
-                Value::Object(obj) => obj.values().cloned().collect(),
-                _ => {
-                    return Err(EvalError::TypeError {
-                        expected: "array or object",
-                        got: iter_val.type_name(),
-                    });
-                }
-            };
-            for item in items {
+            for item in iteration_items(iterable, vars)? {
                 let mut local_vars = vars.clone();
                 local_vars.insert(var.clone(), item);
                 let result = evaluate(predicate, &local_vars)?;
@@ -847,6 +1147,32 @@ pub fn evaluate(expr: &Expr, vars: &HashMap<String, Value>) -> Result<Value, Eva
             }
             Ok(Value::Bool(true))
         }
+        Expr::Lambda { .. } => Err(EvalError::TypeError {
+            expected: "value",
+            got: "lambda",
+        }),
+        Expr::Count {
+            predicate,
+            var,
+            iterable,
+        } => {
+            let mut count = 0;
+            for item in iteration_items(iterable, vars)? {
+                let mut local_vars = vars.clone();
+                local_vars.insert(var.clone(), item);
+                if evaluate(predicate, &local_vars)?.as_bool()? {
+                    count += 1;
+                }
+            }
+            Ok(Value::Number(count as f64))
+        }
+        Expr::Conditional { cond, then, els } => {
+            if evaluate(cond, vars)?.as_bool()? {
+                evaluate(then, vars)
+            } else {
+                evaluate(els, vars)
+            }
+        }
     }
 }
 
@@ -917,6 +1243,20 @@ fn eval_func_call(
             let values: Vec<Value> = pairs.into_iter().map(|(_, v)| v.clone()).collect();
             Ok(Value::Array(values))
         }
+        "merge" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let a = evaluate(&args[0], vars)?;
+            let b = evaluate(&args[1], vars)?;
+            let mut merged = a.as_object()?.clone();
+            merged.extend(b.as_object()?.clone());
+            Ok(Value::Object(merged))
+        }
         "sum" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
@@ -933,7 +1273,7 @@ fn eval_func_call(
             }
             Ok(Value::Number(total))
         }
-        "min" => {
+        "avg" | "mean" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -949,16 +1289,44 @@ fn eval_func_call(
                     got: "empty array",
                 });
             }
-            let mut min_val = arr[0].as_number()?;
-            for item in arr.iter().skip(1) {
+            let mut total = 0.0;
+            for item in arr {
+                total += item.as_number()?;
+            }
+            Ok(Value::Number(total / arr.len() as f64))
+        }
+        "min" | "max" => {
+            // Either a single array, or two or more numbers: max(a, b)
+            let values = match args {
+                [] => {
+                    return Err(EvalError::WrongArgCount {
+                        func: name.to_string(),
+                        expected: 1,
+                        got: 0,
+                    });
+                }
+                [arg] => evaluate(arg, vars)?.as_array()?.to_vec(),
+                _ => args
+                    .iter()
+                    .map(|arg| evaluate(arg, vars))
+                    .collect::<Result<_, _>>()?,
+            };
+            if values.is_empty() {
+                return Err(EvalError::TypeError {
+                    expected: "non-empty array",
+                    got: "empty array",
+                });
+            }
+            let mut result = values[0].as_number()?;
+            for item in values.iter().skip(1) {
                 let n = item.as_number()?;
-                if n < min_val {
-                    min_val = n;
+                if (name == "min" && n < result) || (name == "max" && n > result) {
+                    result = n;
                 }
             }
-            Ok(Value::Number(min_val))
+            Ok(Value::Number(result))
         }
-        "max" => {
+        "abs" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -967,23 +1335,39 @@ fn eval_func_call(
                 });
             }
             let val = evaluate(&args[0], vars)?;
-            let arr = val.as_array()?;
-            if arr.is_empty() {
-                return Err(EvalError::TypeError {
-                    expected: "non-empty array",
-                    got: "empty array",
+            Ok(Value::Number(val.as_number()?.abs()))
+        }
+        "int" | "float" => {
+            if args.len() != 1 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 1,
+                    got: args.len(),
                 });
             }
-            let mut max_val = arr[0].as_number()?;
-            for item in arr.iter().skip(1) {
-                let n = item.as_number()?;
-                if n > max_val {
-                    max_val = n;
+            let val = evaluate(&args[0], vars)?;
+            let n = match &val {
+                Value::Number(n) => *n,
+                Value::String(s) => s
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .ok_or(EvalError::TypeError {
+                        expected: "numeric string",
+                        got: "string",
+                    })?,
+                _ => {
+                    return Err(EvalError::TypeError {
+                        expected: "number or numeric string",
+                        got: val.type_name(),
+                    })
                 }
-            }
-            Ok(Value::Number(max_val))
+            };
+            // int truncates toward zero, so int("-3.7") is -3
+            Ok(Value::Number(if name == "int" { n.trunc() } else { n }))
         }
-        "abs" => {
+        "sqrt" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -992,7 +1376,43 @@ fn eval_func_call(
                 });
             }
             let val = evaluate(&args[0], vars)?;
-            Ok(Value::Number(val.as_number()?.abs()))
+            Ok(Value::Number(val.as_number()?.sqrt()))
+        }
+        "pow" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let base = evaluate(&args[0], vars)?;
+            let exp = evaluate(&args[1], vars)?;
+            Ok(Value::Number(base.as_number()?.powf(exp.as_number()?)))
+        }
+        "format_number" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let n = evaluate(&args[0], vars)?.as_number()?;
+            let digits = evaluate(&args[1], vars)?.as_number()?;
+            if !(0.0..=MAX_FORMAT_DIGITS).contains(&digits) || digits.fract() != 0.0 {
+                return Err(EvalError::TypeError {
+                    expected: "integer from 0 to 100",
+                    got: "number",
+                });
+            }
+            let formatted = format!("{:.*}", digits as usize, n);
+            // Don't render values that round to zero as "-0.00"
+            let formatted = match formatted.strip_prefix('-') {
+                Some(abs) if abs.chars().all(|c| c == '0' || c == '.') => abs.to_string(),
+                _ => formatted,
+            };
+            Ok(Value::String(formatted))
         }
         "lower" => {
             if args.len() != 1 {
@@ -1016,7 +1436,45 @@ fn eval_func_call(
             let val = evaluate(&args[0], vars)?;
             Ok(Value::String(val.as_string()?.to_uppercase()))
         }
+        "ieq" | "icontains" | "istartswith" | "iendswith" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let a = evaluate(&args[0], vars)?;
+            let b = evaluate(&args[1], vars)?;
+            let (a, b) = (a.as_string()?.to_lowercase(), b.as_string()?.to_lowercase());
+            Ok(Value::Bool(match name {
+                "ieq" => a == b,
+                "icontains" => a.contains(&b),
+                "istartswith" => a.starts_with(&b),
+                _ => a.ends_with(&b),
+            }))
+        }
         "strip" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: if args.is_empty() { 1 } else { 2 },
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let s = val.as_string()?;
+            if let Some(chars_arg) = args.get(1) {
+                let chars = evaluate(chars_arg, vars)?;
+                let chars = chars.as_string()?;
+                Ok(Value::String(
+                    s.trim_matches(|c| chars.contains(c)).to_string(),
+                ))
+            } else {
+                Ok(Value::String(s.trim().to_string()))
+            }
+        }
+        "trim" | "trimstart" | "trimend" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -1025,9 +1483,83 @@ fn eval_func_call(
                 });
             }
             let val = evaluate(&args[0], vars)?;
-            Ok(Value::String(val.as_string()?.trim().to_string()))
+            let s = val.as_string()?;
+            let trimmed = match name {
+                "trimstart" => s.trim_start(),
+                "trimend" => s.trim_end(),
+                _ => s.trim(),
+            };
+            Ok(Value::String(trimmed.to_string()))
+        }
+        "strip_prefix" | "strip_suffix" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let affix = evaluate(&args[1], vars)?;
+            let (s, affix) = (val.as_string()?, affix.as_string()?);
+            let stripped = if name == "strip_prefix" {
+                s.strip_prefix(affix)
+            } else {
+                s.strip_suffix(affix)
+            };
+            Ok(Value::String(stripped.unwrap_or(s).to_string()))
         }
-        "unique" => {
+        "replace" => {
+            if args.len() != 3 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 3,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let from = evaluate(&args[1], vars)?;
+            let to = evaluate(&args[2], vars)?;
+            Ok(Value::String(
+                val.as_string()?.replace(from.as_string()?, to.as_string()?),
+            ))
+        }
+        "split" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let sep = evaluate(&args[1], vars)?;
+            let (s, sep) = (val.as_string()?, sep.as_string()?);
+            let parts = if sep.is_empty() {
+                s.chars().map(|c| Value::String(c.to_string())).collect()
+            } else {
+                s.split(sep).map(|p| Value::String(p.to_string())).collect()
+            };
+            Ok(Value::Array(parts))
+        }
+        "join" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let sep = evaluate(&args[1], vars)?;
+            let parts = val
+                .as_array()?
+                .iter()
+                .map(|v| v.as_string())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::String(parts.join(sep.as_string()?)))
+        }
+        "sort" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -1036,16 +1568,34 @@ fn eval_func_call(
                 });
             }
             let val = evaluate(&args[0], vars)?;
-            let arr = val.as_array()?;
-            let mut result = Vec::new();
-            for item in arr {
-                if !result.iter().any(|v| values_equal(v, item)) {
-                    result.push(item.clone());
-                }
+            let mut arr = val.as_array()?.to_vec();
+            if arr.iter().all(|v| matches!(v, Value::Number(_))) {
+                arr.sort_by(|a, b| {
+                    let (Value::Number(a), Value::Number(b)) = (a, b) else {
+                        unreachable!()
+                    };
+                    a.total_cmp(b)
+                });
+            } else if arr.iter().all(|v| matches!(v, Value::String(_))) {
+                arr.sort_by(|a, b| {
+                    let (Value::String(a), Value::String(b)) = (a, b) else {
+                        unreachable!()
+                    };
+                    a.cmp(b)
+                });
+            } else {
+                let got = arr
+                    .iter()
+                    .find(|v| !matches!(v, Value::Number(_) | Value::String(_)))
+                    .map_or("mixed array", |v| v.type_name());
+                return Err(EvalError::TypeError {
+                    expected: "array of numbers or array of strings",
+                    got,
+                });
             }
-            Ok(Value::Array(result))
+            Ok(Value::Array(arr))
         }
-        "env" => {
+        "all" | "any" => {
             if args.len() != 1 {
                 return Err(EvalError::WrongArgCount {
                     func: name.to_string(),
@@ -1054,14 +1604,406 @@ fn eval_func_call(
                 });
             }
             let val = evaluate(&args[0], vars)?;
-            let var_name = val.as_string()?;
-            match std::env::var(var_name) {
-                Ok(value) => Ok(Value::String(value)),
-                Err(_) => Ok(Value::Null),
+            // Check every element, so a non-bool is reported even after the
+            // result is known
+            let bools = val
+                .as_array()?
+                .iter()
+                .map(Value::as_bool)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Bool(if name == "all" {
+                bools.iter().all(|b| *b)
+            } else {
+                bools.iter().any(|b| *b)
+            }))
+        }
+        "reverse" => {
+            if args.len() != 1 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 1,
+                    got: args.len(),
+                });
             }
+            let val = evaluate(&args[0], vars)?;
+            Ok(Value::Array(
+                val.as_array()?.iter().rev().cloned().collect(),
+            ))
         }
-        _ => Err(EvalError::UndefinedFunction(name.to_string())),
-    }
+        "slice" => {
+            if args.len() != 3 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 3,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let start = evaluate(&args[1], vars)?.as_number()?;
+            let end = evaluate(&args[2], vars)?.as_number()?;
+            match &val {
+                Value::Array(arr) => {
+                    let (from, to) = slice_bounds(arr.len(), start, end);
+                    Ok(Value::Array(arr[from..to].to_vec()))
+                }
+                Value::String(s) => {
+                    let chars: Vec<char> = s.chars().collect();
+                    let (from, to) = slice_bounds(chars.len(), start, end);
+                    Ok(Value::String(chars[from..to].iter().collect()))
+                }
+                _ => Err(EvalError::TypeError {
+                    expected: "array or string",
+                    got: val.type_name(),
+                }),
+            }
+        }
+        "map" | "filter" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let arr = val.as_array()?;
+            let Expr::Lambda { param, body } = &args[1] else {
+                return Err(EvalError::TypeError {
+                    expected: "lambda such as x => x * 2",
+                    got: evaluate(&args[1], vars)?.type_name(),
+                });
+            };
+            let mut result = Vec::new();
+            for item in arr {
+                let mut local_vars = vars.clone();
+                local_vars.insert(param.clone(), item.clone());
+                let out = evaluate(body, &local_vars)?;
+                if name == "map" {
+                    result.push(out);
+                } else if out.as_bool()? {
+                    result.push(item.clone());
+                }
+            }
+            Ok(Value::Array(result))
+        }
+        "unique" => {
+            if args.len() != 1 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 1,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let arr = val.as_array()?;
+            let mut result = Vec::new();
+            for item in arr {
+                if !result.iter().any(|v| values_equal(v, item)) {
+                    result.push(item.clone());
+                }
+            }
+            Ok(Value::Array(result))
+        }
+        "range" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: if args.is_empty() { 1 } else { 2 },
+                    got: args.len(),
+                });
+            }
+            let mut bounds = Vec::new();
+            for arg in args {
+                bounds.push(evaluate(arg, vars)?.as_number()?.trunc() as i64);
+            }
+            let (start, end) = match bounds[..] {
+                [end] => (0, end),
+                [start, end] => (start, end),
+                _ => unreachable!(),
+            };
+            let len = end as i128 - start as i128;
+            if len > MAX_RANGE_LEN as i128 {
+                return Err(EvalError::RangeTooLarge {
+                    len,
+                    max: MAX_RANGE_LEN,
+                });
+            }
+            Ok(Value::Array(
+                (start..end.max(start))
+                    .map(|n| Value::Number(n as f64))
+                    .collect(),
+            ))
+        }
+        "flatten" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: if args.is_empty() { 1 } else { 2 },
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let depth = match args.get(1) {
+                Some(depth) => {
+                    let depth = evaluate(depth, vars)?.as_number()?;
+                    if depth < 0.0 || depth.fract() != 0.0 {
+                        return Err(EvalError::TypeError {
+                            expected: "non-negative integer",
+                            got: "number",
+                        });
+                    }
+                    depth as usize
+                }
+                None => 1,
+            };
+            Ok(Value::Array(flatten(val.as_array()?, depth)))
+        }
+        "union" | "intersection" | "difference" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let a_val = evaluate(&args[0], vars)?;
+            let b_val = evaluate(&args[1], vars)?;
+            let a = a_val.as_array()?;
+            let b = b_val.as_array()?;
+            let in_b = |item: &Value| b.iter().any(|v| values_equal(v, item));
+            let candidates: Vec<&Value> = match name {
+                "union" => a.iter().chain(b).collect(),
+                "intersection" => a.iter().filter(|item| in_b(item)).collect(),
+                _ => a.iter().filter(|item| !in_b(item)).collect(),
+            };
+            let mut result: Vec<Value> = Vec::new();
+            for item in candidates {
+                if !result.iter().any(|v| values_equal(v, item)) {
+                    result.push(item.clone());
+                }
+            }
+            Ok(Value::Array(result))
+        }
+        "env" => {
+            if args.len() != 1 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 1,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let var_name = val.as_string()?;
+            match std::env::var(var_name) {
+                Ok(value) => Ok(Value::String(value)),
+                Err(_) => Ok(Value::Null),
+            }
+        }
+        "match" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let s = evaluate(&args[0], vars)?;
+            let pattern = evaluate(&args[1], vars)?;
+            let re = regex::Regex::new(pattern.as_string()?)
+                .map_err(|e| EvalError::InvalidRegex(e.to_string()))?;
+            let Some(caps) = re.captures(s.as_string()?) else {
+                return Ok(Value::Null);
+            };
+            // The full match, then each group; a group that didn't
+            // participate in the match is null
+            Ok(Value::Array(
+                caps.iter()
+                    .map(|m| m.map_or(Value::Null, |m| Value::String(m.as_str().to_string())))
+                    .collect(),
+            ))
+        }
+        "captures" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let s = evaluate(&args[0], vars)?;
+            let pattern = evaluate(&args[1], vars)?;
+            let re = regex::Regex::new(pattern.as_string()?)
+                .map_err(|e| EvalError::InvalidRegex(e.to_string()))?;
+            let Some(caps) = re.captures(s.as_string()?) else {
+                return Ok(Value::Null);
+            };
+            // Named groups by name, unnamed groups by index; a group that
+            // didn't participate in the match is null
+            let groups = re
+                .capture_names()
+                .enumerate()
+                .skip(1)
+                .map(|(i, group_name)| {
+                    let value = caps
+                        .get(i)
+                        .map_or(Value::Null, |m| Value::String(m.as_str().to_string()));
+                    (
+                        group_name.map_or_else(|| i.to_string(), String::from),
+                        value,
+                    )
+                })
+                .collect();
+            Ok(Value::Object(groups))
+        }
+        "json_pointer" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let val = evaluate(&args[0], vars)?;
+            let pointer = evaluate(&args[1], vars)?;
+            json_pointer(&val, pointer.as_string()?)
+        }
+        "has_field" => {
+            if args.len() != 3 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 3,
+                    got: args.len(),
+                });
+            }
+            let text = evaluate(&args[0], vars)?;
+            let field = evaluate(&args[1], vars)?;
+            let expected = evaluate(&args[2], vars)?;
+            Ok(Value::Bool(has_field(
+                text.as_string()?,
+                field.as_string()?,
+                &expected,
+            )))
+        }
+        "version_cmp" | "version_lt" => {
+            if args.len() != 2 {
+                return Err(EvalError::WrongArgCount {
+                    func: name.to_string(),
+                    expected: 2,
+                    got: args.len(),
+                });
+            }
+            let a = evaluate(&args[0], vars)?;
+            let b = evaluate(&args[1], vars)?;
+            let ordering = compare_versions(a.as_string()?, b.as_string()?);
+            if name == "version_lt" {
+                Ok(Value::Bool(ordering == std::cmp::Ordering::Less))
+            } else {
+                Ok(Value::Number(ordering as i32 as f64))
+            }
+        }
+        _ => Err(EvalError::UndefinedFunction(name.to_string())),
+    }
+}
+
+/// Splice nested arrays into their parent, up to `depth` levels deep
+fn flatten(items: &[Value], depth: usize) -> Vec<Value> {
+    let mut result = Vec::new();
+    for item in items {
+        match item {
+            Value::Array(inner) if depth > 0 => result.extend(flatten(inner, depth - 1)),
+            other => result.push(other.clone()),
+        }
+    }
+    result
+}
+
+/// Whether any line of `text` is a JSON object whose `field` equals `expected`.
+/// Lines that aren't JSON objects are ignored.
+fn has_field(text: &str, field: &str, expected: &Value) -> bool {
+    text.lines().any(
+        |line| match serde_json::from_str::<serde_json::Value>(line.trim()) {
+            Ok(serde_json::Value::Object(obj)) => obj
+                .get(field)
+                .is_some_and(|v| values_equal(&json_to_value(v), expected)),
+            _ => false,
+        },
+    )
+}
+
+/// Convert parsed JSON into an expression value. Numbers become `f64`.
+pub fn json_to_value(json: &serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(0.0)),
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Array(arr) => Value::Array(arr.iter().map(json_to_value).collect()),
+        serde_json::Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(k, v)| (k.clone(), json_to_value(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Resolve an RFC 6901 JSON Pointer such as `/a/b/0` against a value.
+fn json_pointer(value: &Value, pointer: &str) -> Result<Value, EvalError> {
+    if pointer.is_empty() {
+        return Ok(value.clone());
+    }
+    let not_found = || EvalError::PointerNotFound(pointer.to_string());
+    let Some(rest) = pointer.strip_prefix('/') else {
+        return Err(not_found());
+    };
+
+    let mut current = value;
+    for token in rest.split('/') {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        current = match current {
+            Value::Object(obj) => obj.get(&token).ok_or_else(not_found)?,
+            Value::Array(arr) => {
+                // Array indices are plain decimal numbers without leading zeros
+                let valid_index = !token.is_empty()
+                    && token.chars().all(|c| c.is_ascii_digit())
+                    && (token == "0" || !token.starts_with('0'));
+                if !valid_index {
+                    return Err(not_found());
+                }
+                let index: usize = token.parse().map_err(|_| not_found())?;
+                arr.get(index).ok_or_else(not_found)?
+            }
+            _ => return Err(not_found()),
+        };
+    }
+    Ok(current.clone())
+}
+
+/// Compare dotted version strings component by component.
+/// Numeric prefixes are compared numerically, missing components count as 0,
+/// and a component with a suffix (`3-beta`) sorts before the bare number (`3`).
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    fn split_component(c: &str) -> (u64, &str) {
+        let digits_end = c.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(c.len());
+        (c[..digits_end].parse().unwrap_or(0), &c[digits_end..])
+    }
+
+    let a_parts: Vec<&str> = a.trim().trim_start_matches('v').split('.').collect();
+    let b_parts: Vec<&str> = b.trim().trim_start_matches('v').split('.').collect();
+
+    for i in 0..a_parts.len().max(b_parts.len()) {
+        let (a_num, a_suffix) = split_component(a_parts.get(i).copied().unwrap_or("0"));
+        let (b_num, b_suffix) = split_component(b_parts.get(i).copied().unwrap_or("0"));
+        let ordering = a_num.cmp(&b_num).then_with(|| match (a_suffix, b_suffix) {
+            ("", "") => std::cmp::Ordering::Equal,
+            ("", _) => std::cmp::Ordering::Greater,
+            (_, "") => std::cmp::Ordering::Less,
+            _ => a_suffix.cmp(b_suffix),
+        });
+        if ordering != std::cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+    std::cmp::Ordering::Equal
 }
 
 fn eval_binary_op(
@@ -1084,12 +2026,26 @@ fn eval_binary_op(
         }
         return Ok(Value::Bool(evaluate(right, vars)?.as_bool()?));
     }
+    if op == BinaryOp::Coalesce {
+        // The only operator that swallows errors: a missing key or variable
+        // on the left falls back to the right, as does null
+        return match evaluate(left, vars) {
+            Ok(Value::Null)
+            | Err(EvalError::KeyNotFound(_))
+            | Err(EvalError::UndefinedVariable(_)) => evaluate(right, vars),
+            result => result,
+        };
+    }
 
     let l = evaluate(left, vars)?;
     let r = evaluate(right, vars)?;
+    apply_binary_op(op, &l, &r)
+}
 
+/// Apply an operator other than `and`, `or` and `??` to evaluated operands
+fn apply_binary_op(op: BinaryOp, l: &Value, r: &Value) -> Result<Value, EvalError> {
     match op {
-        BinaryOp::Add => match (&l, &r) {
+        BinaryOp::Add => match (l, r) {
             (Value::String(ls), Value::String(rs)) => Ok(Value::String(format!("{}{}", ls, rs))),
             (Value::Array(la), Value::Array(ra)) => {
                 let mut result = la.clone();
@@ -1117,31 +2073,31 @@ fn eval_binary_op(
             }
         }
         BinaryOp::Pow => Ok(Value::Number(l.as_number()?.powf(r.as_number()?))),
-        BinaryOp::Eq => Ok(Value::Bool(values_equal(&l, &r))),
-        BinaryOp::Ne => Ok(Value::Bool(!values_equal(&l, &r))),
-        BinaryOp::Lt => match (&l, &r) {
+        BinaryOp::Eq => Ok(Value::Bool(values_equal(l, r))),
+        BinaryOp::Ne => Ok(Value::Bool(!values_equal(l, r))),
+        BinaryOp::Lt => match (l, r) {
             (Value::String(ls), Value::String(rs)) => Ok(Value::Bool(ls < rs)),
             _ => Ok(Value::Bool(l.as_number()? < r.as_number()?)),
         },
-        BinaryOp::Le => match (&l, &r) {
+        BinaryOp::Le => match (l, r) {
             (Value::String(ls), Value::String(rs)) => Ok(Value::Bool(ls <= rs)),
             _ => Ok(Value::Bool(l.as_number()? <= r.as_number()?)),
         },
-        BinaryOp::Gt => match (&l, &r) {
+        BinaryOp::Gt => match (l, r) {
             (Value::String(ls), Value::String(rs)) => Ok(Value::Bool(ls > rs)),
             _ => Ok(Value::Bool(l.as_number()? > r.as_number()?)),
         },
-        BinaryOp::Ge => match (&l, &r) {
+        BinaryOp::Ge => match (l, r) {
             (Value::String(ls), Value::String(rs)) => Ok(Value::Bool(ls >= rs)),
             _ => Ok(Value::Bool(l.as_number()? >= r.as_number()?)),
         },
         BinaryOp::Contains | BinaryOp::NotContains => {
-            let result = match &l {
+            let result = match l {
                 Value::String(haystack) => {
                     let needle = r.as_string()?;
                     haystack.contains(needle)
                 }
-                Value::Array(arr) => arr.iter().any(|v| values_equal(v, &r)),
+                Value::Array(arr) => arr.iter().any(|v| values_equal(v, r)),
                 Value::Object(obj) => {
                     let key = r.as_string()?;
                     obj.contains_key(key)
@@ -1191,7 +2147,7 @@ fn eval_binary_op(
                 result
             }))
         }
-        BinaryOp::And | BinaryOp::Or => unreachable!(),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Coalesce => unreachable!(),
     }
 }
 
@@ -1429,6 +2385,27 @@ mod tests {
         assert!(eval_bool("len(keys(o)) == 2", &v).unwrap());
     }
 
+    #[test]
+    fn test_merge_function() {
+        let mut obj = HashMap::new();
+        obj.insert("a".to_string(), Value::Number(1.0));
+        obj.insert("b".to_string(), Value::Number(2.0));
+        let v = vars(&[("o", Value::Object(obj))]);
+
+        assert!(eval_bool(r#"merge(o, {"b": 3, "c": 4}).b == 3"#, &v).unwrap());
+        assert!(eval_bool(r#"merge(o, {"b": 3, "c": 4}).a == 1"#, &v).unwrap());
+        assert!(eval_bool(r#"keys(merge(o, {"b": 3, "c": 4})) == ["a", "b", "c"]"#, &v).unwrap());
+        assert!(eval_bool(r#"merge({"c": 4}, o) == {"a": 1, "b": 2, "c": 4}"#, &v).unwrap());
+        assert!(matches!(
+            eval_bool("merge(o, [1]) == o", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+        assert!(matches!(
+            eval_bool("merge(o) == o", &v),
+            Err(EvalError::WrongArgCount { .. })
+        ));
+    }
+
     #[test]
     fn test_forall_array() {
         let v = vars(&[(
@@ -1513,4 +2490,736 @@ mod tests {
         assert!(eval_bool(r#"strip(clean) == "no whitespace""#, &v).unwrap());
         assert!(eval_bool(r#"strip("  test  ") == "test""#, &v).unwrap());
     }
+
+    #[test]
+    fn test_parse_error_positions() {
+        let err = parse("n > ").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "parse error: unexpected end of expression at column 4 in 'n >'"
+        );
+
+        let err = parse("1 + + 2").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "parse error: unexpected '+ 2' at column 5 in '1 + + 2'"
+        );
+
+        let err = parse("x y").unwrap_err().to_string();
+        assert_eq!(err, "parse error: unexpected 'y' at column 3 in 'x y'");
+
+        let err = parse("len(arr == 3").unwrap_err().to_string();
+        assert!(err.contains("unexpected end of expression at column 13"));
+    }
+
+    #[test]
+    fn test_json_pointer_function() {
+        let mut inner = HashMap::new();
+        inner.insert(
+            "items".to_string(),
+            Value::Array(vec![
+                Value::String("first".to_string()),
+                Value::String("second".to_string()),
+            ]),
+        );
+        inner.insert("a/b".to_string(), Value::Number(1.0));
+        inner.insert("m~n".to_string(), Value::Number(2.0));
+        let mut obj = HashMap::new();
+        obj.insert("data".to_string(), Value::Object(inner));
+        let v = vars(&[("o", Value::Object(obj))]);
+
+        assert!(eval_bool(r#"json_pointer(o, "/data/items/1") == "second""#, &v).unwrap());
+        assert!(eval_bool(r#"json_pointer(o, "/data/a~1b") == 1"#, &v).unwrap());
+        assert!(eval_bool(r#"json_pointer(o, "/data/m~0n") == 2"#, &v).unwrap());
+        assert!(eval_bool(r#"len(json_pointer(o, "/data/items")) == 2"#, &v).unwrap());
+        assert!(eval_bool(r#"json_pointer(o, "") == o"#, &v).unwrap());
+
+        let err = eval_bool(r#"json_pointer(o, "/data/items/5") == 1"#, &v).unwrap_err();
+        assert_eq!(err.to_string(), "json pointer not found: /data/items/5");
+        assert!(eval_bool(r#"json_pointer(o, "/data/items/01") == 1"#, &v).is_err());
+        assert!(eval_bool(r#"json_pointer(o, "data") == 1"#, &v).is_err());
+    }
+
+    #[test]
+    fn test_has_field_function() {
+        let output = "starting up\n{\"status\": \"ok\", \"count\": 3}\n[1, 2]\n{\"status\": \"done\"}\nnot {json}";
+        let v = vars(&[("output", Value::String(output.to_string()))]);
+
+        assert!(eval_bool(r#"has_field(output, "status", "ok")"#, &v).unwrap());
+        assert!(eval_bool(r#"has_field(output, "status", "done")"#, &v).unwrap());
+        assert!(eval_bool(r#"has_field(output, "count", 3)"#, &v).unwrap());
+        assert!(!eval_bool(r#"has_field(output, "status", "failed")"#, &v).unwrap());
+        assert!(!eval_bool(r#"has_field(output, "count", "3")"#, &v).unwrap());
+        assert!(!eval_bool(r#"has_field(output, "missing", null)"#, &v).unwrap());
+        assert!(eval_bool(r#"has_field(output, "status")"#, &v).is_err());
+    }
+
+    #[test]
+    fn test_strip_functions() {
+        let v = HashMap::new();
+        assert!(eval_bool(r#"strip("***x***", "*") == "x""#, &v).unwrap());
+        assert!(eval_bool(r#"strip("[\"a\"]", "[]\"") == "a""#, &v).unwrap());
+        assert!(eval_bool(r#"strip("  x  ") == "x""#, &v).unwrap());
+        assert!(eval_bool(r#"strip("x", "") == "x""#, &v).unwrap());
+        assert!(eval_bool(r#"strip_prefix("v1.2", "v") == "1.2""#, &v).unwrap());
+        assert!(eval_bool(r#"strip_prefix("1.2", "v") == "1.2""#, &v).unwrap());
+        assert!(eval_bool(r#"strip_prefix("vvx", "v") == "vx""#, &v).unwrap());
+        assert!(eval_bool(r#"strip_suffix("file.txt", ".txt") == "file""#, &v).unwrap());
+        assert!(eval_bool(r#"strip_suffix("file.txt", ".md") == "file.txt""#, &v).unwrap());
+        assert!(eval_bool(r#"strip("a", "b", "c") == "a""#, &v).is_err());
+        assert!(eval_bool(r#"strip_prefix("a") == "a""#, &v).is_err());
+    }
+
+    #[test]
+    fn test_sqrt_and_pow_functions() {
+        let v = vars(&[("area", Value::Number(16.0)), ("side", Value::Number(4.0))]);
+        assert!(eval_bool("sqrt(area) == side", &v).unwrap());
+        assert!(eval_bool("pow(side, 2) == area", &v).unwrap());
+        assert!(eval_bool("pow(2, 0.5) == 2 ^ 0.5", &v).unwrap());
+        assert!(eval_bool("pow(area, -0.5) == 0.25", &v).unwrap());
+
+        // sqrt of a negative number is NaN, which is not equal to anything
+        let nan = evaluate(&parse("sqrt(-1)").unwrap(), &v).unwrap();
+        assert!(matches!(nan, Value::Number(n) if n.is_nan()));
+        assert!(!eval_bool("sqrt(-1) == sqrt(-1)", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("pow(2) == 4", &v),
+            Err(EvalError::WrongArgCount {
+                expected: 2,
+                got: 1,
+                ..
+            })
+        ));
+        assert!(eval_bool(r#"sqrt("4") == 2"#, &v).is_err());
+    }
+
+    #[test]
+    fn test_trim_functions() {
+        let v = vars(&[("msg", Value::String("  done \t\n".to_string()))]);
+        assert!(eval_bool(r#"trim(msg) == "done""#, &v).unwrap());
+        assert!(eval_bool(r#"trimstart(msg) == "done \t\n""#, &v).unwrap());
+        assert!(eval_bool(r#"trimend(msg) == "  done""#, &v).unwrap());
+        assert!(eval_bool(r#"trim("") == """#, &v).unwrap());
+
+        for func in ["trim", "trimstart", "trimend"] {
+            let err = eval_bool(&format!("{}(42) == 42", func), &v).unwrap_err();
+            assert!(matches!(
+                err,
+                EvalError::TypeError {
+                    expected: "string",
+                    got: "number"
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_replace_function() {
+        let v = vars(&[("path", Value::String("a\\b\\c".to_string()))]);
+        assert!(eval_bool(r#"replace(path, "\\", "/") == "a/b/c""#, &v).unwrap());
+        assert!(eval_bool(r#"replace("a.b.c", ".", "") == "abc""#, &v).unwrap());
+        assert!(eval_bool(r#"replace("x+y", "+", "*") == "x*y""#, &v).unwrap());
+        assert!(eval_bool(r#"replace("abc", "z", "y") == "abc""#, &v).unwrap());
+
+        assert!(matches!(
+            eval_bool(r#"replace("abc", "a") == "bc""#, &v),
+            Err(EvalError::WrongArgCount {
+                expected: 3,
+                got: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool(r#"replace("abc", "a", 1) == "1bc""#, &v),
+            Err(EvalError::TypeError {
+                expected: "string",
+                got: "number"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_split_and_join_functions() {
+        let v = vars(&[("s", Value::String("a,b,c".to_string()))]);
+        assert!(eval_bool(r#"len(split(s, ",")) == 3"#, &v).unwrap());
+        assert!(eval_bool(r#"split(s, ",") == ["a", "b", "c"]"#, &v).unwrap());
+        assert!(eval_bool(r#"split(s, ";") == ["a,b,c"]"#, &v).unwrap());
+        assert!(eval_bool(r#"split("", ",") == [""]"#, &v).unwrap());
+        assert!(eval_bool(r#"split("abc", "") == ["a", "b", "c"]"#, &v).unwrap());
+        assert!(eval_bool(r#"join(split(s, ","), " - ") == "a - b - c""#, &v).unwrap());
+        assert!(eval_bool(r#"join([], ",") == """#, &v).unwrap());
+
+        let err = eval_bool(r#"join(["a", 1], ",") == "a,1""#, &v).unwrap_err();
+        assert!(matches!(
+            err,
+            EvalError::TypeError {
+                expected: "string",
+                got: "number"
+            }
+        ));
+        assert!(eval_bool(r#"split(s) == []"#, &v).is_err());
+    }
+
+    #[test]
+    fn test_conditional_expression() {
+        let zero = vars(&[
+            ("n", Value::Number(0.0)),
+            ("msg", Value::String(String::new())),
+        ]);
+        let five = vars(&[
+            ("n", Value::Number(5.0)),
+            ("msg", Value::String("x".into())),
+        ]);
+
+        let rule = r#"if n == 0 then msg == "" else len(msg) > 0"#;
+        assert!(eval_bool(rule, &zero).unwrap());
+        assert!(eval_bool(rule, &five).unwrap());
+
+        // The branch not taken isn't evaluated
+        assert!(eval_bool("(if n == 0 then 0 else 10 / n) == 0", &zero).unwrap());
+        assert!(eval_bool("(if n == 0 then 0 else 10 / n) == 2", &five).unwrap());
+
+        // Chained and nested conditionals
+        let sign = r#"(if n < 0 then "neg" else if n == 0 then "zero" else "pos")"#;
+        assert!(eval_bool(&format!(r#"{} == "zero""#, sign), &zero).unwrap());
+        assert!(eval_bool(&format!(r#"{} == "pos""#, sign), &five).unwrap());
+        assert!(eval_bool(
+            "if if n > 1 then true else false then n == 5 else n == 0",
+            &five
+        )
+        .unwrap());
+
+        assert!(eval_bool("x > 0 forall x in [if n == 0 then 1 else n]", &zero).unwrap());
+        assert!(matches!(
+            eval_bool("if n then 1 else 2", &zero),
+            Err(EvalError::TypeError {
+                expected: "bool",
+                ..
+            })
+        ));
+        assert!(parse("if n == 0 then 1").is_err());
+        assert!(parse("if n == 0 then 1 else 2 extra").is_err());
+        assert!(parse("if_x == 1").is_ok());
+
+        let e = parse("if a then b else c").unwrap();
+        assert_eq!(free_variables(&e), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_format_number_function() {
+        let v = vars(&[("x", Value::Number(12.5))]);
+        assert!(eval_bool(r#"format_number(3.14159, 2) == "3.14""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(2.5, 0) == "2""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(3.7, 0) == "4""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(-1.005, 1) == "-1.0""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(-2.346, 2) == "-2.35""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(-0.001, 2) == "0.00""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number(7, 3) == "7.000""#, &v).unwrap());
+        assert!(eval_bool(r#""total: " + format_number(x, 2) == "total: 12.50""#, &v).unwrap());
+
+        assert!(eval_bool(r#"format_number(1, -1) == "1""#, &v).is_err());
+        assert!(eval_bool(r#"format_number(1, 1.5) == "1""#, &v).is_err());
+        assert!(eval_bool(r#"format_number(1, 1e9) == "1""#, &v).is_err());
+        assert!(eval_bool(r#"format_number(1, 100) != "1""#, &v).unwrap());
+        assert!(eval_bool(r#"format_number("1", 1) == "1.0""#, &v).is_err());
+    }
+
+    #[test]
+    fn test_avg_function() {
+        let v = vars(&[(
+            "latencies",
+            Value::Array(vec![
+                Value::Number(10.0),
+                Value::Number(20.0),
+                Value::Number(60.0),
+            ]),
+        )]);
+        assert!(eval_bool("avg(latencies) == 30", &v).unwrap());
+        assert!(eval_bool("mean(latencies) < 50", &v).unwrap());
+        assert!(eval_bool("avg([-1, 2]) == 0.5", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("avg([]) == 0", &v),
+            Err(EvalError::TypeError {
+                expected: "non-empty array",
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool(r#"mean([1, "2"]) == 1.5"#, &v),
+            Err(EvalError::TypeError {
+                expected: "number",
+                got: "string"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_all_and_any_functions() {
+        let v = vars(&[
+            (
+                "flags",
+                Value::Array(vec![Value::Bool(true), Value::Bool(false)]),
+            ),
+            (
+                "ok",
+                Value::Array(vec![Value::Bool(true), Value::Bool(true)]),
+            ),
+        ]);
+        assert!(!eval_bool("all(flags)", &v).unwrap());
+        assert!(eval_bool("any(flags)", &v).unwrap());
+        assert!(eval_bool("all(ok) and any(ok)", &v).unwrap());
+        assert!(!eval_bool("any([false, false])", &v).unwrap());
+        assert!(eval_bool("all(map([1, 2, 3], x => x > 0))", &v).unwrap());
+
+        // Empty arrays: everything holds, nothing exists
+        assert!(eval_bool("all([])", &v).unwrap());
+        assert!(!eval_bool("any([])", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("all([true, 1])", &v),
+            Err(EvalError::TypeError {
+                expected: "bool",
+                got: "number"
+            })
+        ));
+        assert!(matches!(
+            eval_bool(r#"any([true, "yes"])"#, &v),
+            Err(EvalError::TypeError { got: "string", .. })
+        ));
+        assert!(matches!(
+            eval_bool("all(true)", &v),
+            Err(EvalError::TypeError { got: "bool", .. })
+        ));
+        assert!(matches!(
+            eval_bool("any(flags, ok)", &v),
+            Err(EvalError::WrongArgCount {
+                expected: 1,
+                got: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_sort_and_reverse_functions() {
+        let items = Value::Array(vec![
+            Value::String("pear".into()),
+            Value::String("apple".into()),
+            Value::String("fig".into()),
+        ]);
+        let v = vars(&[("items", items.clone())]);
+        assert!(eval_bool(r#"sort(items) == ["apple", "fig", "pear"]"#, &v).unwrap());
+        assert!(eval_bool(r#"reverse(items) == ["fig", "apple", "pear"]"#, &v).unwrap());
+        assert!(eval_bool(r#"reverse(sort(items)) == ["pear", "fig", "apple"]"#, &v).unwrap());
+        // The input is left untouched
+        assert!(eval_bool(r#"sort(items) != items"#, &v).unwrap());
+        assert!(eval_bool("sort([10, 9, -1, 2.5]) == [-1, 2.5, 9, 10]", &v).unwrap());
+        assert!(eval_bool("sort([]) == [] and reverse([]) == []", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool(r#"sort([1, "a"]) == []"#, &v),
+            Err(EvalError::TypeError {
+                got: "mixed array",
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool("sort([true, false]) == []", &v),
+            Err(EvalError::TypeError { got: "bool", .. })
+        ));
+        assert!(eval_bool(r#"reverse("abc") == "cba""#, &v).is_err());
+    }
+
+    #[test]
+    fn test_slice_function() {
+        let v = vars(&[
+            ("s", Value::String("hello world".into())),
+            (
+                "arr",
+                Value::Array(vec![
+                    Value::Number(1.0),
+                    Value::Number(2.0),
+                    Value::Number(3.0),
+                    Value::Number(4.0),
+                ]),
+            ),
+        ]);
+        assert!(eval_bool(r#"slice(s, 0, 5) == "hello""#, &v).unwrap());
+        assert!(eval_bool(r#"slice(s, -5, 11) == "world""#, &v).unwrap());
+        assert!(eval_bool(r#"slice(s, 0, -6) == "hello""#, &v).unwrap());
+        assert!(eval_bool("slice(arr, 1, 3) == [2, 3]", &v).unwrap());
+        assert!(eval_bool("slice(arr, -2, 4) == [3, 4]", &v).unwrap());
+
+        // Out-of-range bounds clamp
+        assert!(eval_bool("slice(s, 0, 100) == s", &v).unwrap());
+        assert!(eval_bool("slice(arr, -100, 2) == [1, 2]", &v).unwrap());
+        assert!(eval_bool("slice(arr, 10, 20) == []", &v).unwrap());
+        assert!(eval_bool(r#"slice(s, 3, 1) == """#, &v).unwrap());
+
+        assert!(eval_bool("slice(1, 0, 1) == 1", &v).is_err());
+        assert!(matches!(
+            eval_bool("slice(s, 0) == s", &v),
+            Err(EvalError::WrongArgCount { expected: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_int_and_float_functions() {
+        let v = vars(&[
+            ("count", Value::String("42".into())),
+            ("ratio", Value::String(" 0.75 ".into())),
+            ("word", Value::String("forty".into())),
+        ]);
+        assert!(eval_bool("int(count) == 42", &v).unwrap());
+        assert!(eval_bool("int(3.9) == 3", &v).unwrap());
+        assert!(eval_bool("int(-3.9) == -3", &v).unwrap());
+        assert!(eval_bool(r#"int("-3.7") == -3"#, &v).unwrap());
+        assert!(eval_bool("float(ratio) == 0.75", &v).unwrap());
+        assert!(eval_bool("float(2) == 2", &v).unwrap());
+        assert!(eval_bool("int(ratio) == 0", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("int(word) == 0", &v),
+            Err(EvalError::TypeError {
+                expected: "numeric string",
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool(r#"float("nan") == 0"#, &v),
+            Err(EvalError::TypeError { .. })
+        ));
+        assert!(matches!(
+            eval_bool("float(true) == 1", &v),
+            Err(EvalError::TypeError { got: "bool", .. })
+        ));
+    }
+
+    #[test]
+    fn test_coalesce_operator() {
+        let mut obj = HashMap::new();
+        obj.insert("name".to_string(), Value::String("alice".into()));
+        obj.insert("nickname".to_string(), Value::Null);
+        let v = vars(&[("user", Value::Object(obj))]);
+
+        assert!(eval_bool(r#"(user.name ?? "anon") == "alice""#, &v).unwrap());
+        assert!(eval_bool(r#"(user.email ?? "none") == "none""#, &v).unwrap());
+        assert!(eval_bool(r#"(user["email"] ?? "none") == "none""#, &v).unwrap());
+        assert!(eval_bool(r#"(user.nickname ?? user.name) == "alice""#, &v).unwrap());
+        assert!(eval_bool("(missing ?? 3) == 3", &v).unwrap());
+        assert!(eval_bool("(missing ?? other ?? 4) == 4", &v).unwrap());
+
+        // Binds looser than `or`
+        assert!(eval_bool("missing ?? false or true", &v).unwrap());
+
+        // The right side is only evaluated when needed
+        assert!(eval_bool(r#"(user.name ?? undefined_var) == "alice""#, &v).unwrap());
+
+        // Other errors still propagate
+        assert!(matches!(
+            eval_bool("(1 / 0 ?? 2) == 2", &v),
+            Err(EvalError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval_bool(r#"(user.name.first ?? "x") == "x""#, &v),
+            Err(EvalError::TypeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_captures_function() {
+        let v = vars(&[("output", Value::String("login user=alice id=42".into()))]);
+        assert!(eval_bool(
+            r#"captures(output, /user=(?P<name>\w+)/).name == "alice""#,
+            &v
+        )
+        .unwrap());
+        assert!(eval_bool(r#"captures(output, /id=(\d+)/)["1"] == "42""#, &v).unwrap());
+        assert!(eval_bool(
+            r#"keys(captures(output, /(?<user>user)=(\w+) (id)?/)) == ["2", "3", "user"]"#,
+            &v
+        )
+        .unwrap());
+        assert!(eval_bool(r#"captures(output, /missing=(\w+)/) == null"#, &v).unwrap());
+        assert!(eval_bool(r#"captures(output, /user=(\w+)(X)?/)["2"] == null"#, &v).unwrap());
+        assert!(eval_bool(
+            r#"(captures(output, /nope/).x ?? "default") == "default""#,
+            &v
+        )
+        .is_err());
+        assert!(matches!(
+            eval_bool(r#"captures(output, "(") == null"#, &v),
+            Err(EvalError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_null_is_a_value_and_a_type() {
+        let v = vars(&[("x", Value::Null), ("y", Value::Number(1.0))]);
+        assert!(eval_bool("x == null", &v).unwrap());
+        assert!(!eval_bool("x != null", &v).unwrap());
+        assert!(eval_bool("y != null", &v).unwrap());
+        assert!(eval_bool("type(x) == null", &v).unwrap());
+        assert!(!eval_bool("type(y) == null", &v).unwrap());
+        assert!(eval_bool("type(null) == null", &v).unwrap());
+        assert!(eval_bool("[1, null] contains null", &v).unwrap());
+        assert!(eval_bool("(null ?? 5) == 5", &v).unwrap());
+        assert!(eval_bool("x == null and y == 1", &v).unwrap());
+        assert_eq!(parse("null").unwrap(), Expr::Null);
+        assert!(matches!(
+            eval_bool("not null", &v),
+            Err(EvalError::TypeError {
+                expected: "bool",
+                got: "null"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        let v = vars(&[("n", Value::Number(42.0)), ("m", Value::Number(7.0))]);
+        assert!(eval_bool("0 < n < 100", &v).unwrap());
+        assert!(!eval_bool("0 < n < 10", &v).unwrap());
+        assert!(eval_bool("0 <= m < n <= 42", &v).unwrap());
+        assert!(!eval_bool("0 <= m < n < 42", &v).unwrap());
+        assert!(eval_bool("m == 7 == m", &v).unwrap());
+        assert!(eval_bool("0 < n - 40 < m", &v).unwrap());
+
+        // `and` and `or` bind more loosely than a chain
+        assert!(eval_bool("0 < n < 10 or 0 < m < 10", &v).unwrap());
+        assert!(!eval_bool("0 < n < 100 and 10 < m < 100", &v).unwrap());
+        assert!(eval_bool("not 0 < n < 10", &v).unwrap());
+
+        // Stops at the first false comparison without evaluating the rest
+        assert!(!eval_bool("n < 0 < missing", &v).unwrap());
+        assert!(matches!(
+            eval_bool("0 < n < missing", &v),
+            Err(EvalError::UndefinedVariable(_))
+        ));
+
+        let e = parse("0 < n < m").unwrap();
+        assert_eq!(free_variables(&e), vec!["n", "m"]);
+        assert!(parse("a contains b < c").is_err());
+    }
+
+    #[test]
+    fn test_match_function() {
+        let v = vars(&[("line", Value::String("at 12:34 done".into()))]);
+        assert!(eval_bool(r#"match(line, /(\d+):(\d+)/)[1] == "12""#, &v).unwrap());
+        assert!(eval_bool(r#"match(line, /(\d+):(\d+)/) == ["12:34", "12", "34"]"#, &v).unwrap());
+        assert!(eval_bool(r#"match(line, /(\d+)(x)?/)[2] == null"#, &v).unwrap());
+        assert!(eval_bool(r#"match(line, /missing/) == null"#, &v).unwrap());
+        assert!(eval_bool(
+            r#"line matches /done/ and match(line, /done/)[0] == "done""#,
+            &v
+        )
+        .unwrap());
+        assert!(matches!(
+            eval_bool(r#"match(line, "(") == null"#, &v),
+            Err(EvalError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_count_expression() {
+        let codes = Value::Array(vec![
+            Value::Number(200.0),
+            Value::Number(404.0),
+            Value::Number(201.0),
+            Value::Number(500.0),
+        ]);
+        let v = vars(&[("codes", codes), ("count", Value::Number(3.0))]);
+        assert!(eval_bool("count x in codes where x >= 400 == 2", &v).unwrap());
+        assert!(eval_bool("count x in codes where x >= 600 == 0", &v).unwrap());
+        assert!(eval_bool("(count c in codes where (c >= 200 and c < 300)) == 2", &v).unwrap());
+        assert!(eval_bool("count x in codes where not x == 200 == 3", &v).unwrap());
+        assert!(eval_bool(r#"count v in {"a": 1, "b": 2} where v > 1 == 1"#, &v).unwrap());
+        assert!(eval_bool("count x in [] where x > 0 == 0", &v).unwrap());
+
+        // The bound variable doesn't leak, and `count` is still a variable
+        assert!(eval_bool("count == 3", &v).unwrap());
+        assert!(eval_bool("count x in codes where x > count == 4", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("count x in 5 where x > 0 == 0", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+        assert!(matches!(
+            eval_bool("count x in codes where x == 0", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+
+        let expr = parse("count x in codes where x > limit == 0").unwrap();
+        assert_eq!(free_variables(&expr), vec!["codes", "limit"]);
+    }
+
+    #[test]
+    fn test_set_functions() {
+        let v = vars(&[]);
+        assert!(eval_bool("union([1, 2, 2], [3, 1, 4]) == [1, 2, 3, 4]", &v).unwrap());
+        assert!(eval_bool("intersection([1, 2, 3], [2, 3, 4]) == [2, 3]", &v).unwrap());
+        assert!(eval_bool("intersection([3, 3, 1], [1, 3]) == [3, 1]", &v).unwrap());
+        assert!(eval_bool("difference([1, 2, 3], [2]) == [1, 3]", &v).unwrap());
+        assert!(eval_bool("difference([1, 1], []) == [1]", &v).unwrap());
+        assert!(eval_bool(r#"union(["a"], ["b", "a"]) == ["a", "b"]"#, &v).unwrap());
+        assert!(eval_bool("intersection([1], [2]) == []", &v).unwrap());
+        assert!(matches!(
+            eval_bool("union([1], 2) == [1]", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_range_function() {
+        let v = vars(&[]);
+        assert!(eval_bool("range(5) == [0, 1, 2, 3, 4]", &v).unwrap());
+        assert!(eval_bool("range(2, 5) == [2, 3, 4]", &v).unwrap());
+        assert!(eval_bool("range(-2, 1) == [-2, -1, 0]", &v).unwrap());
+        assert!(eval_bool("range(3.9) == [0, 1, 2]", &v).unwrap());
+        assert!(eval_bool("range(0) == []", &v).unwrap());
+        assert!(eval_bool("range(5, 2) == []", &v).unwrap());
+        assert!(eval_bool("x >= 0 forall x in range(5)", &v).unwrap());
+        assert!(matches!(
+            eval_bool(r#"range("5") == []"#, &v),
+            Err(EvalError::TypeError { .. })
+        ));
+        assert!(matches!(
+            eval_bool("range(1, 2, 3) == []", &v),
+            Err(EvalError::WrongArgCount { .. })
+        ));
+        assert_eq!(
+            evaluate(&parse("len(range(1000000))").unwrap(), &v).unwrap(),
+            Value::Number(1_000_000.0)
+        );
+        let v = vars(&[("n", Value::Number(50_000_000_000_000.0))]);
+        assert!(matches!(
+            eval_bool("len(range(n)) > 0", &v),
+            Err(EvalError::RangeTooLarge { .. })
+        ));
+        assert!(matches!(
+            eval_bool("len(range(-n, 0)) > 0", &v),
+            Err(EvalError::RangeTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flatten_function() {
+        let v = vars(&[]);
+        assert!(eval_bool("len(flatten([[1, 2], [3, 4], [5, 6]])) == 6", &v).unwrap());
+        assert!(eval_bool("flatten([1, [2, [3, [4]]], []]) == [1, 2, [3, [4]]]", &v).unwrap());
+        assert!(eval_bool("flatten([1, [2, [3, [4]]]], 2) == [1, 2, 3, [4]]", &v).unwrap());
+        assert!(eval_bool("flatten([1, [2, [3, [4]]]], 99) == [1, 2, 3, 4]", &v).unwrap());
+        assert!(eval_bool("flatten([[1], [2]], 0) == [[1], [2]]", &v).unwrap());
+        assert!(matches!(
+            eval_bool("flatten(1) == [1]", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+        assert!(matches!(
+            eval_bool("flatten([1], 1.5) == [1]", &v),
+            Err(EvalError::TypeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_map_and_filter_functions() {
+        let nums = Value::Array((1..=5).map(|n| Value::Number(n as f64)).collect());
+        let v = vars(&[("nums", nums), ("factor", Value::Number(2.0))]);
+        assert!(eval_bool("map(nums, x => x * 2) == [2, 4, 6, 8, 10]", &v).unwrap());
+        assert!(eval_bool("sum(map(nums, x => x * factor)) == 30", &v).unwrap());
+        assert!(eval_bool("filter(nums, x => x % 2 == 1) == [1, 3, 5]", &v).unwrap());
+        assert!(eval_bool("len(filter(nums, n => n > 2 and n < 5)) == 2", &v).unwrap());
+        assert!(eval_bool("sum(map(filter(nums, x => x > 3), x => x * 10)) == 90", &v).unwrap());
+        assert!(eval_bool(r#"map(["a", "b"], s => upper(s)) == ["A", "B"]"#, &v).unwrap());
+        assert!(eval_bool("map([], x => x) == []", &v).unwrap());
+
+        // The bound variable shadows an outer one without leaking
+        assert!(eval_bool("map(nums, factor => factor)[0] == 1 and factor == 2", &v).unwrap());
+
+        assert!(matches!(
+            eval_bool("filter(nums, x => x) == []", &v),
+            Err(EvalError::TypeError {
+                expected: "bool",
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool("map(nums, factor) == []", &v),
+            Err(EvalError::TypeError { got: "number", .. })
+        ));
+        assert!(matches!(
+            eval_bool("len(x => x) == 1", &v),
+            Err(EvalError::TypeError { got: "lambda", .. })
+        ));
+
+        let expr = parse("map(nums, x => x * factor)").unwrap();
+        assert_eq!(free_variables(&expr), vec!["nums", "factor"]);
+    }
+
+    #[test]
+    fn test_min_max_multiple_args() {
+        let v = vars(&[("a", Value::Number(7.0)), ("b", Value::Number(-2.0))]);
+        assert!(eval_bool("min(a, b) == -2", &v).unwrap());
+        assert!(eval_bool("max(a, b, 10) == 10", &v).unwrap());
+        assert!(eval_bool("max([a, b]) == 7", &v).unwrap());
+        assert!(eval_bool("min(3) == 3", &v).is_err());
+        assert!(matches!(
+            eval_bool("min() == 0", &v),
+            Err(EvalError::WrongArgCount { .. })
+        ));
+        assert!(matches!(
+            eval_bool(r#"max(1, "2") == 2"#, &v),
+            Err(EvalError::TypeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_free_variables() {
+        let e = parse("x > 0 and len(items) == count").unwrap();
+        assert_eq!(free_variables(&e), vec!["x", "items", "count"]);
+
+        let e = parse("n > 0 forall n in values(obj)").unwrap();
+        assert_eq!(free_variables(&e), vec!["obj"]);
+
+        let e = parse(r#"type(x) == number and x == x and [y, 1] contains "a""#).unwrap();
+        assert_eq!(free_variables(&e), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn test_case_insensitive_functions() {
+        let v = vars(&[("os", Value::String("Linux-x86_64".to_string()))]);
+        assert!(eval_bool(r#"ieq(os, "LINUX-X86_64")"#, &v).unwrap());
+        assert!(!eval_bool(r#"ieq(os, "linux")"#, &v).unwrap());
+        assert!(eval_bool(r#"icontains(os, "X86")"#, &v).unwrap());
+        assert!(eval_bool(r#"istartswith(os, "linux")"#, &v).unwrap());
+        assert!(eval_bool(r#"iendswith(os, "_64")"#, &v).unwrap());
+        assert!(!eval_bool(r#"iendswith(os, "LINUX")"#, &v).unwrap());
+
+        assert!(matches!(
+            eval_bool(r#"ieq(os)"#, &v),
+            Err(EvalError::WrongArgCount {
+                expected: 2,
+                got: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_bool(r#"icontains(os, 86)"#, &v),
+            Err(EvalError::TypeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_version_functions() {
+        let v = vars(&[]);
+        assert!(eval_bool(r#"version_lt("1.9", "1.10")"#, &v).unwrap());
+        assert!(!eval_bool(r#"version_lt("1.10", "1.9")"#, &v).unwrap());
+        assert!(eval_bool(r#"version_cmp("1.10", "1.9") == 1"#, &v).unwrap());
+        assert!(eval_bool(r#"version_cmp("1.2", "1.2.0") == 0"#, &v).unwrap());
+        assert!(eval_bool(r#"version_cmp("v2.0", "2.0") == 0"#, &v).unwrap());
+        assert!(eval_bool(r#"version_lt("1.2.3-beta", "1.2.3")"#, &v).unwrap());
+        assert!(eval_bool(r#"version_lt("1.2.3-alpha", "1.2.3-beta")"#, &v).unwrap());
+        assert!(eval_bool(r#"version_cmp("0.9.9", "1") == -1"#, &v).unwrap());
+    }
 }
@@ -1,8 +1,22 @@
-use clap::Parser;
+use crate::output::{ColorTheme, OutputFormat};
+use clap::{Parser, ValueEnum};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
+
+/// Exit code legend shown at the end of `--help`
+const EXIT_CODES: &str = "Exit codes:
+  0    All tests passed (or --fail-under was met)
+  1    A test failed, or a file failed to parse
+  130  Interrupted by Ctrl-C (teardown still runs)";
 
 #[derive(Parser)]
-#[command(name = "cctr", about = "CLI Corpus Test Runner", version)]
+#[command(
+    name = "cctr",
+    about = "CLI Corpus Test Runner",
+    version,
+    after_help = EXIT_CODES
+)]
 pub struct Cli {
     /// Test files or directories (or "-" to read from stdin)
     #[arg(default_value = ".")]
@@ -12,6 +26,18 @@ pub struct Cli {
     #[arg(short, long)]
     pub pattern: Option<String>,
 
+    /// Skip tests whose name or file name contains SUBSTR (repeatable)
+    #[arg(long, value_name = "SUBSTR")]
+    pub exclude: Vec<String>,
+
+    /// Only run tests with any of these comma-separated %tags
+    #[arg(long, value_delimiter = ',', value_name = "TAGS")]
+    pub tags: Vec<String>,
+
+    /// Skip tests with any of these comma-separated %tags
+    #[arg(long, value_delimiter = ',', value_name = "TAGS")]
+    pub skip_tags: Vec<String>,
+
     /// Update expected outputs from actual results
     #[arg(short, long)]
     pub update: bool,
@@ -20,15 +46,199 @@ pub struct Cli {
     #[arg(short, long)]
     pub list: bool,
 
+    /// Check constraints for undefined variables without running tests
+    #[arg(long)]
+    pub lint: bool,
+
+    /// With --list, print only per-suite and total test counts
+    #[arg(long, requires = "list")]
+    pub count: bool,
+
     /// Show each test as it completes with timing (-v), or stream output (-vv)
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// Print nothing unless a test fails, then print only the failures and summary
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Only show suites with failures or skips in the results
+    #[arg(long)]
+    pub failures_only: bool,
+
+    /// Result format: text, or json, junit (XML) or tap to print every result after the run
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Exit successfully if at least PERCENT of the tests that ran passed
+    #[arg(long, value_name = "PERCENT", value_parser = parse_fail_under)]
+    pub fail_under: Option<f64>,
+
+    /// List the N slowest tests after the results (0 lists none)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub slowest: usize,
+
+    /// Write per-test durations as JSON to this file after the run
+    #[arg(long, value_name = "PATH")]
+    pub timings_json: Option<PathBuf>,
+
+    /// Also discover suites and test files whose names start with '.'
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// Parse every test file first and exit with an error before running
+    /// anything if any file fails to parse
+    #[arg(long)]
+    pub abort_on_parse_error: bool,
+
+    /// Stop the run after the first suite with a failure; suites already
+    /// running are interrupted but still run their teardown
+    #[arg(long)]
+    pub bail: bool,
+
+    /// Kill any command still running after SECONDS and fail its test;
+    /// a test's own %timeout takes precedence
+    #[arg(long, value_name = "SECONDS", value_parser = parse_timeout)]
+    pub timeout: Option<Duration>,
+
     /// Run suites sequentially instead of in parallel
     #[arg(short, long)]
     pub sequential: bool,
 
+    /// After running, keep watching the test files and run again whenever
+    /// one changes, until Ctrl-C
+    #[arg(short, long, conflicts_with_all = ["bail", "update"])]
+    pub watch: bool,
+
+    /// Run each selected test N times and report tests that didn't pass
+    /// every run as flaky
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat: u32,
+
+    /// Run suites, and the tests in each file, in a random order to catch
+    /// tests that depend on each other. Setup and teardown keep their order
+    #[arg(long)]
+    pub shuffle: bool,
+
+    /// Seed for --shuffle, to repeat the order of an earlier run
+    #[arg(long, value_name = "SEED", requires = "shuffle")]
+    pub seed: Option<u64>,
+
+    /// Run at most N suites at a time (default: one per CPU); 1 is the same as --sequential
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
     /// Disable colored output
     #[arg(long)]
     pub no_color: bool,
+
+    /// Color palette for results
+    #[arg(long, value_enum, default_value_t = ColorTheme::Default)]
+    pub color_theme: ColorTheme,
+
+    /// Print the effective settings and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Print the files and tests that would run, in order, as JSON and exit
+    #[arg(long)]
+    pub dump_plan: bool,
+
+    /// Print the tests that would run after filtering, and which would be
+    /// skipped, without running anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Log parser and runner decisions to stderr (also CCTR_TRACE=1)
+    #[arg(long)]
+    pub trace: bool,
+}
+
+impl Cli {
+    /// Whether suites run one at a time, from `--sequential` or `--jobs 1`
+    pub fn runs_sequentially(&self) -> bool {
+        self.sequential || self.jobs.is_some_and(|jobs| jobs.get() == 1)
+    }
+
+    /// The effective settings as `name: value` lines, keyed by flag name.
+    /// `use_color` is the resolved color setting, which also depends on
+    /// whether stdout is a terminal.
+    pub fn config_dump(&self, use_color: bool) -> String {
+        fn opt<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "(none)".to_string(), |v| v.to_string())
+        }
+        fn list(values: &[String]) -> String {
+            opt((!values.is_empty()).then(|| values.join(",")))
+        }
+        fn value_name(value: &impl ValueEnum) -> String {
+            value
+                .to_possible_value()
+                .map_or_else(String::new, |v| v.get_name().to_string())
+        }
+
+        let paths: Vec<_> = self.paths.iter().map(|p| p.display().to_string()).collect();
+        let settings = [
+            ("paths", paths.join(" ")),
+            ("pattern", opt(self.pattern.as_ref())),
+            ("exclude", list(&self.exclude)),
+            ("tags", list(&self.tags)),
+            ("skip-tags", list(&self.skip_tags)),
+            ("update", self.update.to_string()),
+            ("list", self.list.to_string()),
+            ("count", self.count.to_string()),
+            ("dump-plan", self.dump_plan.to_string()),
+            ("dry-run", self.dry_run.to_string()),
+            ("lint", self.lint.to_string()),
+            ("verbose", self.verbose.to_string()),
+            ("quiet", self.quiet.to_string()),
+            ("failures-only", self.failures_only.to_string()),
+            ("format", value_name(&self.format)),
+            ("fail-under", opt(self.fail_under)),
+            ("slowest", self.slowest.to_string()),
+            (
+                "timings-json",
+                opt(self.timings_json.as_ref().map(|p| p.display())),
+            ),
+            ("include-hidden", self.include_hidden.to_string()),
+            (
+                "abort-on-parse-error",
+                self.abort_on_parse_error.to_string(),
+            ),
+            ("bail", self.bail.to_string()),
+            ("timeout", opt(self.timeout.map(|t| format!("{:?}", t)))),
+            ("sequential", self.sequential.to_string()),
+            ("jobs", opt(self.jobs)),
+            ("watch", self.watch.to_string()),
+            ("repeat", self.repeat.to_string()),
+            ("shuffle", self.shuffle.to_string()),
+            ("seed", opt(self.seed)),
+            ("trace", self.trace.to_string()),
+            ("color", use_color.to_string()),
+            ("color-theme", value_name(&self.color_theme)),
+        ];
+
+        settings
+            .iter()
+            .map(|(name, value)| format!("{}: {}\n", name, value))
+            .collect()
+    }
+}
+
+/// Parse `--timeout` seconds like `%timeout`: a positive number, fractions allowed
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite() && *s > 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("expected a positive number of seconds, got '{}'", value))
+}
+
+/// Parse `--fail-under` as a percentage from 0 to 100
+fn parse_fail_under(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|p| (0.0..=100.0).contains(p))
+        .ok_or_else(|| format!("expected a percentage from 0 to 100, got '{}'", value))
 }
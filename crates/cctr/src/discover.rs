@@ -11,6 +11,8 @@ pub struct Suite {
     pub has_setup: bool,
     pub has_teardown: bool,
     pub single_file: Option<PathBuf>,
+    /// Include corpus files whose names start with `.`
+    pub include_hidden: bool,
 }
 
 impl Suite {
@@ -40,6 +42,7 @@ impl Suite {
             has_setup,
             has_teardown,
             single_file: None,
+            include_hidden: false,
         }
     }
 
@@ -70,6 +73,7 @@ impl Suite {
             has_setup,
             has_teardown,
             single_file: Some(file_path),
+            include_hidden: false,
         }
     }
 
@@ -89,6 +93,7 @@ impl Suite {
                     && !p
                         .file_name()
                         .is_some_and(|n| n.to_string_lossy().starts_with('_'))
+                    && (self.include_hidden || !is_hidden(p))
             })
             .collect();
         files.sort();
@@ -122,7 +127,14 @@ impl Suite {
     }
 }
 
-pub fn discover_suites(root: &Path) -> Result<Vec<Suite>> {
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+}
+
+/// Find suites under `root`. Directories and files whose names start with `.`
+/// are skipped unless `include_hidden` is set; `root` itself is always searched.
+pub fn discover_suites(root: &Path, include_hidden: bool) -> Result<Vec<Suite>> {
     // If root is a single file, create a suite containing just that file
     if root.is_file() {
         if root.extension().is_some_and(|ext| ext == "txt") {
@@ -139,6 +151,7 @@ pub fn discover_suites(root: &Path) -> Result<Vec<Suite>> {
     for entry in WalkDir::new(root)
         .follow_links(true)
         .into_iter()
+        .filter_entry(|e| include_hidden || e.depth() == 0 || !is_hidden(e.path()))
         .filter_map(|e| e.ok())
     {
         let path = entry.path();
@@ -168,7 +181,10 @@ pub fn discover_suites(root: &Path) -> Result<Vec<Suite>> {
 
     let mut suites: Vec<Suite> = suite_dirs
         .into_iter()
-        .map(|p| Suite::new(p, root))
+        .map(|p| Suite {
+            include_hidden,
+            ..Suite::new(p, root)
+        })
         .collect();
 
     suites.sort_by(|a, b| a.name.cmp(&b.name));
@@ -197,7 +213,7 @@ mod tests {
         fs::create_dir_all(&suite_dir).unwrap();
         create_test_file(&suite_dir, "test.txt", "===\ntest\n===\necho hi\n---\nhi\n");
 
-        let suites = discover_suites(tmp.path()).unwrap();
+        let suites = discover_suites(tmp.path(), false).unwrap();
         assert_eq!(suites.len(), 1);
         assert_eq!(suites[0].name, "suite1");
     }
@@ -212,7 +228,7 @@ mod tests {
         create_test_file(&suite1, "test.txt", "===\ntest\n===\necho hi\n---\nhi\n");
         create_test_file(&suite2, "test.txt", "===\ntest\n===\necho hi\n---\nhi\n");
 
-        let suites = discover_suites(tmp.path()).unwrap();
+        let suites = discover_suites(tmp.path(), false).unwrap();
         assert_eq!(suites.len(), 2);
     }
 
@@ -229,7 +245,7 @@ mod tests {
             "===\nfake\n===\nfake\n---\nfake\n",
         );
 
-        let suites = discover_suites(tmp.path()).unwrap();
+        let suites = discover_suites(tmp.path(), false).unwrap();
         assert_eq!(suites.len(), 1);
         assert_eq!(suites[0].corpus_files().len(), 1);
     }
@@ -246,8 +262,32 @@ mod tests {
             "===\nsetup\n===\necho setup\n---\n",
         );
 
-        let suites = discover_suites(tmp.path()).unwrap();
+        let suites = discover_suites(tmp.path(), false).unwrap();
         assert!(suites[0].has_setup);
         assert!(!suites[0].has_teardown);
     }
+
+    #[test]
+    fn test_hidden_entries_need_include_hidden() {
+        let tmp = TempDir::new().unwrap();
+        let visible = tmp.path().join("visible");
+        let hidden = tmp.path().join(".config/suite");
+        create_test_file(&visible, "test.txt", "===\ntest\n===\necho hi\n---\nhi\n");
+        create_test_file(&visible, ".draft.txt", "===\ntest\n===\necho hi\n---\nhi\n");
+        create_test_file(&hidden, "test.txt", "===\ntest\n===\necho hi\n---\nhi\n");
+
+        let suites = discover_suites(tmp.path(), false).unwrap();
+        let names: Vec<_> = suites.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["visible"]);
+        assert_eq!(suites[0].corpus_files(), vec![visible.join("test.txt")]);
+
+        let suites = discover_suites(tmp.path(), true).unwrap();
+        let names: Vec<_> = suites.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![".config/suite", "visible"]);
+        assert_eq!(suites[1].corpus_files().len(), 2);
+
+        // An explicitly given hidden directory is searched
+        let suites = discover_suites(&tmp.path().join(".config"), false).unwrap();
+        assert_eq!(suites.len(), 1);
+    }
 }
@@ -1,12 +1,17 @@
 pub mod cli;
 pub mod discover;
 pub mod error;
+pub mod lint;
 pub mod matcher;
 pub mod output;
+pub mod report;
 pub mod runner;
 pub mod update;
 
+pub use runner::run_corpus_str;
+
 pub use cctr_corpus::{
-    parse_content, parse_file, CorpusFile, ParseError, SkipDirective, TestCase, VarType,
-    VariableDecl,
+    parse_content, parse_file, set_trace, trace_enabled, CorpusFile, ExpectedExit, LineCount,
+    LineCountOp, MatchMode, NumericTolerance, ParseError, SkipDirective, SortOrder, TestCase,
+    VarType, VariableDecl,
 };
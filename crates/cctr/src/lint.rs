@@ -0,0 +1,187 @@
+//! Static checks on corpus files that don't require running any commands.
+
+use crate::{CorpusFile, MatchMode};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub file_path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+/// Check every constraint's free variables against the variables captured by
+/// the test itself or by earlier tests in the same file.
+pub fn lint_corpus(corpus: &CorpusFile) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    // Named groups of `%match regex` patterns also become variables
+    let regex_names: Vec<Vec<String>> = corpus
+        .tests
+        .iter()
+        .map(|test| match test.match_mode {
+            MatchMode::Regex => Regex::new(&test.expected_output)
+                .map(|re| re.capture_names().flatten().map(String::from).collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        })
+        .collect();
+    let mut known: HashSet<&str> = HashSet::from(["output"]);
+
+    for (test, names) in corpus.tests.iter().zip(&regex_names) {
+        known.extend(test.variable_names());
+        if test.match_mode == MatchMode::Regex {
+            known.insert("groups");
+            known.extend(names.iter().map(String::as_str));
+        }
+
+        for constraint in &test.constraints {
+            let expr = match cctr_expr::parse(constraint) {
+                Ok(expr) => expr,
+                Err(e) => {
+                    warnings.push(LintWarning {
+                        file_path: test.file_path.clone(),
+                        line: test.start_line,
+                        message: format!(
+                            "test '{}': constraint '{}': {}",
+                            test.name, constraint, e
+                        ),
+                    });
+                    continue;
+                }
+            };
+
+            for name in cctr_expr::free_variables(&expr) {
+                if known.contains(name.as_str()) {
+                    continue;
+                }
+                let mut message = format!(
+                    "test '{}': constraint '{}' references undefined variable '{}'",
+                    test.name, constraint, name
+                );
+                if let Some(suggestion) = closest_name(&name, &known) {
+                    message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+                }
+                warnings.push(LintWarning {
+                    file_path: test.file_path.clone(),
+                    line: test.start_line,
+                    message,
+                });
+            }
+        }
+    }
+
+    warnings
+}
+
+/// The known name within edit distance 2 of `name`, if any
+fn closest_name<'a>(name: &str, known: &HashSet<&'a str>) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (edit_distance(name, k), *k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, k)| k)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_content;
+    use std::path::Path;
+
+    fn lint(content: &str) -> Vec<LintWarning> {
+        lint_corpus(&parse_content(content, Path::new("test.txt")).unwrap())
+    }
+
+    #[test]
+    fn test_unknown_variable_flagged() {
+        let warnings = lint(
+            r#"===
+timing
+===
+echo 5
+---
+{{ elapsed }}
+---
+where
+* elasped < 10
+"#,
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 1);
+        assert_eq!(
+            warnings[0].message,
+            "test 'timing': constraint 'elasped < 10' references undefined variable 'elasped' (did you mean 'elapsed'?)"
+        );
+    }
+
+    #[test]
+    fn test_known_variables_not_flagged() {
+        let warnings = lint(
+            r#"===
+capture
+===
+echo 5 1 2
+---
+{{ n }} {{ a }} {{ b }}
+---
+where
+* n > 0
+* x > 0 forall x in [a, b]
+
+===
+uses earlier capture
+===
+echo 6
+---
+{{ m }}
+---
+where
+* m > n
+"#,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn test_regex_groups_known() {
+        let warnings = lint(
+            r#"===
+version
+%match regex
+===
+echo v1.2
+---
+v(?<major>\d+)\.(\d+)
+---
+where
+* major == 1
+* groups[2] == "2"
+"#,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn test_invalid_constraint_flagged() {
+        let warnings = lint("===\nbad\n===\necho 5\n---\n{{ n }}\n---\nwhere\n* n >\n");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("unexpected end of expression"));
+    }
+}
@@ -1,19 +1,24 @@
 use cctr::cli::Cli;
 use cctr::discover::{discover_suites, Suite};
-use cctr::output::Output;
+use cctr::lint::lint_corpus;
+use cctr::output::{junit, tap, Output, OutputFormat};
 use cctr::parse_file;
+use cctr::report::{plan_json, results_json, write_timings_json};
 use cctr::runner::{
-    is_in_teardown, is_interrupted, run_from_stdin, run_suite, set_interrupted, ProgressEvent,
-    SuiteResult,
+    bail_message, count_not_executed, interrupted_message, is_in_teardown, is_interrupted,
+    pass_rate, plan_suite, run_from_stdin, run_suite, set_interrupted, shuffle_suites,
+    ProgressEvent, RunOptions, SuiteResult, TestFilter, INTERRUPTED_EXIT_CODE,
 };
 use cctr::update::update_corpus_file;
 use clap::Parser;
+use notify::{EventKind, RecursiveMode, Watcher};
 use rayon::prelude::*;
 use regex::Regex;
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 fn main() -> anyhow::Result<()> {
     #[cfg(unix)]
@@ -27,7 +32,7 @@ fn main() -> anyhow::Result<()> {
         use std::io::Write;
         if is_interrupted() {
             let _ = writeln!(std::io::stderr(), "\nForce quit");
-            std::process::exit(130);
+            std::process::exit(INTERRUPTED_EXIT_CODE);
         }
         if is_in_teardown() {
             let _ = writeln!(
@@ -46,9 +51,21 @@ fn main() -> anyhow::Result<()> {
     }
 
     let cli = Cli::parse();
+    if cli.trace {
+        cctr::set_trace(true);
+    }
 
     let use_color = !cli.no_color && atty::is(atty::Stream::Stdout);
-    let mut output = Output::new(use_color);
+    let mut output = Output::new(use_color)
+        .quiet(cli.quiet)
+        .failures_only(cli.failures_only)
+        .fail_under(cli.fail_under)
+        .theme(cli.color_theme);
+
+    if cli.print_config {
+        print!("{}", cli.config_dump(use_color));
+        return Ok(());
+    }
 
     if cli.paths.len() == 1 && cli.paths[0].as_os_str() == "-" {
         return run_stdin_mode(&cli, &mut output);
@@ -60,48 +77,142 @@ fn main() -> anyhow::Result<()> {
             std::process::exit(1);
         })
     });
+    let filter = TestFilter {
+        pattern,
+        tags: normalize_tags(&cli.tags),
+        skip_tags: normalize_tags(&cli.skip_tags),
+        exclude: cli.exclude.clone(),
+    };
 
-    let suites = discover_all(&cli.paths)?;
+    let mut suites = discover_all(&cli.paths, cli.include_hidden)?;
 
     if cli.list {
-        list_tests(&suites, pattern.as_ref(), &mut output)?;
+        list_tests(&suites, &filter, cli.count, &mut output)?;
+        return Ok(());
+    }
+
+    if cli.dump_plan || cli.dry_run {
+        let shuffle_seed = shuffle_seed(&cli);
+        if let Some(seed) = shuffle_seed {
+            shuffle_suites(&mut suites, seed);
+        }
+        if cli.dump_plan {
+            let plan = plan_json(&suites, &filter, cli.runs_sequentially(), shuffle_seed);
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        } else {
+            let plan: Vec<_> = suites
+                .iter()
+                .flat_map(|suite| plan_suite(suite, &filter, shuffle_seed))
+                .collect();
+            output.print_dry_run(&plan);
+            if let Some(seed) = shuffle_seed {
+                eprintln!("Shuffled with --seed {}", seed);
+            }
+        }
         return Ok(());
     }
 
+    if cli.lint {
+        let clean = lint_suites(&suites, &mut output)?;
+        std::process::exit(if clean { 0 } else { 1 });
+    }
+
+    if cli.watch {
+        return watch(&cli, &mut output, &filter);
+    }
+
     if suites.is_empty() {
         eprintln!("No test suites found");
         std::process::exit(1);
     }
 
+    let exit_code = run_suites(&cli, &mut output, suites, &filter)?;
+    std::process::exit(exit_code);
+}
+
+/// The seed for `--shuffle`: `--seed` if given, otherwise a random one
+fn shuffle_seed(cli: &Cli) -> Option<u64> {
+    cli.shuffle
+        .then(|| cli.seed.unwrap_or_else(|| fastrand::u64(..)))
+}
+
+/// Run `suites` and print the results, returning the process exit code
+fn run_suites(
+    cli: &Cli,
+    output: &mut Output,
+    mut suites: Vec<Suite>,
+    filter: &TestFilter,
+) -> anyhow::Result<i32> {
+    let use_color = !cli.no_color && atty::is(atty::Stream::Stdout);
+
+    if cli.abort_on_parse_error {
+        check_parse_errors(&suites);
+    }
+
+    let shuffle_seed = shuffle_seed(cli);
+    if let Some(seed) = shuffle_seed {
+        shuffle_suites(&mut suites, seed);
+    }
+
     let start_time = Instant::now();
 
     let (progress_tx, progress_rx) = mpsc::channel::<ProgressEvent>();
     let verbose_level = cli.verbose;
 
     let update = cli.update;
+    // The machine-readable formats replace all human-readable output
+    let quiet = cli.quiet || cli.format != OutputFormat::Text;
+    let color_theme = cli.color_theme;
     let progress_handle = thread::spawn(move || {
-        let mut output = Output::new(use_color);
+        let mut output = Output::new(use_color).quiet(quiet).theme(color_theme);
         for event in progress_rx {
             output.print_progress(&event, verbose_level, update);
         }
         output.finish_progress();
     });
 
-    let pattern_ref = pattern.as_ref();
     let stream_output = verbose_level >= 2;
-    let results: Vec<SuiteResult> = if cli.sequential || suites.len() == 1 {
+    // With --bail, the first failing suite stops the rest of the run the same
+    // way Ctrl-C does; suites that haven't started yet are left out entirely
+    let bailed = AtomicBool::new(false);
+    let run = |suite: &Suite, tx: &mpsc::Sender<ProgressEvent>| {
+        if bailed.load(Ordering::SeqCst) {
+            return None;
+        }
+        let result = run_suite(
+            suite,
+            filter,
+            &RunOptions {
+                default_timeout: cli.timeout,
+                shuffle_seed,
+                repeat: cli.repeat,
+                stream_output,
+            },
+            Some(tx),
+        );
+        if cli.bail && !result.passed() && !bailed.swap(true, Ordering::SeqCst) {
+            set_interrupted();
+        }
+        Some(result)
+    };
+    let run_parallel = || {
         suites
-            .iter()
-            .map(|suite| run_suite(suite, pattern_ref, Some(&progress_tx), stream_output))
+            .par_iter()
+            .filter_map(|suite| run(suite, &progress_tx.clone()))
             .collect()
-    } else {
+    };
+    let results: Vec<SuiteResult> = if cli.runs_sequentially() || suites.len() == 1 {
         suites
-            .par_iter()
-            .map(|suite| {
-                let tx = progress_tx.clone();
-                run_suite(suite, pattern_ref, Some(&tx), stream_output)
-            })
+            .iter()
+            .filter_map(|suite| run(suite, &progress_tx))
             .collect()
+    } else if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.get())
+            .build()?
+            .install(run_parallel)
+    } else {
+        run_parallel()
     };
 
     drop(progress_tx);
@@ -124,19 +235,102 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
+    if let Some(path) = &cli.timings_json {
+        write_timings_json(&results, path)?;
+    }
+
     let elapsed = start_time.elapsed();
-    output.print_results(&results, elapsed, cli.update);
+    print_results(cli, output, &results, elapsed)?;
+
+    if let Some(seed) = shuffle_seed.filter(|_| !cli.quiet || !results.iter().all(|r| r.passed())) {
+        eprintln!("Shuffled with --seed {}", seed);
+    }
 
-    let all_passed = results.iter().all(|r| r.passed());
+    if bailed.load(Ordering::SeqCst) {
+        let not_executed = count_not_executed(&suites, filter, &results);
+        eprintln!("{}", bail_message(not_executed));
+        return Ok(1);
+    }
 
-    std::process::exit(if all_passed { 0 } else { 1 });
+    if is_interrupted() {
+        let not_executed = count_not_executed(&suites, filter, &results);
+        eprintln!("{}", interrupted_message(not_executed));
+        return Ok(INTERRUPTED_EXIT_CODE);
+    }
+
+    let all_passed = results.iter().all(|r| r.passed())
+        || cli
+            .fail_under
+            .is_some_and(|threshold| pass_rate(&results) >= threshold);
+
+    Ok(if all_passed { 0 } else { 1 })
 }
 
-fn discover_all(paths: &[std::path::PathBuf]) -> anyhow::Result<Vec<Suite>> {
+/// Run the suites under `cli.paths`, then run them again whenever a `.txt`
+/// file under them is written, created or removed, until Ctrl-C
+fn watch(cli: &Cli, output: &mut Output, filter: &TestFilter) -> anyhow::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for path in &cli.paths {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+    // Reading the files while running must not trigger another run
+    let is_change = |event: &notify::Event| {
+        matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) && !matches!(
+            event.kind,
+            EventKind::Modify(notify::event::ModifyKind::Metadata(_))
+        ) && event
+            .paths
+            .iter()
+            .any(|p| p.extension().is_some_and(|ext| ext == "txt"))
+    };
+
+    loop {
+        let suites = discover_all(&cli.paths, cli.include_hidden)?;
+        if suites.is_empty() {
+            eprintln!("No test suites found");
+        } else {
+            run_suites(cli, output, suites, filter)?;
+        }
+        if is_interrupted() {
+            return Ok(());
+        }
+        println!("\nWatching for changes (Ctrl-C to quit)...");
+
+        // Wait for a test file to change, checking for Ctrl-C in between
+        loop {
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Ok(event)) if is_change(&event) => break,
+                Ok(Ok(_)) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => eprintln!("Watch error: {}", e),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            if is_interrupted() {
+                return Ok(());
+            }
+        }
+        // Editors often save in several steps; let them finish, then drop
+        // the events they caused
+        thread::sleep(Duration::from_millis(200));
+        while rx.try_recv().is_ok() {}
+
+        if !cli.no_color && atty::is(atty::Stream::Stdout) {
+            // Clear the screen and move the cursor to the top left
+            print!("\x1b[2J\x1b[H");
+        } else {
+            println!("\n{}\n", "=".repeat(40));
+        }
+    }
+}
+
+fn discover_all(paths: &[std::path::PathBuf], include_hidden: bool) -> anyhow::Result<Vec<Suite>> {
     let mut all_suites = Vec::new();
     for path in paths {
         let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
-        let suites = discover_suites(&canonical)?;
+        let suites = discover_suites(&canonical, include_hidden)?;
         all_suites.extend(suites);
     }
     // Deduplicate suites by path (in case overlapping dirs are given)
@@ -155,9 +349,12 @@ fn run_stdin_mode(cli: &Cli, output: &mut Output) -> anyhow::Result<()> {
     let (progress_tx, progress_rx) = mpsc::channel::<ProgressEvent>();
     let verbose_level = cli.verbose;
     let update = cli.update;
+    // The machine-readable formats replace all human-readable output
+    let quiet = cli.quiet || cli.format != OutputFormat::Text;
+    let color_theme = cli.color_theme;
 
     let progress_handle = thread::spawn(move || {
-        let mut output = Output::new(use_color);
+        let mut output = Output::new(use_color).quiet(quiet).theme(color_theme);
         for event in progress_rx {
             output.print_progress(&event, verbose_level, update);
         }
@@ -165,55 +362,128 @@ fn run_stdin_mode(cli: &Cli, output: &mut Output) -> anyhow::Result<()> {
     });
 
     let stream_output = verbose_level >= 2;
-    let result = run_from_stdin(&content, Some(&progress_tx), stream_output);
+    let result = run_from_stdin(
+        &content,
+        &RunOptions {
+            default_timeout: cli.timeout,
+            stream_output,
+            ..RunOptions::default()
+        },
+        Some(&progress_tx),
+    );
 
     drop(progress_tx);
     progress_handle.join().unwrap();
 
     let elapsed = start_time.elapsed();
     let results = vec![result];
-    output.print_results(&results, elapsed, cli.update);
+    if let Some(path) = &cli.timings_json {
+        write_timings_json(&results, path)?;
+    }
+    print_results(cli, output, &results, elapsed)?;
+
+    if is_interrupted() {
+        let total = cctr::parse_content(&content, std::path::Path::new("<stdin>"))
+            .map_or(0, |corpus| corpus.tests.len());
+        let not_executed = total.saturating_sub(results[0].total_tests());
+        eprintln!("{}", interrupted_message(not_executed));
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
 
-    let all_passed = results.iter().all(|r| r.passed());
+    let all_passed = results.iter().all(|r| r.passed())
+        || cli
+            .fail_under
+            .is_some_and(|threshold| pass_rate(&results) >= threshold);
 
     std::process::exit(if all_passed { 0 } else { 1 });
 }
 
+/// Print the results in the `--format` chosen on the command line
+fn print_results(
+    cli: &Cli,
+    output: &mut Output,
+    results: &[SuiteResult],
+    elapsed: Duration,
+) -> anyhow::Result<()> {
+    match cli.format {
+        OutputFormat::Text => {
+            output.print_results(results, elapsed, cli.update);
+            output.print_slowest(results, cli.slowest);
+        }
+        OutputFormat::Json => {
+            let report = results_json(results, elapsed);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Junit => print!("{}", junit::render(results, elapsed)),
+        OutputFormat::Tap => print!("{}", tap::render(results)),
+    }
+    Ok(())
+}
+
 fn list_tests(
     suites: &[Suite],
-    pattern: Option<&Regex>,
+    filter: &TestFilter,
+    count_only: bool,
     output: &mut Output,
 ) -> anyhow::Result<()> {
+    let unfiltered = filter.pattern.is_none()
+        && filter.exclude.is_empty()
+        && filter.tags.is_empty()
+        && filter.skip_tags.is_empty();
     let mut suite_tests = Vec::new();
     for suite in suites {
         let mut all_tests = Vec::new();
         for file in suite.corpus_files() {
             let corpus = parse_file(&file)?;
-
-            let file_matches = pattern.is_none_or(|pat| {
-                file.file_stem()
-                    .and_then(|s| s.to_str())
-                    .is_some_and(|name| pat.is_match(name))
-            });
-
-            let filtered: Vec<_> = if let Some(pat) = pattern {
+            all_tests.extend(
                 corpus
                     .tests
                     .into_iter()
-                    .filter(|t| file_matches || pat.is_match(&t.name))
-                    .collect()
-            } else {
-                corpus.tests
-            };
-
-            all_tests.extend(filtered);
+                    .filter(|t| filter.selects(&file, t)),
+            );
         }
 
-        if !all_tests.is_empty() || pattern.is_none() {
+        if !all_tests.is_empty() || unfiltered {
             suite_tests.push((suite, all_tests));
         }
     }
 
-    output.print_list(&suite_tests);
+    if count_only {
+        output.print_counts(&suite_tests);
+    } else {
+        output.print_list(&suite_tests);
+    }
     Ok(())
 }
+
+/// Lowercase `--tags`/`--skip-tags` values to match the parsed `%tags`
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    tags.iter()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Parse every test file up front, exiting before anything runs if one fails
+fn check_parse_errors(suites: &[Suite]) {
+    for suite in suites {
+        for file in suite.corpus_files() {
+            if let Err(e) = parse_file(&file) {
+                eprintln!("{}: {}", file.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn lint_suites(suites: &[Suite], output: &mut Output) -> anyhow::Result<bool> {
+    let mut warnings = Vec::new();
+    for suite in suites {
+        for file in suite.corpus_files() {
+            let corpus = parse_file(&file)?;
+            warnings.extend(lint_corpus(&corpus));
+        }
+    }
+    output.print_lint(&warnings);
+    Ok(warnings.is_empty())
+}
@@ -1,17 +1,20 @@
 //! Pattern matching for test output with variable extraction and constraints.
 
-use crate::{VarType, VariableDecl};
-use cctr_expr::{eval_bool, Value};
+use crate::{MatchMode, NumericTolerance, VarType, VariableDecl};
+use cctr_expr::{eval_bool, json_to_value, Value};
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum MatchError {
     #[error("failed to build pattern regex: {0}")]
     RegexBuild(#[from] regex::Error),
-    #[error("duplicate variable '{{{{ {0} }}}}' in pattern - each variable can only appear once")]
-    DuplicateVariable(String),
+    #[error("failed to build pattern regex: {0}")]
+    PatternBuild(#[from] fancy_regex::Error),
+    #[error("failed to match pattern regex: {0}")]
+    RegexMatch(fancy_regex::Error),
     #[error("constraint '{constraint}' failed: {error}")]
     ConstraintFailed { constraint: String, error: String },
     #[error("{}", format_constraint_error(.constraint, .bindings))]
@@ -21,6 +24,18 @@ pub enum MatchError {
     },
     #[error("failed to parse JSON for variable '{name}': {error}")]
     JsonParse { name: String, error: String },
+    #[error("{side} output is not valid JSON: {error}")]
+    InvalidJson { side: &'static str, error: String },
+    #[error("invalid date for variable '{name}': {error}")]
+    InvalidDate { name: String, error: String },
+    #[error("invalid uuid for variable '{name}': {error}")]
+    InvalidUuid { name: String, error: String },
+    #[error("invalid {type_name} value for variable '{name}': {error}")]
+    InvalidCustomValue {
+        name: String,
+        type_name: String,
+        error: String,
+    },
 }
 
 fn format_constraint_error(constraint: &str, bindings: &[(String, String)]) -> String {
@@ -36,7 +51,7 @@ fn format_constraint_error(constraint: &str, bindings: &[(String, String)]) -> S
     msg
 }
 
-fn format_value(value: &Value) -> String {
+pub fn format_value(value: &Value) -> String {
     match value {
         Value::Number(n) => {
             if n.fract() == 0.0 && n.abs() < 1e15 {
@@ -73,18 +88,14 @@ fn duck_type_value(text: &str) -> Value {
     // Try JSON object
     if trimmed.starts_with('{') {
         if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-            if let Ok(v) = json_to_value(&json) {
-                return v;
-            }
+            return json_to_value(&json);
         }
     }
 
     // Try JSON array
     if trimmed.starts_with('[') {
         if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-            if let Ok(v) = json_to_value(&json) {
-                return v;
-            }
+            return json_to_value(&json);
         }
     }
 
@@ -120,15 +131,92 @@ fn duck_type_value(text: &str) -> Value {
     Value::String(text.to_string())
 }
 
+/// An ISO-8601 date, optionally followed by a time with optional fractional
+/// seconds and a `Z` or `+HH:MM` offset. `T` or a space separates the two.
+const DATE_PATTERN: &str = r"[0-9]{4}-[0-9]{2}-[0-9]{2}(?:[T ][0-9]{2}:[0-9]{2}(?::[0-9]{2}(?:\.[0-9]+)?)?(?:Z|[+-][0-9]{2}:?[0-9]{2})?)?";
+
+/// `DATE_PATTERN` with a group for each field, for `parse_date`
+static DATE_FIELDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^([0-9]{4})-([0-9]{2})-([0-9]{2})(?:[T ]([0-9]{2}):([0-9]{2})(?::([0-9]{2})(\.[0-9]+)?)?(?:Z|([+-])([0-9]{2}):?([0-9]{2}))?)?$",
+    )
+    .unwrap()
+});
+
+/// Parse a date matched by `DATE_PATTERN` into seconds since the Unix epoch.
+/// A time without an offset is taken to be UTC.
+fn parse_date(text: &str) -> Result<f64, String> {
+    let caps = DATE_FIELDS
+        .captures(text)
+        .ok_or_else(|| format!("'{}' is not an ISO-8601 date", text))?;
+    let field = |i: usize| -> i64 { caps.get(i).map_or(0, |m| m.as_str().parse().unwrap()) };
+    let (year, month, day) = (field(1), field(2), field(3));
+    let (hour, minute, second) = (field(4), field(5), field(6));
+    let (offset_hours, offset_minutes) = (field(9), field(10));
+
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(format!("'{}' is not a valid calendar date", text));
+    }
+    if hour > 23 || minute > 59 || second > 59 || offset_hours > 23 || offset_minutes > 59 {
+        return Err(format!("'{}' is not a valid time", text));
+    }
+
+    let fraction: f64 = caps.get(7).map_or(0.0, |m| m.as_str().parse().unwrap());
+    let offset = (offset_hours * 3600 + offset_minutes * 60)
+        * if caps.get(8).is_some_and(|m| m.as_str() == "-") {
+            -1
+        } else {
+            1
+        };
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Ok(seconds as f64 + fraction)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Anything shaped like a UUID. Non-hex digits are captured so that
+/// `extract_values` can report them rather than the pattern silently failing.
+const UUID_PATTERN: &str =
+    r"[0-9A-Za-z]{8}-[0-9A-Za-z]{4}-[0-9A-Za-z]{4}-[0-9A-Za-z]{4}-[0-9A-Za-z]{12}";
+
 pub struct MatchResult {
     pub matched: bool,
     pub captured: HashMap<String, Value>,
 }
 
+type ParseFn = Box<dyn Fn(&str) -> Result<Value, String>>;
+
+/// A placeholder type registered with [`Matcher::register_type`]
+struct CustomType {
+    capture_regex: String,
+    parse: ParseFn,
+}
+
 pub struct Matcher<'a> {
     variables: &'a [VariableDecl],
     constraints: &'a [String],
     env_vars: &'a [(String, String)],
+    ascii: bool,
+    anchored: bool,
+    custom_types: HashMap<String, CustomType>,
 }
 
 impl<'a> Matcher<'a> {
@@ -141,6 +229,63 @@ impl<'a> Matcher<'a> {
             variables,
             constraints,
             env_vars,
+            ascii: false,
+            anchored: true,
+            custom_types: HashMap::new(),
+        }
+    }
+
+    /// Use ASCII-only `\d`, `\w` and `\b` in the pattern regex (`%ascii`).
+    /// By default these follow Unicode, so e.g. `\d` also matches `٣`.
+    pub fn ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// Whether the pattern must match the whole output (the default) or may
+    /// match anywhere in it (`%contains`)
+    pub fn anchored(mut self, anchored: bool) -> Self {
+        self.anchored = anchored;
+        self
+    }
+
+    /// Register a placeholder type, used as `{{ x: name }}`. The captured text
+    /// must match `capture_regex` and is converted to a value by `parse`;
+    /// an `Err` from `parse` fails the match with that message. Registered
+    /// types take precedence over built-in types of the same name.
+    pub fn register_type(
+        mut self,
+        name: &str,
+        capture_regex: &str,
+        parse: impl Fn(&str) -> Result<Value, String> + 'static,
+    ) -> Self {
+        self.custom_types.insert(
+            name.to_lowercase(),
+            CustomType {
+                capture_regex: capture_regex.to_string(),
+                parse: Box::new(parse),
+            },
+        );
+        self
+    }
+
+    fn custom_type(&self, var: &VariableDecl) -> Option<&CustomType> {
+        let var_type = var.var_type.as_ref()?;
+        self.custom_types.get(var_type.name())
+    }
+
+    /// A character class or assertion, restricted to ASCII under `%ascii`.
+    /// Spelled out, since fancy-regex doesn't accept `(?-u:...)`.
+    fn class(&self, class: &str) -> String {
+        if !self.ascii {
+            return class.to_string();
+        }
+        const WORD: &str = "[0-9A-Za-z_]";
+        match class {
+            r"\d" => "[0-9]".to_string(),
+            r"\w" => WORD.to_string(),
+            r"\b" => format!("(?:(?<={w})(?!{w})|(?<!{w})(?={w}))", w = WORD),
+            _ => unreachable!("no ASCII form for {}", class),
         }
     }
 
@@ -153,26 +298,47 @@ impl<'a> Matcher<'a> {
         let clean_pattern = self.strip_type_annotations(pattern);
         let regex = self.build_regex(&clean_pattern)?;
 
-        let Some(caps) = regex.captures(actual) else {
+        let Some(caps) = regex.captures(actual).map_err(MatchError::RegexMatch)? else {
             return Ok(MatchResult {
                 matched: false,
                 captured: HashMap::new(),
             });
         };
 
+        let captured = self.extract_values(&caps)?;
+        self.check_constraints(actual, prior_vars, &captured)?;
+
+        Ok(MatchResult {
+            matched: true,
+            captured,
+        })
+    }
+
+    /// Evaluate the constraints against prior and captured variables, plus
+    /// the implicit `output` variable holding the full actual output.
+    pub fn check_constraints(
+        &self,
+        actual: &str,
+        prior_vars: &HashMap<String, Value>,
+        captured: &HashMap<String, Value>,
+    ) -> Result<(), MatchError> {
         // Set CCTR_* env vars so env() function can access them
         for (key, value) in self.env_vars {
             std::env::set_var(key, value);
         }
 
-        let captured = self.extract_values(&caps)?;
-
         // Merge prior variables with newly captured ones (new values override)
         let mut all_values = prior_vars.clone();
         all_values.extend(captured.clone());
 
         let bindings = self.format_all_bindings(&all_values);
 
+        // The full output is available to constraints as `output` unless a
+        // variable of that name was captured by this test
+        if !captured.contains_key("output") {
+            all_values.insert("output".to_string(), Value::String(actual.to_string()));
+        }
+
         for constraint in self.constraints {
             match eval_bool(constraint, &all_values) {
                 Ok(true) => {}
@@ -191,15 +357,16 @@ impl<'a> Matcher<'a> {
             }
         }
 
-        Ok(MatchResult {
-            matched: true,
-            captured,
-        })
+        Ok(())
     }
 
     /// Strip type annotations from placeholders: {{ x: number }} -> {{ x }}
     fn strip_type_annotations(&self, pattern: &str) -> String {
-        let re = Regex::new(r"\{\{\s*(\w+)\s*:\s*[^}]+\}\}").unwrap();
+        let re = Regex::new(&format!(
+            r"(?s)\{{\{{\s*({}+)\s*:\s*.+?\}}\}}",
+            self.class(r"\w")
+        ))
+        .unwrap();
         re.replace_all(pattern, "{{ $1 }}").to_string()
     }
 
@@ -212,17 +379,14 @@ impl<'a> Matcher<'a> {
         bindings
     }
 
-    fn build_regex(&self, pattern: &str) -> Result<Regex, MatchError> {
-        let var_pattern = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
-
-        // Check for duplicate variable names
+    /// Build the regex for a pattern. A variable that appears more than once
+    /// is captured at its first occurrence and must repeat the same text at
+    /// every later one, via a backreference.
+    fn build_regex(&self, pattern: &str) -> Result<fancy_regex::Regex, MatchError> {
+        let var_pattern =
+            Regex::new(&format!(r"\{{\{{\s*({}+)\s*\}}\}}", self.class(r"\w"))).unwrap();
+        let number_pattern = format!(r"-?{d}+(?:\.{d}+)?", d = self.class(r"\d"));
         let mut seen_vars = std::collections::HashSet::new();
-        for cap in var_pattern.captures_iter(pattern) {
-            let var_name = cap.get(1).unwrap().as_str();
-            if self.variables.iter().any(|v| v.name == var_name) && !seen_vars.insert(var_name) {
-                return Err(MatchError::DuplicateVariable(var_name.to_string()));
-            }
-        }
 
         let mut regex_str = String::new();
         let mut last_end = 0;
@@ -234,20 +398,50 @@ impl<'a> Matcher<'a> {
             let literal = &pattern[last_end..full_match.start()];
             regex_str.push_str(&regex::escape(literal));
 
-            if let Some(var) = self.variables.iter().find(|v| v.name == var_name) {
+            if self.variables.iter().any(|v| v.name == var_name) && !seen_vars.insert(var_name) {
+                regex_str.push_str(&format!(r"\k<{}>", var_name));
+            } else if let Some(var) = self.variables.iter().find(|v| v.name == var_name) {
                 // For JSON types, we use a greedy approach that captures balanced brackets/braces.
                 // The actual JSON validation happens in extract_values via serde_json.
-                let capture_pattern = match var.var_type {
-                    Some(VarType::Number) => r"-?\d+(?:\.\d+)?",
-                    Some(VarType::String) => r".*?",
-                    Some(VarType::JsonString) => r#""(?:[^"\\]|\\.)*""#,
-                    Some(VarType::JsonBool) => r"true|false",
-                    Some(VarType::JsonArray) => r"\[[\s\S]*\]",
-                    Some(VarType::JsonObject) => r"\{[\s\S]*\}",
-                    // Duck-typed: match anything (greedy but stops at next literal)
-                    None => r".*?",
+                let custom_type = self.custom_type(var);
+                let capture_pattern = if let Some(custom_regex) = &var.custom_regex {
+                    custom_regex.as_str()
+                } else if let Some(custom_type) = custom_type {
+                    custom_type.capture_regex.as_str()
+                } else {
+                    match var.var_type {
+                        Some(VarType::Number) => number_pattern.as_str(),
+                        Some(VarType::String) => r".*?",
+                        Some(VarType::JsonString) => r#""(?:[^"\\]|\\.)*""#,
+                        Some(VarType::JsonBool) => r"true|false",
+                        Some(VarType::JsonArray) => r"\[[\s\S]*\]",
+                        Some(VarType::JsonObject) => r"\{[\s\S]*\}",
+                        Some(VarType::Date) => DATE_PATTERN,
+                        Some(VarType::Uuid) => UUID_PATTERN,
+                        // Duck-typed: match anything (greedy but stops at next literal)
+                        Some(VarType::Custom(_)) | None => r".*?",
+                    }
                 };
-                regex_str.push_str(&format!("(?P<{}>{})", var_name, capture_pattern));
+                regex_str.push_str(&format!("(?P<{}>(?:{}))", var_name, capture_pattern));
+
+                // Don't let a number stop partway through an alphanumeric run
+                // (e.g. `12` out of `12abc`) unless the pattern itself continues
+                // with a word character, as in `{{ t: number }}s`.
+                let continues_with_word_char =
+                    pattern[full_match.end()..].chars().next().is_some_and(|c| {
+                        c == '_'
+                            || if self.ascii {
+                                c.is_ascii_alphanumeric()
+                            } else {
+                                c.is_alphanumeric()
+                            }
+                    });
+                if custom_type.is_none()
+                    && var.var_type == Some(VarType::Number)
+                    && !continues_with_word_char
+                {
+                    regex_str.push_str(&self.class(r"\b"));
+                }
             } else {
                 regex_str.push_str(&regex::escape(
                     &pattern[full_match.start()..full_match.end()],
@@ -258,67 +452,93 @@ impl<'a> Matcher<'a> {
         }
 
         regex_str.push_str(&regex::escape(&pattern[last_end..]));
-        let regex_str = format!("(?s)^{}$", regex_str);
+        let regex_str = if self.anchored {
+            format!("(?s)^{}$", regex_str)
+        } else {
+            format!("(?s){}", regex_str)
+        };
 
-        Ok(Regex::new(&regex_str)?)
+        Ok(fancy_regex::Regex::new(&regex_str)?)
     }
 
-    fn extract_values(&self, caps: &regex::Captures) -> Result<HashMap<String, Value>, MatchError> {
+    fn extract_values(
+        &self,
+        caps: &fancy_regex::Captures<'_, str>,
+    ) -> Result<HashMap<String, Value>, MatchError> {
         let mut values = HashMap::new();
 
         for var in self.variables {
             if let Some(m) = caps.name(&var.name) {
                 let text = m.as_str();
-                let value = match var.var_type {
-                    Some(VarType::Number) => {
-                        let n: f64 = text.parse().unwrap_or(0.0);
-                        Value::Number(n)
-                    }
-                    Some(VarType::String) => Value::String(text.to_string()),
-                    Some(VarType::JsonString) => {
-                        let json: serde_json::Value =
-                            serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
-                                name: var.name.clone(),
-                                error: e.to_string(),
-                            })?;
-                        match json {
-                            serde_json::Value::String(s) => Value::String(s),
-                            _ => {
-                                return Err(MatchError::JsonParse {
+                let value = if let Some(custom_type) = self.custom_type(var) {
+                    (custom_type.parse)(text).map_err(|error| MatchError::InvalidCustomValue {
+                        name: var.name.clone(),
+                        type_name: var.var_type.as_ref().map_or("", |t| t.name()).to_string(),
+                        error,
+                    })?
+                } else {
+                    match var.var_type {
+                        Some(VarType::Number) => {
+                            let n: f64 = text.parse().unwrap_or(0.0);
+                            Value::Number(n)
+                        }
+                        Some(VarType::String) => Value::String(text.to_string()),
+                        Some(VarType::JsonString) => {
+                            let json: serde_json::Value =
+                                serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
                                     name: var.name.clone(),
-                                    error: "expected JSON string".to_string(),
-                                })
+                                    error: e.to_string(),
+                                })?;
+                            match json {
+                                serde_json::Value::String(s) => Value::String(s),
+                                _ => {
+                                    return Err(MatchError::JsonParse {
+                                        name: var.name.clone(),
+                                        error: "expected JSON string".to_string(),
+                                    })
+                                }
                             }
                         }
+                        Some(VarType::JsonBool) => {
+                            let b = text == "true";
+                            Value::Bool(b)
+                        }
+                        Some(VarType::JsonArray) => {
+                            let json: serde_json::Value =
+                                serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
+                                    name: var.name.clone(),
+                                    error: e.to_string(),
+                                })?;
+                            json_to_value(&json)
+                        }
+                        Some(VarType::JsonObject) => {
+                            let json: serde_json::Value =
+                                serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
+                                    name: var.name.clone(),
+                                    error: e.to_string(),
+                                })?;
+                            json_to_value(&json)
+                        }
+                        Some(VarType::Date) => {
+                            Value::Number(parse_date(text).map_err(|error| {
+                                MatchError::InvalidDate {
+                                    name: var.name.clone(),
+                                    error,
+                                }
+                            })?)
+                        }
+                        Some(VarType::Uuid) => {
+                            if !text.chars().all(|c| c == '-' || c.is_ascii_hexdigit()) {
+                                return Err(MatchError::InvalidUuid {
+                                    name: var.name.clone(),
+                                    error: format!("'{}' contains non-hex digits", text),
+                                });
+                            }
+                            Value::String(text.to_string())
+                        }
+                        // Duck-typed: infer from value
+                        Some(VarType::Custom(_)) | None => duck_type_value(text),
                     }
-                    Some(VarType::JsonBool) => {
-                        let b = text == "true";
-                        Value::Bool(b)
-                    }
-                    Some(VarType::JsonArray) => {
-                        let json: serde_json::Value =
-                            serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
-                                name: var.name.clone(),
-                                error: e.to_string(),
-                            })?;
-                        json_to_value(&json).map_err(|e| MatchError::JsonParse {
-                            name: var.name.clone(),
-                            error: e,
-                        })?
-                    }
-                    Some(VarType::JsonObject) => {
-                        let json: serde_json::Value =
-                            serde_json::from_str(text).map_err(|e| MatchError::JsonParse {
-                                name: var.name.clone(),
-                                error: e.to_string(),
-                            })?;
-                        json_to_value(&json).map_err(|e| MatchError::JsonParse {
-                            name: var.name.clone(),
-                            error: e,
-                        })?
-                    }
-                    // Duck-typed: infer from value
-                    None => duck_type_value(text),
                 };
                 values.insert(var.name.clone(), value);
             }
@@ -328,23 +548,98 @@ impl<'a> Matcher<'a> {
     }
 }
 
-fn json_to_value(json: &serde_json::Value) -> Result<Value, String> {
-    match json {
-        serde_json::Value::Null => Ok(Value::Null),
-        serde_json::Value::Bool(b) => Ok(Value::Bool(*b)),
-        serde_json::Value::Number(n) => Ok(Value::Number(n.as_f64().unwrap_or(0.0))),
-        serde_json::Value::String(s) => Ok(Value::String(s.clone())),
-        serde_json::Value::Array(arr) => {
-            let items: Result<Vec<_>, _> = arr.iter().map(json_to_value).collect();
-            Ok(Value::Array(items?))
+static NUMBER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"-?\d+(?:\.\d+)?(?:[eE][-+]?\d+)?").unwrap());
+
+/// Compare expected and actual output, allowing numbers to differ within the
+/// given tolerance. All non-numeric text must match exactly. A pair of numbers
+/// matches if it is within either the absolute or the relative tolerance.
+pub fn numbers_match_within(expected: &str, actual: &str, tolerance: NumericTolerance) -> bool {
+    let number = &*NUMBER_PATTERN;
+
+    let expected_numbers: Vec<_> = number.find_iter(expected).collect();
+    let actual_numbers: Vec<_> = number.find_iter(actual).collect();
+    if expected_numbers.len() != actual_numbers.len()
+        || number.split(expected).ne(number.split(actual))
+    {
+        return false;
+    }
+
+    expected_numbers.iter().zip(&actual_numbers).all(|(e, a)| {
+        let (Ok(e), Ok(a)) = (e.as_str().parse::<f64>(), a.as_str().parse::<f64>()) else {
+            return false;
+        };
+        let diff = (e - a).abs();
+        diff == 0.0
+            || tolerance.absolute.is_some_and(|tol| diff <= tol)
+            || tolerance
+                .relative
+                .is_some_and(|ratio| diff <= ratio * e.abs().max(a.abs()))
+    })
+}
+
+/// Compare expected and actual output as JSON for the `%match json` modes.
+/// Panics for the other modes, which the runner checks elsewhere.
+pub fn json_matches(mode: MatchMode, expected: &str, actual: &str) -> Result<bool, MatchError> {
+    let expected: serde_json::Value =
+        serde_json::from_str(expected).map_err(|e| MatchError::InvalidJson {
+            side: "expected",
+            error: e.to_string(),
+        })?;
+    let actual: serde_json::Value =
+        serde_json::from_str(actual).map_err(|e| MatchError::InvalidJson {
+            side: "actual",
+            error: e.to_string(),
+        })?;
+
+    Ok(match mode {
+        MatchMode::JsonSubset => json_subset(&expected, &actual),
+        MatchMode::Json => json_subset(&expected, &actual) && json_subset(&actual, &expected),
+        MatchMode::Pattern | MatchMode::Regex => {
+            unreachable!("json_matches called for non-JSON mode {:?}", mode)
         }
-        serde_json::Value::Object(obj) => {
-            let mut map = HashMap::new();
-            for (k, v) in obj {
-                map.insert(k.clone(), json_to_value(v)?);
-            }
-            Ok(Value::Object(map))
+    })
+}
+
+/// Match the whole actual output against the `%match regex` pattern. On a
+/// match, named groups become duck-typed variables and `groups` holds every
+/// numbered group as a string, with the full match at index 0.
+pub fn regex_captures(
+    pattern: &str,
+    actual: &str,
+) -> Result<Option<HashMap<String, Value>>, MatchError> {
+    let regex = Regex::new(&format!(r"\A(?:{})\z", pattern))?;
+    let Some(caps) = regex.captures(actual) else {
+        return Ok(None);
+    };
+
+    let mut captured = HashMap::new();
+    let groups = caps
+        .iter()
+        .map(|m| Value::String(m.map_or("", |m| m.as_str()).to_string()))
+        .collect();
+    captured.insert("groups".to_string(), Value::Array(groups));
+    for name in regex.capture_names().flatten() {
+        if let Some(m) = caps.name(name) {
+            captured.insert(name.to_string(), duck_type_value(m.as_str()));
+        }
+    }
+    Ok(Some(captured))
+}
+
+/// Check that `expected` is contained in `actual`: object keys in `expected`
+/// must be present with matching values, and arrays must match as a prefix.
+fn json_subset(expected: &serde_json::Value, actual: &serde_json::Value) -> bool {
+    use serde_json::Value as J;
+    match (expected, actual) {
+        (J::Object(e), J::Object(a)) => e
+            .iter()
+            .all(|(k, ev)| a.get(k).is_some_and(|av| json_subset(ev, av))),
+        (J::Array(e), J::Array(a)) => {
+            e.len() <= a.len() && e.iter().zip(a).all(|(ev, av)| json_subset(ev, av))
         }
+        (J::Number(e), J::Number(a)) => e.as_f64() == a.as_f64(),
+        _ => expected == actual,
     }
 }
 
@@ -361,8 +656,11 @@ mod tests {
                 "json bool" => VarType::JsonBool,
                 "json array" => VarType::JsonArray,
                 "json object" => VarType::JsonObject,
+                "date" => VarType::Date,
+                "uuid" => VarType::Uuid,
                 _ => VarType::String,
             }),
+            custom_regex: None,
         }
     }
 
@@ -398,6 +696,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_modulo_constraint() {
+        let vars = vec![make_var("n", Some("number"))];
+        let even = vec!["n % 2 == 0".to_string()];
+        let matcher = Matcher::new(&vars, &even, &[]);
+        assert!(
+            matcher
+                .matches("n={{ n }}", "n=42", &no_prior())
+                .unwrap()
+                .matched
+        );
+        assert!(matcher.matches("n={{ n }}", "n=7", &no_prior()).is_err());
+    }
+
     #[test]
     fn test_constraint_fail() {
         let vars = vec![make_var("n", Some("number"))];
@@ -607,6 +919,221 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_repeated_variable_must_agree() {
+        let vars = vec![make_var("id", None)];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        let pattern = "created {{ id }}\nfetched {{ id }}";
+
+        let result = matcher
+            .matches(pattern, "created abc\nfetched abc", &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("id"),
+            Some(&Value::String("abc".to_string()))
+        );
+
+        assert!(
+            !matcher
+                .matches(pattern, "created abc\nfetched xyz", &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_repeated_variable_backtracks_to_agreeing_split() {
+        let vars = vec![make_var("a", None)];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        let result = matcher
+            .matches("{{ a }} {{ a }}", "x y x y", &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("a"),
+            Some(&Value::String("x y".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_anchored_requires_whole_output() {
+        let vars = vec![make_var("n", Some("number"))];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        let output = "starting up\nready in 3 ms\n";
+
+        assert!(
+            !matcher
+                .matches("ready in {{ n }} ms", output, &no_prior())
+                .unwrap()
+                .matched
+        );
+        assert!(
+            matcher
+                .matches("starting up\nready in {{ n }} ms\n", output, &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_unanchored_matches_substring() {
+        let vars = vec![make_var("n", Some("number")), make_var("msg", None)];
+        let matcher = Matcher::new(&vars, &[], &[]).anchored(false);
+        let output = "banner v1.2\n---\nready in 3 ms\nlog: first\nsecond\nend\n";
+
+        let result = matcher
+            .matches("ready in {{ n }} ms", output, &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(result.captured.get("n"), Some(&Value::Number(3.0)));
+
+        // `(?s)` lets a capture span lines
+        let result = matcher
+            .matches("log: {{ msg }}\nend", output, &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("msg"),
+            Some(&Value::String("first\nsecond".to_string()))
+        );
+
+        assert!(
+            !matcher
+                .matches("ready in {{ n }} s", output, &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_date_match() {
+        let vars = vec![make_var("ts", Some("date")), make_var("msg", None)];
+        let constraints = vec!["ts >= 1700000000".to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+
+        let result = matcher
+            .matches(
+                "[{{ ts }}] {{ msg }}",
+                "[2023-11-14T22:13:20Z] started",
+                &no_prior(),
+            )
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("ts"),
+            Some(&Value::Number(1700000000.0))
+        );
+
+        let at = |text: &str| {
+            matcher
+                .matches(
+                    "[{{ ts }}] {{ msg }}",
+                    &format!("[{}] x", text),
+                    &no_prior(),
+                )
+                .unwrap()
+                .captured
+                .get("ts")
+                .cloned()
+        };
+        assert_eq!(
+            at("2023-11-14T23:13:20.5+01:00"),
+            Some(Value::Number(1700000000.5))
+        );
+        assert_eq!(
+            at("2023-11-14 17:13:20-0500"),
+            Some(Value::Number(1700000000.0))
+        );
+        assert_eq!(at("2024-02-29"), Some(Value::Number(1709164800.0)));
+
+        assert!(
+            !matcher
+                .matches("[{{ ts }}] {{ msg }}", "[yesterday] x", &no_prior())
+                .unwrap()
+                .matched
+        );
+        assert!(matches!(
+            matcher.matches("[{{ ts }}] {{ msg }}", "[2023-02-29] x", &no_prior()),
+            Err(MatchError::InvalidDate { .. })
+        ));
+    }
+
+    #[test]
+    fn test_uuid_match() {
+        let vars = vec![make_var("id", Some("uuid"))];
+        let constraints = vec!["len(id) == 36".to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+
+        let result = matcher
+            .matches(
+                "created {{ id }}",
+                "created 123e4567-E89B-12d3-a456-426614174000",
+                &no_prior(),
+            )
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("id"),
+            Some(&Value::String(
+                "123e4567-E89B-12d3-a456-426614174000".to_string()
+            ))
+        );
+
+        assert!(
+            !matcher
+                .matches("created {{ id }}", "created 123e4567", &no_prior())
+                .unwrap()
+                .matched
+        );
+        let Err(err) = matcher.matches(
+            "created {{ id }}",
+            "created 123e4567-e89b-12d3-a456-42661417zzzz",
+            &no_prior(),
+        ) else {
+            panic!("expected an invalid uuid error");
+        };
+        assert_eq!(
+            err.to_string(),
+            "invalid uuid for variable 'id': '123e4567-e89b-12d3-a456-42661417zzzz' contains non-hex digits"
+        );
+    }
+
+    #[test]
+    fn test_custom_regex_variable() {
+        let vars = vec![VariableDecl {
+            name: "ip".to_string(),
+            var_type: None,
+            custom_regex: Some(r"\d+\.\d+\.\d+\.\d+".to_string()),
+        }];
+        let constraints = vec![r#"ip startswith "10.""#.to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+        let pattern = "listening on {{ ip: /\\d+\\.\\d+\\.\\d+\\.\\d+/ }}:8080";
+
+        let result = matcher
+            .matches(pattern, "listening on 10.0.0.12:8080", &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("ip"),
+            Some(&Value::String("10.0.0.12".to_string()))
+        );
+
+        assert!(
+            !matcher
+                .matches(pattern, "listening on localhost:8080", &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_days_from_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    }
+
     #[test]
     fn test_prior_vars_available_in_constraints() {
         let vars = vec![make_var("b", Some("number"))];
@@ -632,4 +1159,323 @@ mod tests {
         assert!(result.matched);
         assert_eq!(result.captured.get("x"), Some(&Value::Number(99.0)));
     }
+
+    #[test]
+    fn test_number_capture_does_not_split_token() {
+        let vars = vec![make_var("n", Some("number"))];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        assert!(
+            !matcher
+                .matches("val: {{ n: number }}", "val: 12abc", &no_prior())
+                .unwrap()
+                .matched
+        );
+
+        let vars = vec![make_var("n", Some("number")), make_var("rest", None)];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        assert!(
+            !matcher
+                .matches("val: {{ n: number }}{{ rest }}", "val: 12abc", &no_prior())
+                .unwrap()
+                .matched
+        );
+        let result = matcher
+            .matches("val: {{ n: number }}{{ rest }}", "val: 12 abc", &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(result.captured.get("n"), Some(&Value::Number(12.0)));
+    }
+
+    #[test]
+    fn test_number_capture_followed_by_unit() {
+        let vars = vec![make_var("t", Some("number"))];
+        let matcher = Matcher::new(&vars, &[], &[]);
+        let result = matcher
+            .matches("done in {{ t: number }}s", "done in 1.25s", &no_prior())
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(result.captured.get("t"), Some(&Value::Number(1.25)));
+    }
+
+    #[test]
+    fn test_numeric_relative_tolerance() {
+        let relative = NumericTolerance {
+            absolute: None,
+            relative: Some(0.001),
+        };
+        let tiny_absolute = NumericTolerance {
+            absolute: Some(0.0001),
+            relative: None,
+        };
+        assert!(numbers_match_within("1000000", "1000001", relative));
+        assert!(!numbers_match_within("1000000", "1000001", tiny_absolute));
+        assert!(!numbers_match_within("1000000", "1002000", relative));
+        assert!(numbers_match_within(
+            "total: 1000000 bytes",
+            "total: 1000001 bytes",
+            relative
+        ));
+        assert!(!numbers_match_within(
+            "total: 1000000 bytes",
+            "total: 1000001 bits",
+            relative
+        ));
+    }
+
+    #[test]
+    fn test_numeric_tolerance_either_satisfies() {
+        let both = NumericTolerance {
+            absolute: Some(0.5),
+            relative: Some(0.001),
+        };
+        // Within absolute but not relative tolerance
+        assert!(numbers_match_within("0.1", "0.4", both));
+        // Within relative but not absolute tolerance
+        assert!(numbers_match_within("1000000", "1000100", both));
+        assert!(!numbers_match_within("10", "11", both));
+        assert!(!numbers_match_within("1 2", "1 2 3", both));
+    }
+
+    #[test]
+    fn test_ascii_mode_number_digits() {
+        let vars = vec![make_var("n", Some("number"))];
+        let unicode = Matcher::new(&vars, &[], &[]);
+        let ascii = Matcher::new(&vars, &[], &[]).ascii(true);
+
+        // Arabic-Indic digit three is a Unicode digit but not an ASCII one
+        assert!(
+            unicode
+                .matches("{{ n: number }}", "٣", &no_prior())
+                .unwrap()
+                .matched
+        );
+        assert!(
+            !ascii
+                .matches("{{ n: number }}", "٣", &no_prior())
+                .unwrap()
+                .matched
+        );
+        assert!(
+            ascii
+                .matches("{{ n: number }}", "3", &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_ascii_mode_word_boundary() {
+        let vars = vec![make_var("n", Some("number")), make_var("rest", None)];
+        let pattern = "{{ n: number }}{{ rest }}";
+
+        // `é` is a word character under Unicode, so the number can't end before it
+        let unicode = Matcher::new(&vars, &[], &[]);
+        assert!(
+            !unicode
+                .matches(pattern, "12é", &no_prior())
+                .unwrap()
+                .matched
+        );
+
+        let ascii = Matcher::new(&vars, &[], &[]).ascii(true);
+        let result = ascii.matches(pattern, "12é", &no_prior()).unwrap();
+        assert!(result.matched);
+        assert_eq!(result.captured.get("n"), Some(&Value::Number(12.0)));
+        assert_eq!(
+            result.captured.get("rest"),
+            Some(&Value::String("é".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_json_pointer_on_captured_object() {
+        let vars = vec![make_var("resp", Some("json object"))];
+        let constraints = vec![r#"json_pointer(resp, "/user/roles/1") == "admin""#.to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+        let actual = r#"{"user": {"name": "ann", "roles": ["dev", "admin"]}}"#;
+        assert!(
+            matcher
+                .matches("{{ resp }}", actual, &no_prior())
+                .unwrap()
+                .matched
+        );
+
+        let constraints = vec![r#"json_pointer(resp, "/user/roles/2") == "admin""#.to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+        let err = matcher
+            .matches("{{ resp }}", actual, &no_prior())
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("json pointer not found: /user/roles/2"));
+    }
+
+    #[test]
+    fn test_json_subset_ignores_extra_fields() {
+        let actual = r#"{"id": 7, "name": "widget", "tags": ["a", "b"], "meta": {"x": 1, "y": 2}}"#;
+        assert!(json_matches(MatchMode::JsonSubset, r#"{"name": "widget"}"#, actual).unwrap());
+        assert!(json_matches(
+            MatchMode::JsonSubset,
+            r#"{"tags": ["a"], "meta": {"y": 2.0}}"#,
+            actual
+        )
+        .unwrap());
+        assert!(!json_matches(MatchMode::JsonSubset, r#"{"name": "gadget"}"#, actual).unwrap());
+        assert!(!json_matches(MatchMode::JsonSubset, r#"{"missing": null}"#, actual).unwrap());
+        assert!(!json_matches(MatchMode::JsonSubset, r#"{"tags": ["b"]}"#, actual).unwrap());
+    }
+
+    #[test]
+    fn test_json_match_is_semantic() {
+        assert!(json_matches(
+            MatchMode::Json,
+            r#"{"a": 1, "b": [1, 2]}"#,
+            r#"{"b":[1,2],"a":1.0}"#
+        )
+        .unwrap());
+        assert!(!json_matches(MatchMode::Json, r#"{"a": 1}"#, r#"{"a": 1, "b": 2}"#).unwrap());
+        assert!(!json_matches(MatchMode::Json, "[1, 2]", "[1, 2, 3]").unwrap());
+    }
+
+    #[test]
+    fn test_regex_captures_numbered_groups() {
+        let captured = regex_captures(r"version (\d+)\.(\d+)(-rc)?", "version 1.12")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            captured.get("groups"),
+            Some(&Value::Array(vec![
+                Value::String("version 1.12".into()),
+                Value::String("1".into()),
+                Value::String("12".into()),
+                Value::String("".into()),
+            ]))
+        );
+
+        let captured = regex_captures(r"(?<user>\w+) has (?<n>\d+) items", "bob has 3 items")
+            .unwrap()
+            .unwrap();
+        assert_eq!(captured.get("user"), Some(&Value::String("bob".into())));
+        assert_eq!(captured.get("n"), Some(&Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_regex_captures_whole_output() {
+        assert!(regex_captures(r"\d+", "abc 123").unwrap().is_none());
+        assert!(regex_captures(r"(?s)abc.*", "abc\n123").unwrap().is_some());
+        assert!(regex_captures("(unclosed", "x").is_err());
+    }
+
+    #[test]
+    fn test_json_match_invalid_json() {
+        let err = json_matches(MatchMode::JsonSubset, "{}", "not json").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("actual output is not valid JSON"));
+    }
+
+    fn uuid_matcher<'a>(vars: &'a [VariableDecl], constraints: &'a [String]) -> Matcher<'a> {
+        Matcher::new(vars, constraints, &[]).register_type("uuid", r"[0-9a-fA-F-]{36}", |text| {
+            let groups: Vec<_> = text.split('-').map(str::len).collect();
+            if groups == [8, 4, 4, 4, 12] {
+                Ok(Value::String(text.to_lowercase()))
+            } else {
+                Err(format!("'{}' is not a UUID", text))
+            }
+        })
+    }
+
+    #[test]
+    fn test_custom_type_binds_value() {
+        let vars = vec![VariableDecl {
+            name: "id".to_string(),
+            var_type: Some(VarType::Custom("uuid".to_string())),
+            custom_regex: None,
+        }];
+        let constraints = vec![r#"id startswith "123e4567""#.to_string()];
+        let matcher = uuid_matcher(&vars, &constraints);
+
+        let result = matcher
+            .matches(
+                "created {{ id }} ok",
+                "created 123E4567-E89B-12D3-A456-426614174000 ok",
+                &no_prior(),
+            )
+            .unwrap();
+        assert!(result.matched);
+        assert_eq!(
+            result.captured.get("id"),
+            Some(&Value::String(
+                "123e4567-e89b-12d3-a456-426614174000".to_string()
+            ))
+        );
+
+        // Text the capture regex doesn't accept doesn't match at all
+        let result = matcher
+            .matches("created {{ id }} ok", "created 42 ok", &no_prior())
+            .unwrap();
+        assert!(!result.matched);
+    }
+
+    #[test]
+    fn test_custom_type_parse_error() {
+        let vars = vec![VariableDecl {
+            name: "id".to_string(),
+            var_type: Some(VarType::Custom("uuid".to_string())),
+            custom_regex: None,
+        }];
+        let constraints = vec![];
+        let matcher = uuid_matcher(&vars, &constraints);
+
+        let Err(err) = matcher.matches(
+            "{{ id }}",
+            "123e4567e89b-12d3-a456-4266-14174000",
+            &no_prior(),
+        ) else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(
+            err.to_string(),
+            "invalid uuid value for variable 'id': '123e4567e89b-12d3-a456-4266-14174000' is not a UUID"
+        );
+    }
+
+    #[test]
+    fn test_custom_type_overrides_builtin() {
+        let vars = vec![make_var("n", Some("number"))];
+        let constraints = vec!["n == 255".to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]).register_type(
+            "number",
+            r"0x[0-9a-f]+",
+            |text| {
+                i64::from_str_radix(&text[2..], 16)
+                    .map(|n| Value::Number(n as f64))
+                    .map_err(|e| e.to_string())
+            },
+        );
+        assert!(
+            matcher
+                .matches("{{ n }}", "0xff", &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn test_unregistered_custom_type_is_duck_typed() {
+        let vars = vec![VariableDecl {
+            name: "n".to_string(),
+            var_type: Some(VarType::Custom("semver".to_string())),
+            custom_regex: None,
+        }];
+        let constraints = vec!["n == 5".to_string()];
+        let matcher = Matcher::new(&vars, &constraints, &[]);
+        assert!(
+            matcher
+                .matches("{{ n }}", "5", &no_prior())
+                .unwrap()
+                .matched
+        );
+    }
 }
@@ -1,12 +1,114 @@
-use crate::runner::{ProgressEvent, SuiteResult, TestResult};
+pub mod junit;
+pub mod tap;
+
+use crate::runner::{pass_rate, ProgressEvent, SuiteResult, TestResult};
 use similar::{ChangeTag, TextDiff};
 use std::io::Write;
 use std::time::Duration;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+/// Named color palettes selectable with `--color-theme`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ColorTheme {
+    /// Red/green/yellow
+    #[default]
+    Default,
+    /// Blue/orange, distinguishable with red-green color blindness
+    Colorblind,
+    /// No colors; failures are shown in bold
+    Mono,
+}
+
+/// Result formats selectable with `--format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Progress and a human-readable summary
+    #[default]
+    Text,
+    /// One JSON document with every result, printed after the run
+    Json,
+    /// A JUnit XML document, printed after the run
+    Junit,
+    /// A TAP version 14 document, printed after the run
+    Tap,
+}
+
+/// Foreground color and weight for one kind of output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bold: bool,
+}
+
+impl Style {
+    const fn color(color: Color) -> Self {
+        Self {
+            fg: Some(color),
+            bold: false,
+        }
+    }
+
+    const PLAIN: Self = Self {
+        fg: None,
+        bold: false,
+    };
+
+    const BOLD: Self = Self {
+        fg: None,
+        bold: true,
+    };
+}
+
+/// Styles used for each kind of result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub pass: Style,
+    pub fail: Style,
+    pub skip: Style,
+    pub update: Style,
+    pub warning: Style,
+    /// Unchanged lines in diffs
+    pub context: Style,
+}
+
+impl Theme {
+    pub fn new(theme: ColorTheme) -> Self {
+        match theme {
+            ColorTheme::Default => Self {
+                pass: Style::color(Color::Green),
+                fail: Style::color(Color::Red),
+                skip: Style::color(Color::Yellow),
+                update: Style::color(Color::Cyan),
+                warning: Style::color(Color::Yellow),
+                context: Style::color(Color::White),
+            },
+            ColorTheme::Colorblind => Self {
+                pass: Style::color(Color::Blue),
+                fail: Style::color(Color::Ansi256(208)),
+                skip: Style::color(Color::Magenta),
+                update: Style::color(Color::Cyan),
+                warning: Style::color(Color::Magenta),
+                context: Style::color(Color::White),
+            },
+            ColorTheme::Mono => Self {
+                pass: Style::PLAIN,
+                fail: Style::BOLD,
+                skip: Style::PLAIN,
+                update: Style::PLAIN,
+                warning: Style::BOLD,
+                context: Style::PLAIN,
+            },
+        }
+    }
+}
+
 pub struct Output {
     stdout: StandardStream,
     dot_count: usize,
+    quiet: bool,
+    failures_only: bool,
+    fail_under: Option<f64>,
+    theme: Theme,
 }
 
 impl Output {
@@ -19,11 +121,40 @@ impl Output {
         Self {
             stdout: StandardStream::stdout(color_choice),
             dot_count: 0,
+            quiet: false,
+            failures_only: false,
+            fail_under: None,
+            theme: Theme::new(ColorTheme::Default),
         }
     }
 
-    fn set_color(&mut self, color: Color) {
-        let _ = self.stdout.set_color(ColorSpec::new().set_fg(Some(color)));
+    /// Omit the per-suite summary line for suites where every test passed.
+    pub fn failures_only(mut self, failures_only: bool) -> Self {
+        self.failures_only = failures_only;
+        self
+    }
+
+    /// Report the pass rate against this threshold in the summary.
+    pub fn fail_under(mut self, fail_under: Option<f64>) -> Self {
+        self.fail_under = fail_under;
+        self
+    }
+
+    pub fn theme(mut self, theme: ColorTheme) -> Self {
+        self.theme = Theme::new(theme);
+        self
+    }
+
+    /// Suppress progress and success output; only failures are reported.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    fn set_style(&mut self, style: Style) {
+        let _ = self
+            .stdout
+            .set_color(ColorSpec::new().set_fg(style.fg).set_bold(style.bold));
     }
 
     fn set_bold(&mut self) {
@@ -39,6 +170,9 @@ impl Output {
     }
 
     pub fn print_progress(&mut self, event: &ProgressEvent, verbose_level: u8, update_mode: bool) {
+        if self.quiet {
+            return;
+        }
         match event {
             ProgressEvent::TestStart { suite, file, name } => {
                 if verbose_level >= 1 {
@@ -71,12 +205,12 @@ impl Output {
             }
             ProgressEvent::Skip { suite, reason } => {
                 if verbose_level >= 1 {
-                    self.set_color(Color::Yellow);
+                    self.set_style(self.theme.skip);
                     write!(self.stdout, "S").unwrap();
                     self.reset();
                     writeln!(self.stdout, " {}: {}", suite, reason).unwrap();
                 } else {
-                    self.set_color(Color::Yellow);
+                    self.set_style(self.theme.skip);
                     write!(self.stdout, "S").unwrap();
                     self.reset();
                     let _ = self.stdout.flush();
@@ -89,16 +223,16 @@ impl Output {
 
     fn print_dot(&mut self, result: &TestResult, update_mode: bool) {
         if result.skipped {
-            self.set_color(Color::Yellow);
+            self.set_style(self.theme.skip);
             write!(self.stdout, "s").unwrap();
         } else if result.passed {
-            self.set_color(Color::Green);
+            self.set_style(self.theme.pass);
             write!(self.stdout, ".").unwrap();
         } else if update_mode {
-            self.set_color(Color::Cyan);
+            self.set_style(self.theme.update);
             write!(self.stdout, "U").unwrap();
         } else {
-            self.set_color(Color::Red);
+            self.set_style(self.theme.fail);
             write!(self.stdout, "F").unwrap();
         }
         self.reset();
@@ -117,16 +251,16 @@ impl Output {
 
     fn print_verbose_result(&mut self, result: &TestResult, update_mode: bool) {
         if result.skipped {
-            self.set_color(Color::Yellow);
+            self.set_style(self.theme.skip);
             write!(self.stdout, "⊘").unwrap();
         } else if result.passed {
-            self.set_color(Color::Green);
+            self.set_style(self.theme.pass);
             write!(self.stdout, "✓").unwrap();
         } else if update_mode {
-            self.set_color(Color::Cyan);
+            self.set_style(self.theme.update);
             write!(self.stdout, "↺").unwrap();
         } else {
-            self.set_color(Color::Red);
+            self.set_style(self.theme.fail);
             write!(self.stdout, "✗").unwrap();
         }
         self.reset();
@@ -146,7 +280,7 @@ impl Output {
         .unwrap();
 
         if result.skipped {
-            self.set_color(Color::Yellow);
+            self.set_style(self.theme.skip);
             if let Some(reason) = &result.skip_reason {
                 writeln!(self.stdout, " ({})", reason).unwrap();
             } else {
@@ -155,19 +289,33 @@ impl Output {
             self.reset();
         } else {
             self.set_dim();
+            if result.passed && result.attempts > 1 {
+                write!(self.stdout, " (passed on attempt {})", result.attempts).unwrap();
+            }
+            if let Some(repeats) = result.repeats {
+                write!(
+                    self.stdout,
+                    " (passed {}/{} runs)",
+                    repeats.passed, repeats.runs
+                )
+                .unwrap();
+            }
             writeln!(self.stdout, " {:.2}s", result.elapsed.as_secs_f64()).unwrap();
             self.reset();
         }
 
         // Print warning if present
         if let Some(warning) = &result.warning {
-            self.set_color(Color::Yellow);
+            self.set_style(self.theme.warning);
             writeln!(self.stdout, "  ⚠ Warning: {}", warning).unwrap();
             self.reset();
         }
     }
 
     pub fn finish_progress(&mut self) {
+        if self.quiet {
+            return;
+        }
         if self.dot_count > 0 {
             writeln!(self.stdout).unwrap();
         }
@@ -175,10 +323,15 @@ impl Output {
     }
 
     pub fn print_results(&mut self, results: &[SuiteResult], elapsed: Duration, update_mode: bool) {
+        if self.quiet && results.iter().all(|r| r.passed()) {
+            return;
+        }
+
         let mut total_passed = 0;
         let mut total_failed = 0;
         let mut total_skipped = 0;
         let mut failed_tests: Vec<&TestResult> = Vec::new();
+        let mut flaky_count = 0;
         let mut parse_errors: Vec<(&std::path::Path, &str)> = Vec::new();
 
         let mut sorted_results: Vec<_> = results.iter().collect();
@@ -187,7 +340,7 @@ impl Output {
         for suite_result in &sorted_results {
             if let Some(setup_error) = &suite_result.setup_error {
                 let skipped_count = suite_result.suite.test_count();
-                self.set_color(Color::Yellow);
+                self.set_style(self.theme.skip);
                 write!(self.stdout, "⊘ {}", suite_result.suite.name).unwrap();
                 self.reset();
                 writeln!(
@@ -244,7 +397,10 @@ impl Output {
             };
 
             if suite_result.passed() && !has_parse_errors {
-                self.set_color(Color::Green);
+                if self.quiet || (self.failures_only && suite_skipped == 0) {
+                    continue;
+                }
+                self.set_style(self.theme.pass);
                 write!(self.stdout, "✓ {}", suite_result.suite.name).unwrap();
                 self.reset();
                 writeln!(
@@ -258,10 +414,10 @@ impl Output {
                 .unwrap();
             } else {
                 if update_mode {
-                    self.set_color(Color::Cyan);
+                    self.set_style(self.theme.update);
                     write!(self.stdout, "↺ {}", suite_result.suite.name).unwrap();
                 } else {
-                    self.set_color(Color::Red);
+                    self.set_style(self.theme.fail);
                     write!(self.stdout, "✗ {}", suite_result.suite.name).unwrap();
                 }
                 self.reset();
@@ -288,14 +444,14 @@ impl Output {
         // Print parse errors first
         if !parse_errors.is_empty() {
             writeln!(self.stdout).unwrap();
-            self.set_color(Color::Red);
+            self.set_style(self.theme.fail);
             self.set_bold();
             writeln!(self.stdout, "Parse Errors:").unwrap();
             self.reset();
 
             for (path, error) in &parse_errors {
                 writeln!(self.stdout).unwrap();
-                self.set_color(Color::Red);
+                self.set_style(self.theme.fail);
                 write!(self.stdout, "✗").unwrap();
                 self.reset();
                 writeln!(self.stdout, " {}", path.display()).unwrap();
@@ -306,17 +462,20 @@ impl Output {
         if !failed_tests.is_empty() {
             writeln!(self.stdout).unwrap();
             if update_mode {
-                self.set_color(Color::Cyan);
+                self.set_style(self.theme.update);
                 self.set_bold();
                 writeln!(self.stdout, "Updated:").unwrap();
             } else {
-                self.set_color(Color::Red);
+                self.set_style(self.theme.fail);
                 self.set_bold();
                 writeln!(self.stdout, "Failures:").unwrap();
             }
             self.reset();
 
             for result in failed_tests {
+                if result.repeats.is_some_and(|r| r.flaky()) {
+                    flaky_count += 1;
+                }
                 writeln!(self.stdout).unwrap();
                 let file_stem = result
                     .test
@@ -326,10 +485,10 @@ impl Output {
                     .unwrap_or_default();
 
                 if update_mode {
-                    self.set_color(Color::Cyan);
+                    self.set_style(self.theme.update);
                     write!(self.stdout, "↺").unwrap();
                 } else {
-                    self.set_color(Color::Red);
+                    self.set_style(self.theme.fail);
                     write!(self.stdout, "✗").unwrap();
                 }
                 self.reset();
@@ -342,11 +501,32 @@ impl Output {
 
                 // Print warning if present
                 if let Some(warning) = &result.warning {
-                    self.set_color(Color::Yellow);
+                    self.set_style(self.theme.warning);
                     writeln!(self.stdout, "  ⚠ Warning: {}", warning).unwrap();
                     self.reset();
                 }
 
+                if result.attempts > 1 {
+                    writeln!(self.stdout, "  Failed all {} attempts", result.attempts).unwrap();
+                }
+
+                match result.repeats {
+                    Some(repeats) if repeats.flaky() => {
+                        self.set_style(self.theme.warning);
+                        writeln!(
+                            self.stdout,
+                            "  Flaky: passed {} of {} runs",
+                            repeats.passed, repeats.runs
+                        )
+                        .unwrap();
+                        self.reset();
+                    }
+                    Some(repeats) => {
+                        writeln!(self.stdout, "  Failed all {} runs", repeats.runs).unwrap();
+                    }
+                    None => {}
+                }
+
                 if let Some(error) = &result.error {
                     writeln!(self.stdout, "  Error: {}", error).unwrap();
                 } else if let Some(actual) = &result.actual_output {
@@ -363,6 +543,9 @@ impl Output {
                     )
                     .unwrap();
                     writeln!(self.stdout, "  Command: {}", result.test.command).unwrap();
+                    if let Some(stream) = result.mismatched_stream {
+                        writeln!(self.stdout, "  Mismatched {}", stream).unwrap();
+                    }
                     writeln!(self.stdout).unwrap();
                     self.print_diff(&result.expected_output, actual);
                 }
@@ -373,7 +556,7 @@ impl Output {
         let elapsed_str = format!(" in {:.2}s", elapsed.as_secs_f64());
 
         if total_failed == 0 && total_skipped == 0 {
-            self.set_color(Color::Green);
+            self.set_style(self.theme.pass);
             self.set_bold();
             write!(self.stdout, "All {} tests passed", total_passed).unwrap();
             self.reset();
@@ -398,6 +581,63 @@ impl Output {
                 .unwrap();
             }
         }
+
+        if flaky_count > 0 {
+            self.set_style(self.theme.warning);
+            write!(self.stdout, "Flaky:").unwrap();
+            self.reset();
+            writeln!(
+                self.stdout,
+                " {} {} passed some runs but not all",
+                flaky_count,
+                if flaky_count == 1 { "test" } else { "tests" }
+            )
+            .unwrap();
+        }
+
+        if let Some(threshold) = self.fail_under.filter(|_| total_failed > 0 && !update_mode) {
+            let rate = pass_rate(results);
+            let (verdict, style) = if rate >= threshold {
+                ("meets", self.theme.pass)
+            } else {
+                ("below", self.theme.fail)
+            };
+            write!(self.stdout, "Pass rate: {:.1}% (", rate).unwrap();
+            self.set_style(style);
+            write!(self.stdout, "{}", verdict).unwrap();
+            self.reset();
+            writeln!(self.stdout, " --fail-under {}%)", threshold).unwrap();
+        }
+    }
+
+    /// Print the `n` tests that took longest to run, slowest first.
+    pub fn print_slowest(&mut self, results: &[SuiteResult], n: usize) {
+        let slowest = slowest_tests(results, n);
+        if slowest.is_empty() {
+            return;
+        }
+
+        writeln!(self.stdout).unwrap();
+        self.set_bold();
+        writeln!(self.stdout, "Slowest tests:").unwrap();
+        self.reset();
+        for result in slowest {
+            let file_stem = result
+                .test
+                .file_path
+                .file_stem()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default();
+            writeln!(
+                self.stdout,
+                "  {:>7.2}s  {}/{}: {}",
+                result.elapsed.as_secs_f64(),
+                result.suite,
+                file_stem,
+                result.test.name
+            )
+            .unwrap();
+        }
     }
 
     pub fn print_diff(&mut self, expected: &str, actual: &str) {
@@ -410,13 +650,13 @@ impl Output {
 
             for op in group {
                 for change in diff.iter_changes(op) {
-                    let (sign, color) = match change.tag() {
-                        ChangeTag::Delete => ("-", Color::Red),
-                        ChangeTag::Insert => ("+", Color::Green),
-                        ChangeTag::Equal => (" ", Color::White),
+                    let (sign, style) = match change.tag() {
+                        ChangeTag::Delete => ("-", self.theme.fail),
+                        ChangeTag::Insert => ("+", self.theme.pass),
+                        ChangeTag::Equal => (" ", self.theme.context),
                     };
 
-                    self.set_color(color);
+                    self.set_style(style);
                     write!(self.stdout, "{}{}", sign, change.value()).unwrap();
                     self.reset();
                     if change.missing_newline() {
@@ -427,6 +667,103 @@ impl Output {
         }
     }
 
+    pub fn print_lint(&mut self, warnings: &[crate::lint::LintWarning]) {
+        for warning in warnings {
+            let display_path = std::env::current_dir()
+                .ok()
+                .and_then(|cwd| warning.file_path.strip_prefix(&cwd).ok())
+                .map(|p| p.to_path_buf())
+                .unwrap_or_else(|| warning.file_path.clone());
+            write!(self.stdout, "{}:{}: ", display_path.display(), warning.line).unwrap();
+            self.set_style(self.theme.warning);
+            write!(self.stdout, "warning:").unwrap();
+            self.reset();
+            writeln!(self.stdout, " {}", warning.message).unwrap();
+        }
+
+        if warnings.is_empty() {
+            writeln!(self.stdout, "No problems found").unwrap();
+        } else {
+            writeln!(self.stdout).unwrap();
+            self.set_bold();
+            write!(self.stdout, "{} warning(s)", warnings.len()).unwrap();
+            self.reset();
+            writeln!(self.stdout).unwrap();
+        }
+    }
+
+    pub fn print_dry_run(&mut self, files: &[crate::runner::PlannedFile]) {
+        let mut to_run = 0;
+        let mut skipped = 0;
+        let mut conditional = 0;
+        for file in files {
+            let stem = file
+                .file_path
+                .file_stem()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default();
+            if let Some(error) = &file.parse_error {
+                self.set_style(self.theme.fail);
+                write!(self.stdout, "✗").unwrap();
+                self.reset();
+                writeln!(self.stdout, " {}/{}: {}", file.suite, stem, error).unwrap();
+                continue;
+            }
+            for (test, reason) in &file.tests {
+                write!(self.stdout, "{}/{}: {}", file.suite, stem, test.name).unwrap();
+                if file.phase != "test" {
+                    self.set_dim();
+                    write!(self.stdout, " [{}]", file.phase).unwrap();
+                    self.reset();
+                }
+                match reason {
+                    Some(reason) if reason.starts_with("if: ") => {
+                        conditional += 1;
+                        self.set_style(self.theme.skip);
+                        write!(self.stdout, " (skip {})", reason).unwrap();
+                        self.reset();
+                    }
+                    Some(reason) => {
+                        skipped += 1;
+                        self.set_style(self.theme.skip);
+                        write!(self.stdout, " (skip: {})", reason).unwrap();
+                        self.reset();
+                    }
+                    None => to_run += 1,
+                }
+                writeln!(self.stdout).unwrap();
+            }
+        }
+
+        writeln!(self.stdout).unwrap();
+        self.set_bold();
+        write!(self.stdout, "Dry run:").unwrap();
+        self.reset();
+        write!(self.stdout, " {} to run, {} skipped", to_run, skipped).unwrap();
+        if conditional > 0 {
+            write!(
+                self.stdout,
+                ", {} skipped if a condition holds",
+                conditional
+            )
+            .unwrap();
+        }
+        writeln!(self.stdout).unwrap();
+    }
+
+    pub fn print_counts(&mut self, results: &[(&crate::discover::Suite, Vec<crate::TestCase>)]) {
+        let mut total = 0;
+        for (suite, tests) in results {
+            writeln!(self.stdout, "{}: {} test(s)", suite.name, tests.len()).unwrap();
+            total += tests.len();
+        }
+        writeln!(self.stdout).unwrap();
+        self.set_bold();
+        write!(self.stdout, "Total:").unwrap();
+        self.reset();
+        writeln!(self.stdout, " {} test(s)", total).unwrap();
+    }
+
     pub fn print_list(&mut self, results: &[(&crate::discover::Suite, Vec<crate::TestCase>)]) {
         for (suite, tests_by_file) in results {
             let mut markers = Vec::new();
@@ -476,3 +813,85 @@ impl Output {
         }
     }
 }
+
+/// The `n` slowest tests that ran, slowest first. Skipped tests are left out.
+pub fn slowest_tests(results: &[SuiteResult], n: usize) -> Vec<&TestResult> {
+    let mut ran: Vec<&TestResult> = results
+        .iter()
+        .flat_map(|s| &s.file_results)
+        .flat_map(|f| &f.results)
+        .filter(|r| !r.skipped)
+        .collect();
+    ran.sort_by_key(|r| std::cmp::Reverse(r.elapsed));
+    ran.truncate(n);
+    ran
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_theme_colors() {
+        let theme = Theme::new(ColorTheme::Default);
+        assert_eq!(theme.pass.fg, Some(Color::Green));
+        assert_eq!(theme.fail.fg, Some(Color::Red));
+        assert_eq!(theme.skip.fg, Some(Color::Yellow));
+    }
+
+    #[test]
+    fn test_colorblind_theme_avoids_red_green() {
+        let theme = Theme::new(ColorTheme::Colorblind);
+        assert_eq!(theme.pass.fg, Some(Color::Blue));
+        assert_eq!(theme.fail.fg, Some(Color::Ansi256(208)));
+        assert_eq!(theme.skip.fg, Some(Color::Magenta));
+    }
+
+    #[test]
+    fn test_mono_theme_uses_bold_only() {
+        let theme = Theme::new(ColorTheme::Mono);
+        assert_eq!(theme.pass, Style::PLAIN);
+        assert_eq!(theme.fail, Style::BOLD);
+        assert_eq!(theme.skip, Style::PLAIN);
+        assert!([theme.update, theme.warning, theme.context]
+            .iter()
+            .all(|s| s.fg.is_none()));
+    }
+
+    #[test]
+    fn test_slowest_tests_sorted_by_elapsed() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("timed");
+        std::fs::create_dir_all(&suite_dir).unwrap();
+        std::fs::write(
+            suite_dir.join("t.txt"),
+            "===\na\n===\ntrue\n---\n\n===\nb\n===\ntrue\n---\n\n===\nc\n===\ntrue\n---\n\n===\nd\n%skip\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        let suite = crate::discover::Suite::new(suite_dir, tmp.path());
+        let mut results = vec![crate::runner::run_suite(
+            &suite,
+            &crate::runner::TestFilter::default(),
+            &crate::runner::RunOptions::default(),
+            None,
+        )];
+        for result in &mut results[0].file_results[0].results {
+            result.elapsed = match result.test.name.as_str() {
+                "a" => Duration::from_millis(20),
+                "b" => Duration::from_millis(300),
+                "c" => Duration::from_millis(100),
+                _ => Duration::from_secs(10),
+            };
+        }
+
+        let names = |n| -> Vec<String> {
+            slowest_tests(&results, n)
+                .iter()
+                .map(|r| r.test.name.clone())
+                .collect()
+        };
+        assert_eq!(names(2), ["b", "c"]);
+        assert_eq!(names(10), ["b", "c", "a"]);
+        assert!(names(0).is_empty());
+    }
+}
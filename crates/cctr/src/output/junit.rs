@@ -0,0 +1,269 @@
+//! JUnit XML results for `--format junit`, for CI dashboards.
+
+use crate::runner::{SuiteResult, TestResult};
+use similar::{ChangeTag, TextDiff};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Render `results` as a `<testsuites>` document with a `<testsuite>` per
+/// suite and a `<testcase>` per test. Setup errors and files that failed to
+/// parse become `<testcase>` elements with an `<error>`.
+pub fn render(results: &[SuiteResult], elapsed: Duration) -> String {
+    let mut suites = String::new();
+    let mut totals = Counts::default();
+    for suite_result in results {
+        let (xml, counts) = render_suite(suite_result);
+        suites.push_str(&xml);
+        totals.add(&counts);
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites name=\"cctr\" {} time=\"{:.3}\">",
+        totals.attributes(),
+        elapsed.as_secs_f64()
+    )
+    .unwrap();
+    xml.push_str(&suites);
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+#[derive(Default)]
+struct Counts {
+    tests: usize,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.tests += other.tests;
+        self.failures += other.failures;
+        self.errors += other.errors;
+        self.skipped += other.skipped;
+    }
+
+    fn attributes(&self) -> String {
+        format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\"",
+            self.tests, self.failures, self.errors, self.skipped
+        )
+    }
+}
+
+fn render_suite(suite_result: &SuiteResult) -> (String, Counts) {
+    let suite = &suite_result.suite.name;
+    let mut cases = String::new();
+    let mut counts = Counts::default();
+
+    if let Some(error) = &suite_result.setup_error {
+        counts.tests += 1;
+        counts.errors += 1;
+        write_error_case(&mut cases, "setup", suite, error);
+    }
+    for file_result in &suite_result.file_results {
+        if let Some(error) = &file_result.parse_error {
+            counts.tests += 1;
+            counts.errors += 1;
+            let name = file_result.file_path.display().to_string();
+            write_error_case(&mut cases, &name, suite, error);
+        }
+        for result in &file_result.results {
+            counts.tests += 1;
+            if result.skipped {
+                counts.skipped += 1;
+            } else if !result.passed {
+                counts.failures += 1;
+            }
+            write_test_case(&mut cases, result);
+        }
+    }
+
+    let mut xml = String::new();
+    writeln!(
+        xml,
+        "  <testsuite name=\"{}\" {} time=\"{:.3}\">",
+        escape(suite),
+        counts.attributes(),
+        suite_result.elapsed.as_secs_f64()
+    )
+    .unwrap();
+    xml.push_str(&cases);
+    xml.push_str("  </testsuite>\n");
+    (xml, counts)
+}
+
+fn write_test_case(xml: &mut String, result: &TestResult) {
+    let test = &result.test;
+    let file_stem = test
+        .file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    write!(
+        xml,
+        "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" line=\"{}\" time=\"{:.3}\"",
+        escape(&test.name),
+        escape(&format!("{}.{}", result.suite, file_stem)),
+        escape(&test.file_path.display().to_string()),
+        test.start_line,
+        result.elapsed.as_secs_f64()
+    )
+    .unwrap();
+
+    if result.skipped {
+        let reason = result.skip_reason.as_deref().unwrap_or("skipped");
+        writeln!(xml, ">\n      <skipped message=\"{}\"/>", escape(reason)).unwrap();
+    } else if !result.passed {
+        let message = match (&result.error, result.mismatched_stream) {
+            (Some(error), _) => error.clone(),
+            (None, Some(stream)) => format!("{} did not match", stream),
+            (None, None) => "output did not match".to_string(),
+        };
+        let mut details = format!("Command: {}\n", test.command);
+        if result.error.is_none() {
+            if let Some(actual) = &result.actual_output {
+                details.push('\n');
+                details.push_str(&diff(&result.expected_output, actual));
+            }
+        }
+        writeln!(
+            xml,
+            ">\n      <failure message=\"{}\">{}</failure>",
+            escape(&message),
+            escape(&details)
+        )
+        .unwrap();
+    } else {
+        xml.push_str("/>\n");
+        return;
+    }
+    xml.push_str("    </testcase>\n");
+}
+
+fn write_error_case(xml: &mut String, name: &str, suite: &str, error: &str) {
+    writeln!(
+        xml,
+        "    <testcase name=\"{}\" classname=\"{}\" time=\"0.000\">\n      <error message=\"{}\"/>\n    </testcase>",
+        escape(name),
+        escape(suite),
+        escape(error)
+    )
+    .unwrap();
+}
+
+/// Expected vs actual as `-`/`+` lines, like the terminal diff without color
+fn diff(expected: &str, actual: &str) -> String {
+    let diff = TextDiff::from_lines(expected, actual);
+    let mut out = String::new();
+    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+        if idx > 0 {
+            out.push_str("...\n");
+        }
+        for op in group {
+            for change in diff.iter_changes(op) {
+                let sign = match change.tag() {
+                    ChangeTag::Delete => "-",
+                    ChangeTag::Insert => "+",
+                    ChangeTag::Equal => " ",
+                };
+                out.push_str(sign);
+                out.push_str(change.value());
+                if change.missing_newline() {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Escape text for XML attributes and content. Control characters that XML
+/// 1.0 doesn't allow are replaced with U+FFFD.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() && (c as u32) < 0x20 => out.push('\u{FFFD}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discover::Suite;
+    use crate::runner::{run_suite, RunOptions, TestFilter};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_is_well_formed_with_matching_counts() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("ci");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("mixed.txt"),
+            "===\npasses <&>\n===\necho ok\n---\nok\n\n===\nfails \"quoted\"\n===\necho '<b>&amp;</b>'\n---\n<i>\n\n===\nskipped\n%skip(not 'today')\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        fs::write(suite_dir.join("broken.txt"), "===\nunterminated\n").unwrap();
+
+        let suite = Suite::new(suite_dir, tmp.path());
+        let results = vec![run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions::default(),
+            None,
+        )];
+        let xml = render(&results, Duration::from_millis(1500));
+
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().name(), "testsuites");
+        assert_eq!(root.attribute("tests"), Some("4"));
+        assert_eq!(root.attribute("failures"), Some("1"));
+        assert_eq!(root.attribute("errors"), Some("1"));
+        assert_eq!(root.attribute("skipped"), Some("1"));
+        assert_eq!(root.attribute("time"), Some("1.500"));
+
+        let suite = root.first_element_child().unwrap();
+        assert_eq!(suite.attribute("name"), Some("ci"));
+        let cases: Vec<_> = suite
+            .children()
+            .filter(|n| n.has_tag_name("testcase"))
+            .collect();
+        assert_eq!(cases.len(), 4);
+        assert!(cases[0].children().any(|n| n.has_tag_name("error")));
+        assert_eq!(cases[1].attribute("name"), Some("passes <&>"));
+        assert_eq!(cases[1].attribute("classname"), Some("ci.mixed"));
+        assert!(cases[1].first_element_child().is_none());
+
+        let failure = cases[2].first_element_child().unwrap();
+        assert_eq!(failure.tag_name().name(), "failure");
+        assert_eq!(failure.attribute("message"), Some("output did not match"));
+        let text = failure.text().unwrap();
+        assert!(text.contains("-<i>\n+<b>&amp;</b>"), "{}", text);
+
+        let skipped = cases[3].first_element_child().unwrap();
+        assert_eq!(skipped.tag_name().name(), "skipped");
+        assert_eq!(skipped.attribute("message"), Some("not 'today'"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&\"c'"), "a&lt;b&gt;&amp;&quot;c&apos;");
+        assert_eq!(escape("tab\tbell\u{7}"), "tab\tbell\u{FFFD}");
+    }
+}
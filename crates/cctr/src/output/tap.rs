@@ -0,0 +1,209 @@
+//! TAP version 14 results for `--format tap`, for `prove` and other TAP
+//! consumers.
+
+use crate::runner::{SuiteResult, TestResult};
+use std::fmt::Write;
+
+/// Render `results` as a TAP document: one test point per test, in run
+/// order, with a YAML diagnostic block for each failure. Setup errors and
+/// files that failed to parse are failing test points of their own.
+pub fn render(results: &[SuiteResult]) -> String {
+    let mut points = Vec::new();
+    for suite_result in results {
+        let suite = &suite_result.suite.name;
+        if let Some(error) = &suite_result.setup_error {
+            points.push(error_point(&format!("{}: setup", suite), error));
+        }
+        for file_result in &suite_result.file_results {
+            if let Some(error) = &file_result.parse_error {
+                let name = format!("{}: {}", suite, file_result.file_path.display());
+                points.push(error_point(&name, error));
+            }
+            points.extend(file_result.results.iter().map(test_point));
+        }
+    }
+
+    let mut tap = String::from("TAP version 14\n");
+    writeln!(tap, "1..{}", points.len()).unwrap();
+    for (index, point) in points.iter().enumerate() {
+        let status = if point.ok { "ok" } else { "not ok" };
+        write!(
+            tap,
+            "{} {} - {}",
+            status,
+            index + 1,
+            escape(&point.description)
+        )
+        .unwrap();
+        if let Some(reason) = &point.skip {
+            write!(tap, " # SKIP {}", escape(reason)).unwrap();
+        }
+        tap.push('\n');
+        if !point.diagnostics.is_empty() {
+            tap.push_str("  ---\n");
+            for (key, value) in &point.diagnostics {
+                tap.push_str(&yaml_field(key, value));
+            }
+            tap.push_str("  ...\n");
+        }
+    }
+    tap
+}
+
+struct TestPoint {
+    ok: bool,
+    description: String,
+    skip: Option<String>,
+    diagnostics: Vec<(&'static str, String)>,
+}
+
+fn test_point(result: &TestResult) -> TestPoint {
+    let test = &result.test;
+    let file_stem = test
+        .file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let description = format!("{}/{}: {}", result.suite, file_stem, test.name);
+
+    if result.skipped {
+        let reason = result.skip_reason.clone().unwrap_or_default();
+        return TestPoint {
+            ok: true,
+            description,
+            skip: Some(reason),
+            diagnostics: Vec::new(),
+        };
+    }
+    if result.passed {
+        return TestPoint {
+            ok: true,
+            description,
+            skip: None,
+            diagnostics: Vec::new(),
+        };
+    }
+
+    let message = match (&result.error, result.mismatched_stream) {
+        (Some(error), _) => error.clone(),
+        (None, Some(stream)) => format!("{} did not match", stream),
+        (None, None) => "output did not match".to_string(),
+    };
+    let mut diagnostics = vec![
+        ("message", message),
+        ("severity", "fail".to_string()),
+        ("file", test.file_path.display().to_string()),
+        ("line", test.start_line.to_string()),
+        ("command", test.command.clone()),
+    ];
+    if result.error.is_none() {
+        if let Some(actual) = &result.actual_output {
+            diagnostics.push(("expected", result.expected_output.clone()));
+            diagnostics.push(("actual", actual.clone()));
+        }
+    }
+    TestPoint {
+        ok: false,
+        description,
+        skip: None,
+        diagnostics,
+    }
+}
+
+fn error_point(description: &str, error: &str) -> TestPoint {
+    TestPoint {
+        ok: false,
+        description: description.to_string(),
+        skip: None,
+        diagnostics: vec![
+            ("message", error.to_string()),
+            ("severity", "fail".to_string()),
+        ],
+    }
+}
+
+/// Escape `\` and `#`, which TAP gives special meaning in descriptions
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('#', "\\#")
+}
+
+/// One `key: value` line of a YAML diagnostic block. Multi-line values are
+/// literal block scalars; others are JSON strings, which are valid YAML.
+fn yaml_field(key: &str, value: &str) -> String {
+    if key == "line" {
+        return format!("  {}: {}\n", key, value);
+    }
+    if value.contains('\n') && !value.starts_with(' ') {
+        let mut field = format!("  {}: |-\n", key);
+        for line in value.lines() {
+            if line.is_empty() {
+                field.push('\n');
+            } else {
+                writeln!(field, "    {}", line).unwrap();
+            }
+        }
+        return field;
+    }
+    format!(
+        "  {}: {}\n",
+        key,
+        serde_json::to_string(value).unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discover::Suite;
+    use crate::runner::{run_suite, RunOptions, TestFilter};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_tap() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("tap");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("mixed.txt"),
+            "===\npasses #1\n===\necho ok\n---\nok\n\n===\nfails\n===\nprintf 'a\\nc\\n'\n---\na\nb\n\n===\nskipped\n%skip(not today)\n===\ntrue\n---\n",
+        )
+        .unwrap();
+
+        let suite = Suite::new(suite_dir, tmp.path());
+        let results = vec![run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions::default(),
+            None,
+        )];
+        let tap = render(&results);
+        let lines: Vec<&str> = tap.lines().collect();
+
+        assert_eq!(lines[0], "TAP version 14");
+        assert_eq!(lines[1], "1..3");
+        assert_eq!(lines[2], "ok 1 - tap/mixed: passes \\#1");
+        assert_eq!(lines[3], "not ok 2 - tap/mixed: fails");
+        assert_eq!(lines[4], "  ---");
+        assert_eq!(lines[5], "  message: \"output did not match\"");
+        assert!(tap.contains("  expected: |-\n    a\n    b\n  actual: |-\n    a\n    c\n  ...\n"));
+        assert_eq!(
+            lines.last(),
+            Some(&"ok 3 - tap/mixed: skipped # SKIP not today")
+        );
+    }
+
+    #[test]
+    fn test_yaml_field() {
+        assert_eq!(yaml_field("line", "4"), "  line: 4\n");
+        assert_eq!(
+            yaml_field("actual", "a: \"b\""),
+            "  actual: \"a: \\\"b\\\"\"\n"
+        );
+        assert_eq!(
+            yaml_field("actual", "a\n\nb"),
+            "  actual: |-\n    a\n\n    b\n"
+        );
+        assert_eq!(yaml_field("actual", " a\nb"), "  actual: \" a\\nb\"\n");
+    }
+}
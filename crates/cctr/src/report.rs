@@ -0,0 +1,441 @@
+//! Machine-readable reports written alongside the normal terminal output.
+
+use crate::discover::Suite;
+use crate::runner::{plan_suite, PlannedFile, SuiteResult, TestFilter};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::Duration;
+
+/// Version of the JSON layout of every machine-readable report, emitted as a
+/// top-level `schema_version` field. Bump it on any breaking change (removed
+/// or renamed fields, changed types); adding fields is not breaking.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Per-test durations for `--timings-json`: one entry per executed
+/// (non-skipped) test, in run order.
+pub fn timings_json(results: &[SuiteResult]) -> Value {
+    let entries: Vec<Value> = results
+        .iter()
+        .flat_map(|suite_result| &suite_result.file_results)
+        .flat_map(|file_result| &file_result.results)
+        .filter(|r| !r.skipped)
+        .map(|r| {
+            let file = r
+                .test
+                .file_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            json!({
+                "suite": r.suite,
+                "file": file,
+                "name": r.test.name,
+                "elapsed_ms": r.elapsed.as_secs_f64() * 1000.0,
+                "passed": r.passed,
+            })
+        })
+        .collect();
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "tests": entries,
+    })
+}
+
+/// Every result for `--format json`: per suite, per file, per test, with
+/// test counts and the overall verdict. `actual_output` is null for tests
+/// that didn't run.
+pub fn results_json(results: &[SuiteResult], elapsed: Duration) -> Value {
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    let suites: Vec<Value> = results
+        .iter()
+        .map(|suite_result| {
+            let files: Vec<Value> = suite_result
+                .file_results
+                .iter()
+                .map(|file_result| {
+                    let tests: Vec<Value> = file_result
+                        .results
+                        .iter()
+                        .map(|r| {
+                            if r.skipped {
+                                skipped += 1;
+                            } else if r.passed {
+                                passed += 1;
+                            } else {
+                                failed += 1;
+                            }
+                            json!({
+                                "name": r.test.name,
+                                "file": r.test.file_path.display().to_string(),
+                                "line": r.test.start_line,
+                                "passed": r.passed && !r.skipped,
+                                "skipped": r.skipped,
+                                "skip_reason": r.skip_reason,
+                                "elapsed_secs": r.elapsed.as_secs_f64(),
+                                "attempts": r.attempts,
+                                "expected_output": r.expected_output,
+                                "actual_output": r.actual_output,
+                                "error": r.error,
+                                "mismatched_stream": r.mismatched_stream,
+                            })
+                        })
+                        .collect();
+                    json!({
+                        "file": file_result.file_path.display().to_string(),
+                        "parse_error": file_result.parse_error,
+                        "tests": tests,
+                    })
+                })
+                .collect();
+            json!({
+                "suite": suite_result.suite.name,
+                "passed": suite_result.passed(),
+                "elapsed_secs": suite_result.elapsed.as_secs_f64(),
+                "setup_error": suite_result.setup_error,
+                "files": files,
+            })
+        })
+        .collect();
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "passed": results.iter().all(|r| r.passed()),
+        "elapsed_secs": elapsed.as_secs_f64(),
+        "summary": {
+            "passed": passed,
+            "failed": failed,
+            "skipped": skipped,
+        },
+        "suites": suites,
+    })
+}
+
+pub fn write_timings_json(results: &[SuiteResult], path: &Path) -> anyhow::Result<()> {
+    let content = serde_json::to_string_pretty(&timings_json(results))?;
+    std::fs::write(path, content + "\n")?;
+    Ok(())
+}
+
+/// The execution plan for `--dump-plan`: per suite, the files in the order
+/// they run (setup, test files, teardown) and the tests each will run,
+/// after applying `filter`. Suites run in parallel unless `sequential`.
+/// With `--shuffle`, pass the suites already shuffled by `shuffle_suites`
+/// and the seed, which shuffles each file's tests the way the run does.
+pub fn plan_json(
+    suites: &[Suite],
+    filter: &TestFilter,
+    sequential: bool,
+    shuffle_seed: Option<u64>,
+) -> Value {
+    let suites: Vec<Value> = suites
+        .iter()
+        .map(|suite| {
+            let files: Vec<Value> = plan_suite(suite, filter, shuffle_seed)
+                .iter()
+                .map(plan_file)
+                .collect();
+            json!({
+                "suite": suite.name,
+                "files": files,
+            })
+        })
+        .collect();
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "sequential": sequential || suites.len() <= 1,
+        "seed": shuffle_seed,
+        "suites": suites,
+    })
+}
+
+/// One file of the plan, with the reason each test would be skipped, as
+/// `--dry-run` shows it, or null
+fn plan_file(planned: &PlannedFile) -> Value {
+    let file = planned
+        .file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if let Some(error) = &planned.parse_error {
+        return json!({
+            "file": file,
+            "phase": planned.phase,
+            "tests": [],
+            "parse_error": error,
+        });
+    }
+    let tests: Vec<Value> = planned
+        .tests
+        .iter()
+        .map(|(t, skip)| {
+            json!({
+                "name": t.name,
+                "line": t.start_line,
+                "require": t.require,
+                "skip": skip,
+            })
+        })
+        .collect();
+    json!({
+        "file": file,
+        "phase": planned.phase,
+        "tests": tests,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discover::Suite;
+    use crate::runner::{run_suite, shuffle_suites, RunOptions};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_timings_json_has_entry_per_run_test() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("timed");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("slow.txt"),
+            "===\nsleepy\n===\nsleep 0.05 && echo done\n---\ndone\n\n===\nfails\n===\necho no\n---\nyes\n\n===\nskipped\n%skip\n===\necho skip\n---\nskip\n",
+        )
+        .unwrap();
+
+        let suite = Suite::new(suite_dir, tmp.path());
+        let results = vec![run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions::default(),
+            None,
+        )];
+        let timings = timings_json(&results);
+        assert_eq!(timings["schema_version"], SCHEMA_VERSION);
+        let entries = timings["tests"].as_array().unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["suite"], "timed");
+        assert_eq!(entries[0]["file"], "slow");
+        assert_eq!(entries[0]["name"], "sleepy");
+        assert_eq!(entries[0]["passed"], true);
+        assert!(entries[0]["elapsed_ms"].as_f64().unwrap() >= 50.0);
+        assert_eq!(entries[1]["name"], "fails");
+        assert_eq!(entries[1]["passed"], false);
+        assert!(entries[1]["elapsed_ms"].as_f64().unwrap() >= 0.0);
+    }
+
+    #[test]
+    fn test_results_json_round_trips() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("reported");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("mixed.txt"),
+            "===\npasses\n===\necho ok\n---\nok\n\n===\nfails\n===\necho no\n---\nyes\n\n===\nskipped\n%skip(not today)\n===\necho skip\n---\nskip\n",
+        )
+        .unwrap();
+
+        let suite = Suite::new(suite_dir, tmp.path());
+        let results = vec![run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions::default(),
+            None,
+        )];
+        let text =
+            serde_json::to_string_pretty(&results_json(&results, Duration::from_secs(2))).unwrap();
+        let report: Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(report["schema_version"], SCHEMA_VERSION);
+        assert_eq!(report["passed"], false);
+        assert_eq!(report["elapsed_secs"], 2.0);
+        assert_eq!(
+            report["summary"],
+            json!({"passed": 1, "failed": 1, "skipped": 1})
+        );
+        let suite = &report["suites"][0];
+        assert_eq!(suite["suite"], "reported");
+        assert_eq!(suite["passed"], false);
+        let tests = suite["files"][0]["tests"].as_array().unwrap();
+        assert_eq!(tests[0]["name"], "passes");
+        assert_eq!(tests[0]["passed"], true);
+        assert_eq!(tests[1]["expected_output"], "yes");
+        assert_eq!(tests[1]["actual_output"], "no");
+        assert_eq!(tests[1]["error"], Value::Null);
+        assert_eq!(tests[2]["skipped"], true);
+        assert_eq!(tests[2]["passed"], false);
+        assert_eq!(tests[2]["skip_reason"], "not today");
+        assert_eq!(tests[2]["actual_output"], Value::Null);
+    }
+
+    #[test]
+    fn test_plan_json_lists_files_and_tests_in_run_order() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("planned");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("_setup.txt"),
+            "===\nprepare\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            suite_dir.join("_teardown.txt"),
+            "===\ncleanup\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            suite_dir.join("b_users.txt"),
+            "===\ncreate user\n%require\n===\ntrue\n---\n\n===\ndelete user\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            suite_dir.join("a_auth.txt"),
+            "===\nlogin\n===\ntrue\n---\n\n===\nlogout\n===\ntrue\n---\n",
+        )
+        .unwrap();
+        fs::write(suite_dir.join("c_broken.txt"), "===\nunterminated\n").unwrap();
+        let suite = Suite::new(suite_dir, tmp.path());
+
+        let plan = plan_json(
+            std::slice::from_ref(&suite),
+            &TestFilter::default(),
+            false,
+            None,
+        );
+        assert_eq!(plan["schema_version"], SCHEMA_VERSION);
+        assert_eq!(plan["sequential"], true);
+        let files = plan["suites"][0]["files"].as_array().unwrap();
+        let order: Vec<_> = files
+            .iter()
+            .map(|f| {
+                format!(
+                    "{}:{}",
+                    f["phase"].as_str().unwrap(),
+                    f["file"].as_str().unwrap()
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                "setup:_setup",
+                "test:a_auth",
+                "test:b_users",
+                "test:c_broken",
+                "teardown:_teardown"
+            ]
+        );
+        assert_eq!(files[1]["tests"][0]["name"], "login");
+        assert_eq!(files[1]["tests"][1]["name"], "logout");
+        assert_eq!(files[2]["tests"][0]["require"], true);
+        assert_eq!(files[2]["tests"][1]["require"], false);
+        assert!(files[3]["parse_error"].is_string());
+
+        // The pattern filters test files but not setup and teardown
+        let filter = TestFilter {
+            pattern: Some(regex::Regex::new("^delete").unwrap()),
+            ..Default::default()
+        };
+        let plan = plan_json(&[suite], &filter, false, None);
+        let files = plan["suites"][0]["files"].as_array().unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[1]["file"], "b_users");
+        assert_eq!(files[1]["tests"].as_array().unwrap().len(), 1);
+        assert_eq!(files[1]["tests"][0]["name"], "delete user");
+        assert_eq!(files[2]["file"], "c_broken");
+    }
+
+    #[test]
+    fn test_plan_json_follows_shuffle_seed() {
+        let tmp = TempDir::new().unwrap();
+        let mut suites = Vec::new();
+        for name in ["s1", "s2", "s3", "s4"] {
+            let suite_dir = tmp.path().join(name);
+            fs::create_dir_all(&suite_dir).unwrap();
+            let tests: String = (1..=6)
+                .map(|i| format!("===\nt{}\n===\ntrue\n---\n\n", i))
+                .collect();
+            fs::write(suite_dir.join("tests.txt"), tests).unwrap();
+            suites.push(Suite::new(suite_dir, tmp.path()));
+        }
+        shuffle_suites(&mut suites, 7);
+
+        let plan = plan_json(&suites, &TestFilter::default(), true, Some(7));
+        assert_eq!(plan["seed"], 7);
+        let planned: Vec<String> = plan["suites"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|suite| {
+                suite["files"][0]["tests"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| format!("{}/{}", suite["suite"], t["name"]))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let ran: Vec<String> = suites
+            .iter()
+            .flat_map(|suite| {
+                let result = run_suite(
+                    suite,
+                    &TestFilter::default(),
+                    &RunOptions {
+                        shuffle_seed: Some(7),
+                        ..Default::default()
+                    },
+                    None,
+                );
+                result.file_results[0]
+                    .results
+                    .iter()
+                    .map(|r| format!("{:?}/{:?}", result.suite.name, r.test.name))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(planned, ran);
+        let unshuffled: Vec<String> = ["s1", "s2", "s3", "s4"]
+            .iter()
+            .flat_map(|s| (1..=6).map(move |i| format!("{:?}/{:?}", s, format!("t{}", i))))
+            .collect();
+        assert_ne!(planned, unshuffled);
+    }
+
+    #[test]
+    fn test_plan_json_marks_skipped_tests() {
+        let tmp = TempDir::new().unwrap();
+        let suite_dir = tmp.path().join("focused");
+        fs::create_dir_all(&suite_dir).unwrap();
+        fs::write(
+            suite_dir.join("focus.txt"),
+            "===
+focused
+%only
+===
+true
+---
+
+===
+unfocused
+===
+true
+---
+
+===
+later
+%skip(not yet)
+===
+true
+---
+",
+        )
+        .unwrap();
+        let suite = Suite::new(suite_dir, tmp.path());
+
+        let plan = plan_json(&[suite], &TestFilter::default(), false, None);
+        let tests = plan["suites"][0]["files"][0]["tests"].as_array().unwrap();
+        assert_eq!(tests[0]["skip"], Value::Null);
+        assert_eq!(tests[1]["skip"], "not focused");
+        assert_eq!(tests[2]["skip"], "not focused");
+    }
+}
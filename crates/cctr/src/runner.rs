@@ -1,8 +1,11 @@
 use crate::discover::Suite;
-use crate::matcher::Matcher;
-use crate::{parse_content, parse_file, TestCase};
+use crate::matcher::{format_value, json_matches, numbers_match_within, regex_captures, Matcher};
+use crate::{
+    parse_content, parse_file, CorpusFile, ExpectedExit, MatchMode, ParseError, SortOrder, TestCase,
+};
 use cctr_expr::Value;
 use regex::Regex;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
@@ -79,6 +82,28 @@ pub struct TestResult {
     pub warning: Option<String>,
     pub elapsed: Duration,
     pub suite: String,
+    /// Times the command ran: 1 plus any `%retry` attempts, 0 if it never ran
+    pub attempts: u32,
+    /// For a test with a `%stderr` block that failed, the stream that didn't
+    /// match ("stdout" or "stderr"); `actual_output` and `expected_output`
+    /// then hold that stream
+    pub mismatched_stream: Option<&'static str>,
+    /// How many of the runs passed, for a test run several times with `--repeat`
+    pub repeats: Option<Repeats>,
+}
+
+/// Outcome of running one test several times with `--repeat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeats {
+    pub runs: u32,
+    pub passed: u32,
+}
+
+impl Repeats {
+    /// Whether the test passed some runs but not all of them
+    pub fn flaky(&self) -> bool {
+        self.passed > 0 && self.passed < self.runs
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -120,6 +145,182 @@ impl SuiteResult {
     }
 }
 
+/// Exit code for a run cut short by Ctrl-C, distinct from failing tests (1)
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Count the tests in `suites` selected by `filter` that have no result,
+/// e.g. because the run was interrupted. Files that fail to parse are not
+/// counted, since they are already reported as failures.
+pub fn count_not_executed(suites: &[Suite], filter: &TestFilter, results: &[SuiteResult]) -> usize {
+    let executed: HashMap<&Path, usize> = results
+        .iter()
+        .flat_map(|r| &r.file_results)
+        .map(|f| (f.file_path.as_path(), f.results.len()))
+        .collect();
+    suites
+        .iter()
+        .flat_map(|suite| suite.corpus_files())
+        .filter_map(|file| {
+            let corpus = parse_file(&file).ok()?;
+            let selected = corpus
+                .tests
+                .iter()
+                .filter(|t| filter.selects(&file, t))
+                .count();
+            let ran = executed.get(file.as_path()).copied().unwrap_or(0);
+            Some(selected.saturating_sub(ran))
+        })
+        .sum()
+}
+
+/// Which tests to run, from `-p`, `--exclude`, `--tags` and `--skip-tags`.
+/// The default selects every test.
+#[derive(Debug, Default)]
+pub struct TestFilter {
+    /// Matches test names, or file stems to select every test in the file
+    pub pattern: Option<Regex>,
+    /// Only run tests with at least one of these tags (lowercase), if any
+    pub tags: Vec<String>,
+    /// Don't run tests with any of these tags (lowercase)
+    pub skip_tags: Vec<String>,
+    /// Don't run tests whose name or file stem contains any of these
+    pub exclude: Vec<String>,
+}
+
+/// How `run_suite` runs the tests it selects. The default runs each test
+/// once, in file order, with no timeout but its own `%timeout`.
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    /// Timeout for tests without a `%timeout`, from `--timeout`
+    pub default_timeout: Option<Duration>,
+    /// Seed for shuffling each file's tests, from `--shuffle`
+    pub shuffle_seed: Option<u64>,
+    /// How many times to run each test, from `--repeat`
+    pub repeat: u32,
+    /// Send output lines as progress events while a test runs, for `-vv`
+    pub stream_output: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            default_timeout: None,
+            shuffle_seed: None,
+            repeat: 1,
+            stream_output: false,
+        }
+    }
+}
+
+impl RunOptions {
+    /// These options for setup and teardown, which always run once each,
+    /// in file order
+    fn in_file_order(&self) -> RunOptions {
+        RunOptions {
+            shuffle_seed: None,
+            repeat: 1,
+            ..*self
+        }
+    }
+}
+
+/// Where a corpus file's tests run and how they are reported
+#[derive(Debug, Clone, Copy)]
+pub struct FileContext<'a> {
+    /// Used for reporting and to resolve relative `%env-file` paths
+    pub file_path: &'a Path,
+    pub work_dir: &'a Path,
+    pub suite_name: &'a str,
+    /// Environment added to every test, before file- and test-level `%env`
+    pub env_vars: &'a [(String, String)],
+}
+
+impl TestFilter {
+    pub fn selects(&self, file: &Path, test: &TestCase) -> bool {
+        let stem = file.file_stem().and_then(|s| s.to_str());
+        let pattern_matches = self.pattern.as_ref().is_none_or(|pat| {
+            pat.is_match(&test.name) || stem.is_some_and(|stem| pat.is_match(stem))
+        });
+        let excluded = self.exclude.iter().any(|ex| {
+            test.name.contains(ex.as_str()) || stem.is_some_and(|stem| stem.contains(ex.as_str()))
+        });
+        let has_tag = |tags: &[String]| test.tags.iter().any(|t| tags.contains(t));
+        pattern_matches
+            && !excluded
+            && (self.tags.is_empty() || has_tag(&self.tags))
+            && !has_tag(&self.skip_tags)
+    }
+}
+
+/// The line printed after the results of an interrupted run
+pub fn interrupted_message(not_executed: usize) -> String {
+    format!(
+        "run interrupted; {} {} not executed",
+        not_executed,
+        if not_executed == 1 { "test" } else { "tests" }
+    )
+}
+
+/// Derive the seed for shuffling one file's tests from the run's `--seed`
+/// and the file's `suite/file.txt` name, so a file is shuffled the same way
+/// whatever order the suites run in
+fn shuffle_seed_for(seed: u64, name: &str) -> u64 {
+    // FNV-1a, which unlike `DefaultHasher` is stable across Rust releases
+    name.bytes().fold(seed ^ 0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Shuffle the order suites start in for `--shuffle`
+pub fn shuffle_suites(suites: &mut [Suite], seed: u64) {
+    fastrand::Rng::with_seed(seed).shuffle(suites);
+}
+
+/// Shuffle one test file's tests for `--shuffle`, before filtering
+fn shuffle_tests(tests: &mut [TestCase], seed: u64, suite_name: &str, file_path: &Path) {
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    let file_seed = shuffle_seed_for(seed, &format!("{}/{}", suite_name, file_name));
+    fastrand::Rng::with_seed(file_seed).shuffle(tests);
+}
+
+/// The line printed after the results of a run stopped by `--bail`
+pub fn bail_message(not_executed: usize) -> String {
+    format!(
+        "stopped after a failing suite (--bail); {} {} not executed",
+        not_executed,
+        if not_executed == 1 { "test" } else { "tests" }
+    )
+}
+
+/// Percentage of tests that passed, excluding skipped tests. Parse errors
+/// and setup errors count as failures. With no tests run the rate is 100.
+pub fn pass_rate(results: &[SuiteResult]) -> f64 {
+    let mut passed = 0;
+    let mut failed = 0;
+    for suite_result in results {
+        if suite_result.setup_error.is_some() {
+            failed += 1;
+        }
+        for file_result in &suite_result.file_results {
+            if file_result.parse_error.is_some() {
+                failed += 1;
+            }
+            for result in file_result.results.iter().filter(|r| !r.skipped) {
+                if result.passed {
+                    passed += 1;
+                } else {
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if passed + failed == 0 {
+        100.0
+    } else {
+        100.0 * passed as f64 / (passed + failed) as f64
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ProgressEvent {
     TestStart {
@@ -155,6 +356,52 @@ fn is_multiline(command: &str) -> bool {
     command.contains('\n')
 }
 
+/// Quote an argument so the shell passes it through literally
+fn quote_arg(arg: &str, shell: Shell) -> String {
+    match shell {
+        Shell::PowerShell => format!("'{}'", arg.replace('\'', "''")),
+        Shell::Cmd => format!("\"{}\"", arg.replace('"', "\"\"")),
+        Shell::Bash | Shell::Sh | Shell::Zsh => {
+            format!("'{}'", arg.replace('\'', "'\\''"))
+        }
+    }
+}
+
+/// Replace `{{ name }}` in the command with values captured by earlier tests
+/// in the file. Strings are inserted as-is, without quoting.
+fn substitute_command_variables(
+    test: &TestCase,
+    prior_vars: &HashMap<String, Value>,
+) -> Result<String, String> {
+    let mut command = test.command.clone();
+    for name in &test.command_variables {
+        let Some(value) = prior_vars.get(name) else {
+            return Err(format!(
+                "command uses {{{{ {} }}}}, but no earlier passing test in this file captured it",
+                name
+            ));
+        };
+        let text = match value {
+            Value::String(s) => s.clone(),
+            other => format_value(other),
+        };
+        let placeholder = Regex::new(&format!(r"\{{\{{\s*{}\s*\}}\}}", name)).unwrap();
+        command = placeholder
+            .replace_all(&command, regex::NoExpand(&text))
+            .into_owned();
+    }
+    Ok(command)
+}
+
+/// Append `%args` to the command's (last) line
+fn command_with_args(command: &str, args: &[String], shell: Shell) -> String {
+    if args.is_empty() {
+        return command.to_string();
+    }
+    let quoted: Vec<String> = args.iter().map(|a| quote_arg(a, shell)).collect();
+    format!("{} {}", command.trim_end(), quoted.join(" "))
+}
+
 fn build_command(
     command: &str,
     work_dir: &Path,
@@ -202,38 +449,151 @@ fn build_command(
     cmd
 }
 
+/// Why a command stopped without exiting on its own
+#[derive(Debug, Clone, PartialEq)]
+enum Termination {
+    /// Killed by a signal, described as e.g. "terminated by signal SIGSEGV (11)"
+    Signal(String),
+    /// Killed by cctr when its `%timeout` or `--timeout` expired
+    TimedOut(Duration),
+}
+
+impl std::fmt::Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Termination::Signal(description) => write!(f, "{}", description),
+            Termination::TimedOut(timeout) => write!(f, "timed out after {:?}", timeout),
+        }
+    }
+}
+
+/// What a command printed and how it ended
+#[derive(Debug)]
+struct CommandOutput {
+    /// stdout and stderr combined, or only stdout if stderr was split
+    output: String,
+    /// -1 if there is none
+    exit_code: i32,
+    /// How the command was stopped, if it didn't exit on its own
+    termination: Option<Termination>,
+    /// stderr, if it was captured separately
+    stderr: Option<String>,
+}
+
+impl CommandOutput {
+    fn new(output: String, exit_code: i32) -> Self {
+        CommandOutput {
+            output,
+            exit_code,
+            termination: None,
+            stderr: None,
+        }
+    }
+}
+
+/// Per-test settings for running a command
+#[derive(Debug, Default, Clone, Copy)]
+struct CommandOptions<'a> {
+    timeout: Option<Duration>,
+    /// `%stdin` text to feed to the command
+    stdin: Option<&'a str>,
+    /// Capture stderr separately, for a `%stderr` block
+    split_stderr: bool,
+}
+
+/// Strip ANSI escapes, normalize line endings and drop trailing newlines
+fn normalize_output(output: &str) -> String {
+    let stripped = strip_ansi_escapes::strip_str(output);
+    stripped
+        .replace("\r\n", "\n")
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+/// Describe a signal termination, e.g. "terminated by signal SIGSEGV (11)"
+#[cfg(unix)]
+fn termination_signal(status: &std::process::ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let signal = status.signal()?;
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGUSR2 => "SIGUSR2",
+        _ => return Some(format!("terminated by signal {}", signal)),
+    };
+    Some(format!("terminated by signal {} ({})", name, signal))
+}
+
+#[cfg(not(unix))]
+fn termination_signal(_status: &std::process::ExitStatus) -> Option<String> {
+    None
+}
+
 fn run_command(
     command: &str,
     work_dir: &Path,
     env_vars: &[(String, String)],
     shell: Option<Shell>,
     interruptible: bool,
-) -> (String, i32) {
+    options: CommandOptions,
+) -> CommandOutput {
+    let CommandOptions {
+        timeout,
+        stdin,
+        split_stderr,
+    } = options;
     let shell = shell.unwrap_or_else(default_shell);
     let mut cmd = build_command(command, work_dir, env_vars, shell);
 
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
 
     let mut child = match cmd.spawn() {
         Ok(child) => child,
-        Err(e) => return (format!("Failed to execute command: {}", e), -1),
+        Err(e) => return CommandOutput::new(format!("Failed to execute command: {}", e), -1),
     };
+    feed_stdin(&mut child, stdin);
 
+    let start = Instant::now();
     let exit_status = loop {
         if interruptible && is_interrupted() {
             let _ = child.kill();
             let _ = child.wait();
-            return (String::new(), 130);
+            return CommandOutput::new(String::new(), 130);
+        }
+        if let Some(timeout) = timeout.filter(|t| start.elapsed() >= *t) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return CommandOutput {
+                termination: Some(Termination::TimedOut(timeout)),
+                ..CommandOutput::new(String::new(), -1)
+            };
         }
         match child.try_wait() {
             Ok(Some(status)) => break status,
             Ok(None) => std::thread::sleep(Duration::from_millis(10)),
-            Err(e) => return (format!("Failed to wait for command: {}", e), -1),
+            Err(e) => return CommandOutput::new(format!("Failed to wait for command: {}", e), -1),
         }
     };
 
     let exit_code = exit_status.code().unwrap_or(-1);
+    let termination = termination_signal(&exit_status).map(Termination::Signal);
     let mut stdout_str = String::new();
     let mut stderr_str = String::new();
     if let Some(mut r) = child.stdout.take() {
@@ -242,15 +602,55 @@ fn run_command(
     if let Some(mut r) = child.stderr.take() {
         let _ = std::io::Read::read_to_string(&mut r, &mut stderr_str);
     }
+    if split_stderr {
+        return CommandOutput {
+            output: normalize_output(&stdout_str),
+            exit_code,
+            termination,
+            stderr: Some(normalize_output(&stderr_str)),
+        };
+    }
     let combined = format!("{}{}", stdout_str, stderr_str);
-    let stripped = strip_ansi_escapes::strip_str(&combined);
-    let normalized = stripped.replace("\r\n", "\n");
-    (normalized.trim_end_matches('\n').to_string(), exit_code)
+    CommandOutput {
+        termination,
+        ..CommandOutput::new(normalize_output(&combined), exit_code)
+    }
+}
+
+/// Write `%stdin` text to the child's stdin, followed by a newline unless it
+/// is empty, then close the pipe. The write happens on its own thread so a
+/// command that produces output before reading all its input can't deadlock.
+fn feed_stdin(child: &mut std::process::Child, input: Option<&str>) {
+    let (Some(mut pipe), Some(input)) = (child.stdin.take(), input) else {
+        return;
+    };
+    let mut bytes = input.as_bytes().to_vec();
+    if !bytes.is_empty() {
+        bytes.push(b'\n');
+    }
+    std::thread::spawn(move || {
+        // A command that exits without reading its input would otherwise
+        // raise SIGPIPE, which kills cctr; blocked, the write fails instead
+        #[cfg(unix)]
+        unsafe {
+            let mut set: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGPIPE);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        }
+        let _ = std::io::Write::write_all(&mut pipe, &bytes);
+    });
 }
 
 /// Callback for streaming output lines
 pub type OutputCallback = Box<dyn Fn(&str) + Send>;
 
+/// Lines buffered between the stdout/stderr readers and the consumer in
+/// streaming mode. When full, the readers block, so a command that writes
+/// faster than its output is handled is paused by the pipe rather than
+/// buffered in memory.
+const STREAM_CHANNEL_CAPACITY: usize = 256;
+
 fn run_command_streaming(
     command: &str,
     work_dir: &Path,
@@ -258,31 +658,43 @@ fn run_command_streaming(
     shell: Option<Shell>,
     on_line: OutputCallback,
     interruptible: bool,
-) -> (String, i32) {
-    use std::sync::mpsc::channel;
+    options: CommandOptions,
+) -> CommandOutput {
+    use std::sync::mpsc::sync_channel;
+
+    let CommandOptions {
+        timeout,
+        stdin,
+        split_stderr,
+    } = options;
 
     let shell = shell.unwrap_or_else(default_shell);
     let mut cmd = build_command(command, work_dir, env_vars, shell);
 
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
 
     let mut child = match cmd.spawn() {
         Ok(child) => child,
-        Err(e) => return (format!("Failed to execute command: {}", e), -1),
+        Err(e) => return CommandOutput::new(format!("Failed to execute command: {}", e), -1),
     };
+    feed_stdin(&mut child, stdin);
 
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
-    // Use a channel to receive lines from both stdout and stderr as they arrive
-    let (tx, rx) = channel::<String>();
+    // Use a bounded channel to receive lines from both stdout and stderr as
+    // they arrive, tagged with whether they came from stderr
+    let (tx, rx) = sync_channel::<(bool, String)>(STREAM_CHANNEL_CAPACITY);
 
     let tx_stdout = tx.clone();
     let stdout_handle = std::thread::spawn(move || {
         let reader = BufReader::new(stdout);
         for line in reader.lines().map_while(Result::ok) {
-            let _ = tx_stdout.send(line);
+            let _ = tx_stdout.send((false, line));
         }
     });
 
@@ -290,28 +702,49 @@ fn run_command_streaming(
     let stderr_handle = std::thread::spawn(move || {
         let reader = BufReader::new(stderr);
         for line in reader.lines().map_while(Result::ok) {
-            let _ = tx_stderr.send(line);
+            let _ = tx_stderr.send((true, line));
         }
     });
 
     let mut output_lines = Vec::new();
+    let mut stderr_lines = Vec::new();
+    let start = Instant::now();
+    let split = |output_lines: &[String], stderr_lines: &[String], exit_code| CommandOutput {
+        stderr: split_stderr.then(|| stderr_lines.join("\n").replace("\r\n", "\n")),
+        ..CommandOutput::new(output_lines.join("\n").replace("\r\n", "\n"), exit_code)
+    };
 
     loop {
         match rx.recv_timeout(Duration::from_millis(10)) {
-            Ok(line) => {
+            Ok((from_stderr, line)) => {
                 let stripped = strip_ansi_escapes::strip_str(&line);
                 on_line(&stripped);
-                output_lines.push(stripped);
+                if split_stderr && from_stderr {
+                    stderr_lines.push(stripped);
+                } else {
+                    output_lines.push(stripped);
+                }
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
                 if interruptible && is_interrupted() {
                     let _ = child.kill();
                     let _ = child.wait();
+                    // Unblock readers waiting on a full channel
+                    drop(rx);
                     let _ = stdout_handle.join();
                     let _ = stderr_handle.join();
-                    let combined = output_lines.join("\n");
-                    let normalized = combined.replace("\r\n", "\n");
-                    return (normalized, 130);
+                    return split(&output_lines, &stderr_lines, 130);
+                }
+                if let Some(timeout) = timeout.filter(|t| start.elapsed() >= *t) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    // Don't join the readers: a background process started by
+                    // the command may still hold the pipes open
+                    drop(rx);
+                    return CommandOutput {
+                        termination: Some(Termination::TimedOut(timeout)),
+                        ..split(&output_lines, &stderr_lines, -1)
+                    };
                 }
             }
             Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
@@ -321,19 +754,61 @@ fn run_command_streaming(
     let _ = stdout_handle.join();
     let _ = stderr_handle.join();
 
-    let exit_code = match child.wait() {
-        Ok(status) => status.code().unwrap_or(-1),
-        Err(_) => -1,
+    let (exit_code, termination) = match child.wait() {
+        Ok(status) => (
+            status.code().unwrap_or(-1),
+            termination_signal(&status).map(Termination::Signal),
+        ),
+        Err(_) => (-1, None),
     };
 
-    let combined = output_lines.join("\n");
     // Normalize line endings (Windows uses \r\n)
-    let normalized = combined.replace("\r\n", "\n");
-    (normalized, exit_code)
+    CommandOutput {
+        termination,
+        ..split(&output_lines, &stderr_lines, exit_code)
+    }
 }
 
 use crate::SkipDirective;
-use cctr_corpus::Platform;
+use cctr_corpus::{trace_enabled, Platform};
+
+/// Load `KEY=VALUE` lines from env files named relative to `corpus_path`.
+/// Blank lines and `#` comments are ignored, and an `export ` prefix and
+/// surrounding quotes on the value are stripped.
+fn load_env_files(corpus_path: &Path, files: &[String]) -> Result<Vec<(String, String)>, String> {
+    let base = corpus_path.parent().unwrap_or(Path::new("."));
+    let mut vars = Vec::new();
+    for file in files {
+        let path = base.join(file);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read env file '{}': {}", path.display(), e))?;
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!(
+                    "invalid line {} in env file '{}': expected KEY=VALUE",
+                    line_num + 1,
+                    path.display()
+                ));
+            };
+            let value = value.trim();
+            let value = [('"', '"'), ('\'', '\'')]
+                .iter()
+                .find_map(|(open, close)| {
+                    value
+                        .strip_prefix(*open)
+                        .and_then(|v| v.strip_suffix(*close))
+                })
+                .unwrap_or(value);
+            vars.push((key.trim().to_string(), value.to_string()));
+        }
+    }
+    Ok(vars)
+}
 
 fn is_current_platform(platform: Platform) -> bool {
     match platform {
@@ -353,6 +828,117 @@ fn matches_platform(platforms: &[Platform]) -> bool {
     platforms.iter().any(|p| is_current_platform(*p))
 }
 
+/// A file as `--dry-run` reports it: the selected tests, each with the reason
+/// it would be skipped, if that can be told without running anything
+#[derive(Debug)]
+pub struct PlannedFile {
+    pub suite: String,
+    pub file_path: PathBuf,
+    /// "setup", "test" or "teardown"
+    pub phase: &'static str,
+    pub tests: Vec<(TestCase, Option<String>)>,
+    pub parse_error: Option<String>,
+}
+
+/// The files and tests `run_suite` would run for `suite`, in order, without
+/// creating a work dir or running any commands. `%skip if` conditions are
+/// reported as `if: <condition>` rather than evaluated. With a
+/// `shuffle_seed`, test files list their tests in the shuffled order.
+pub fn plan_suite(
+    suite: &Suite,
+    filter: &TestFilter,
+    shuffle_seed: Option<u64>,
+) -> Vec<PlannedFile> {
+    let plan_file = |file_path: PathBuf,
+                     phase: &'static str,
+                     filter: &TestFilter,
+                     shuffle_seed: Option<u64>| {
+        let mut corpus = match parse_file(&file_path) {
+            Ok(corpus) => corpus,
+            Err(e) => {
+                return PlannedFile {
+                    suite: suite.name.clone(),
+                    file_path,
+                    phase,
+                    tests: vec![],
+                    parse_error: Some(e.to_string()),
+                };
+            }
+        };
+        let describe = |skip: &SkipDirective| match &skip.condition {
+            Some(condition) => format!("if: {}", condition),
+            None => skip
+                .message
+                .clone()
+                .unwrap_or_else(|| "skipped".to_string()),
+        };
+        let file_reason = if !matches_platform(&corpus.file_platform) {
+            let names: Vec<_> = corpus
+                .file_platform
+                .iter()
+                .map(|p| format!("{:?}", p).to_lowercase())
+                .collect();
+            Some(format!("platform: {}", names.join(", ")))
+        } else {
+            corpus.file_skip.as_ref().map(describe)
+        };
+        if let Some(seed) = shuffle_seed {
+            shuffle_tests(&mut corpus.tests, seed, &suite.name, &file_path);
+        }
+        let selected: Vec<_> = corpus
+            .tests
+            .into_iter()
+            .filter(|t| filter.selects(&file_path, t))
+            .collect();
+        let focused = selected.iter().any(|t| t.only);
+        let tests = selected
+            .into_iter()
+            .map(|test| {
+                let reason = file_reason.clone().or_else(|| {
+                    if focused && !test.only {
+                        Some("not focused".to_string())
+                    } else {
+                        test.skip.as_ref().map(describe)
+                    }
+                });
+                (test, reason)
+            })
+            .collect();
+        PlannedFile {
+            suite: suite.name.clone(),
+            file_path,
+            phase,
+            tests,
+            parse_error: None,
+        }
+    };
+
+    let mut files = Vec::new();
+    if suite.has_setup {
+        files.push(plan_file(
+            suite.path.join("_setup.txt"),
+            "setup",
+            &TestFilter::default(),
+            None,
+        ));
+    }
+    for file in suite.corpus_files() {
+        let planned = plan_file(file, "test", filter, shuffle_seed);
+        if !planned.tests.is_empty() || planned.parse_error.is_some() {
+            files.push(planned);
+        }
+    }
+    if suite.has_teardown {
+        files.push(plan_file(
+            suite.path.join("_teardown.txt"),
+            "teardown",
+            &TestFilter::default(),
+            None,
+        ));
+    }
+    files
+}
+
 fn should_skip(
     skip: &SkipDirective,
     work_dir: &Path,
@@ -364,7 +950,16 @@ fn should_skip(
     // Check shell condition - use file_shell if specified, otherwise default
     match &skip.condition {
         Some(condition) => {
-            let (output, exit_code) = run_command(condition, work_dir, env_vars, file_shell, true);
+            let CommandOutput {
+                output, exit_code, ..
+            } = run_command(
+                condition,
+                work_dir,
+                env_vars,
+                file_shell,
+                true,
+                CommandOptions::default(),
+            );
             if debug {
                 eprintln!(
                     "[DEBUG SKIP] condition: {:?}, exit_code: {}, output: {:?}, is_windows: {}",
@@ -374,6 +969,18 @@ fn should_skip(
                     cfg!(windows)
                 );
             }
+            if trace_enabled() {
+                eprintln!(
+                    "[TRACE run] skip condition {:?} exited {}: {}",
+                    condition,
+                    exit_code,
+                    if exit_code == 0 {
+                        "skipping"
+                    } else {
+                        "running"
+                    }
+                );
+            }
             if exit_code == 0 {
                 Some(
                     skip.message
@@ -400,18 +1007,65 @@ pub struct StreamingContext<'a> {
     pub name: String,
 }
 
-#[allow(clippy::too_many_arguments)]
 fn run_test(
     test: &TestCase,
-    work_dir: &Path,
-    suite_name: &str,
-    env_vars: &[(String, String)],
+    context: &FileContext,
     file_shell: Option<Shell>,
     streaming: Option<StreamingContext<'_>>,
     interruptible: bool,
+    options: &RunOptions,
     prior_vars: &HashMap<String, Value>,
 ) -> (TestResult, HashMap<String, Value>) {
+    let FileContext {
+        work_dir,
+        suite_name,
+        env_vars,
+        ..
+    } = *context;
     let start = Instant::now();
+    let file_shell = test.shell.or(file_shell);
+    let timeout = test.timeout.or(options.default_timeout);
+
+    // A matching platform block replaces the default expected output; with
+    // no match the default block is used
+    let platform_test;
+    let test = match test
+        .platform_outputs
+        .iter()
+        .find(|block| matches_platform(&block.platforms))
+    {
+        Some(block) => {
+            platform_test = TestCase {
+                expected_output: block.expected_output.clone(),
+                variables: block.variables.clone(),
+                ..test.clone()
+            };
+            &platform_test
+        }
+        None => test,
+    };
+
+    // Later entries win, so test-level %env overrides file-level settings
+    let test_env_vars;
+    let env_vars = if test.env_files.is_empty() && test.env.is_empty() {
+        env_vars
+    } else {
+        match load_env_files(&test.file_path, &test.env_files) {
+            Ok(loaded) => {
+                test_env_vars = [env_vars, &loaded, &test.env].concat();
+                &test_env_vars
+            }
+            Err(e) => return error_result(test, suite_name, e, start),
+        }
+    };
+
+    let retry_on = match test.retry_on.as_deref().map(Regex::new).transpose() {
+        Ok(retry_on) => retry_on,
+        Err(e) => {
+            let error = format!("invalid %retry-on regex: {}", e);
+            return error_result(test, suite_name, error, start);
+        }
+    };
 
     if let Some(skip) = &test.skip {
         if let Some(reason) = should_skip(skip, work_dir, env_vars, file_shell) {
@@ -427,6 +1081,9 @@ fn run_test(
                     warning: None,
                     elapsed: start.elapsed(),
                     suite: suite_name.to_string(),
+                    attempts: 0,
+                    mismatched_stream: None,
+                    repeats: None,
                 },
                 HashMap::new(),
             );
@@ -434,6 +1091,19 @@ fn run_test(
     }
 
     let effective_shell = file_shell.unwrap_or_else(default_shell);
+    if trace_enabled() {
+        eprintln!(
+            "[TRACE run] {}:{}: {:?} using shell {:?}",
+            test.file_path.display(),
+            test.start_line,
+            test.name,
+            effective_shell
+        );
+    }
+    let command = match substitute_command_variables(test, prior_vars) {
+        Ok(command) => command_with_args(&command, &test.args, effective_shell),
+        Err(e) => return error_result(test, suite_name, e, start),
+    };
 
     let warning = if effective_shell == Shell::Cmd && is_multiline(&test.command) {
         Some(
@@ -444,84 +1114,103 @@ fn run_test(
         None
     };
 
-    let (actual_output, exit_code) = if let Some(ctx) = streaming {
-        let tx = ctx.progress_tx.clone();
-        let suite = ctx.suite.clone();
-        let file = ctx.file.clone();
-        let name = ctx.name.clone();
-        run_command_streaming(
-            &test.command,
-            work_dir,
-            env_vars,
-            file_shell,
-            Box::new(move |line| {
-                let _ = tx.send(ProgressEvent::TestOutput {
-                    suite: suite.clone(),
-                    file: file.clone(),
-                    name: name.clone(),
-                    line: line.to_string(),
-                });
-            }),
-            interruptible,
-        )
-    } else {
-        run_command(&test.command, work_dir, env_vars, file_shell, interruptible)
+    // Run the command, retrying failures up to `%retry` more times (only when the
+    // output matches `%retry-on`, if given)
+    let mut retries_left = test.retry;
+    let options = CommandOptions {
+        timeout,
+        stdin: test.stdin.as_deref(),
+        split_stderr: test.expected_stderr.is_some(),
     };
-    let elapsed = start.elapsed();
+    let (actual_output, (passed, error, expected_output, captured), mismatched_stream) = loop {
+        let CommandOutput {
+            output: actual_output,
+            exit_code,
+            termination,
+            stderr,
+        } = if let Some(ctx) = &streaming {
+            let tx = ctx.progress_tx.clone();
+            let suite = ctx.suite.clone();
+            let file = ctx.file.clone();
+            let name = ctx.name.clone();
+            run_command_streaming(
+                &command,
+                work_dir,
+                env_vars,
+                file_shell,
+                Box::new(move |line| {
+                    let _ = tx.send(ProgressEvent::TestOutput {
+                        suite: suite.clone(),
+                        file: file.clone(),
+                        name: name.clone(),
+                        line: line.to_string(),
+                    });
+                }),
+                interruptible,
+                options,
+            )
+        } else {
+            run_command(
+                &command,
+                work_dir,
+                env_vars,
+                file_shell,
+                interruptible,
+                options,
+            )
+        };
 
-    let (passed, error, expected_output, captured) =
-        if test.variables.is_empty() && test.constraints.is_empty() {
-            let expected = &test.expected_output;
-            if expected.is_empty() {
-                (exit_code == 0, None, expected.clone(), HashMap::new())
-            } else {
-                (
-                    actual_output == *expected,
-                    None,
-                    expected.clone(),
-                    HashMap::new(),
-                )
+        let exit_code = match &test.exit_file {
+            Some(path) if termination.is_none() => read_exit_file(work_dir, path),
+            _ => Ok(exit_code),
+        };
+
+        // A command killed by a signal (e.g. a crash) fails even if its
+        // partial output happens to match, unless `%exit nonzero` accepts it.
+        // A timeout always fails.
+        let signal = match (termination, &test.exit) {
+            (Some(Termination::Signal(_)), Some(ExpectedExit::NonZero)) => None,
+            (Some(signal @ Termination::Signal(_)), Some(expected)) => {
+                Some(format!("expected exit code {}, {}", expected, signal))
             }
-        } else if !test.variables.is_empty() {
-            let matcher = Matcher::new(&test.variables, &test.constraints, env_vars);
-            match matcher.matches(&test.expected_output, &actual_output, prior_vars) {
-                Ok(match_result) => {
-                    if match_result.matched {
-                        (
-                            true,
-                            None,
-                            test.expected_output.clone(),
-                            match_result.captured,
-                        )
-                    } else {
-                        (false, None, test.expected_output.clone(), HashMap::new())
-                    }
-                }
-                Err(e) => (
-                    false,
-                    Some(e.to_string()),
-                    test.expected_output.clone(),
-                    HashMap::new(),
-                ),
+            (termination, _) => termination.map(|t| t.to_string()),
+        };
+        let check = match (signal, exit_code) {
+            (Some(error), _) | (None, Err(error)) => (
+                false,
+                Some(error),
+                test.expected_output.clone(),
+                HashMap::new(),
+            ),
+            (None, Ok(exit_code)) => {
+                check_output(test, &actual_output, exit_code, env_vars, prior_vars)
             }
-        } else {
-            // No variables but has constraints referencing prior vars
-            let matcher = Matcher::new(&test.variables, &test.constraints, env_vars);
-            let expected = &test.expected_output;
-            let output_matches = if expected.is_empty() {
-                exit_code == 0
-            } else {
-                actual_output == *expected
-            };
-            if output_matches {
-                match matcher.matches(&test.expected_output, &actual_output, prior_vars) {
-                    Ok(_) => (true, None, expected.clone(), HashMap::new()),
-                    Err(e) => (false, Some(e.to_string()), expected.clone(), HashMap::new()),
-                }
-            } else {
-                (false, None, expected.clone(), HashMap::new())
+        };
+        // With a %stderr block, report whichever stream didn't match
+        let (actual_output, check, mismatched_stream) = match (&test.expected_stderr, stderr) {
+            (Some(_), _) if !check.0 => {
+                let stream = check.1.is_none().then_some("stdout");
+                (actual_output, check, stream)
             }
+            (Some(expected), Some(stderr)) if stderr != *expected => (
+                stderr,
+                (false, None, expected.clone(), HashMap::new()),
+                Some("stderr"),
+            ),
+            _ => (actual_output, check, None),
         };
+        let retry = !check.0
+            && retries_left > 0
+            && !(interruptible && is_interrupted())
+            && retry_on
+                .as_ref()
+                .is_none_or(|re| re.is_match(&actual_output));
+        if !retry {
+            break (actual_output, check, mismatched_stream);
+        }
+        retries_left -= 1;
+    };
+    let elapsed = start.elapsed();
 
     (
         TestResult {
@@ -535,74 +1224,391 @@ fn run_test(
             warning,
             elapsed,
             suite: suite_name.to_string(),
+            attempts: test.retry - retries_left + 1,
+            mismatched_stream,
+            repeats: None,
         },
         captured,
     )
 }
 
-#[allow(clippy::too_many_arguments)]
-fn run_corpus_file(
-    file_path: &Path,
-    work_dir: &Path,
-    suite_name: &str,
+/// Read the exit code written by the command to `%exit-file`
+fn read_exit_file(work_dir: &Path, path: &str) -> Result<i32, String> {
+    let content = std::fs::read_to_string(work_dir.join(path))
+        .map_err(|e| format!("failed to read %exit-file '{}': {}", path, e))?;
+    content.trim().parse().map_err(|_| {
+        format!(
+            "%exit-file '{}' does not contain an exit code: {:?}",
+            path,
+            content.trim()
+        )
+    })
+}
+
+/// Describe the first pair of adjacent output lines that is out of `order`,
+/// ignoring trailing blank lines
+fn unsorted_pair(output: &str, order: SortOrder) -> Option<String> {
+    let mut lines: Vec<&str> = output.lines().collect();
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let index = lines.windows(2).position(|pair| match order {
+        SortOrder::Ascending => pair[0] > pair[1],
+        SortOrder::Descending => pair[0] < pair[1],
+    })?;
+    Some(format!(
+        "output is not in {} order: line {} {:?} comes before line {} {:?}",
+        order.name(),
+        index + 1,
+        lines[index],
+        index + 2,
+        lines[index + 1]
+    ))
+}
+
+/// Describe the first output line that repeats an earlier one, ignoring
+/// trailing blank lines
+fn duplicate_line(output: &str) -> Option<String> {
+    let mut lines: Vec<&str> = output.lines().collect();
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(first) = seen.insert(*line, index) {
+            return Some(format!(
+                "output has a duplicate line: line {} {:?} repeats line {}",
+                index + 1,
+                line,
+                first + 1
+            ));
+        }
+    }
+    None
+}
+
+/// Result of comparing a command's output against a test's expectations:
+/// (passed, error, expected output, captured variables)
+type OutputCheck = (bool, Option<String>, String, HashMap<String, Value>);
+
+fn check_output(
+    test: &TestCase,
+    actual_output: &str,
+    exit_code: i32,
     env_vars: &[(String, String)],
-    pattern: Option<&Regex>,
-    progress_tx: Option<&Sender<ProgressEvent>>,
-    stream_output: bool,
-    ignore_interruption: bool,
-) -> FileResult {
-    let corpus = match parse_file(file_path) {
-        Ok(corpus) => corpus,
-        Err(e) => {
-            return FileResult {
-                file_path: file_path.to_path_buf(),
-                results: vec![],
-                parse_error: Some(e.to_string()),
-            };
+    prior_vars: &HashMap<String, Value>,
+) -> OutputCheck {
+    if let Some(error) = test
+        .sort_order
+        .and_then(|order| unsorted_pair(actual_output, order))
+    {
+        return (
+            false,
+            Some(error),
+            test.expected_output.clone(),
+            HashMap::new(),
+        );
+    }
+
+    if let Some(error) = test
+        .expect_unique
+        .then(|| duplicate_line(actual_output))
+        .flatten()
+    {
+        return (
+            false,
+            Some(error),
+            test.expected_output.clone(),
+            HashMap::new(),
+        );
+    }
+
+    if let Some(substring) = test
+        .not_contains
+        .iter()
+        .find(|s| actual_output.contains(s.as_str()))
+    {
+        return (
+            false,
+            Some(format!("output unexpectedly contained: {}", substring)),
+            test.expected_output.clone(),
+            HashMap::new(),
+        );
+    }
+
+    if let Some(expected) = test.exit.as_ref().filter(|e| !e.matches(exit_code)) {
+        return (
+            false,
+            Some(format!(
+                "expected exit code {}, got {}",
+                expected, exit_code
+            )),
+            test.expected_output.clone(),
+            HashMap::new(),
+        );
+    }
+
+    if let Some(line_count) = test.line_count {
+        let lines = actual_output.lines().count();
+        if !line_count.matches(lines) {
+            let error = format!("expected {} output lines, got {}", line_count, lines);
+            return (
+                false,
+                Some(error),
+                test.expected_output.clone(),
+                HashMap::new(),
+            );
         }
-    };
+    }
 
-    // Helper to skip all tests in file
-    let skip_all_tests = |corpus: &cctr_corpus::CorpusFile,
-                          reason: String,
-                          progress_tx: Option<&Sender<ProgressEvent>>| {
-        let file_stem = file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
-        let mut results = Vec::new();
-        for test in &corpus.tests {
-            if let Some(tx) = progress_tx {
-                let _ = tx.send(ProgressEvent::TestStart {
-                    suite: suite_name.to_string(),
-                    file: file_stem.clone(),
-                    name: test.name.clone(),
-                });
-            }
-            let result = TestResult {
-                test: test.clone(),
-                passed: true,
-                skipped: true,
-                skip_reason: Some(reason.clone()),
-                actual_output: None,
-                expected_output: test.expected_output.clone(),
-                error: None,
-                warning: None,
-                elapsed: Duration::ZERO,
-                suite: suite_name.to_string(),
+    if test.match_mode == MatchMode::Regex {
+        let matcher = Matcher::new(&test.variables, &test.constraints, env_vars);
+        let result = regex_captures(&test.expected_output, actual_output).and_then(|captured| {
+            let Some(captured) = captured else {
+                return Ok(None);
             };
-            if let Some(tx) = progress_tx {
-                let _ = tx.send(ProgressEvent::TestComplete(Box::new(result.clone())));
+            matcher
+                .check_constraints(actual_output, prior_vars, &captured)
+                .map(|()| Some(captured))
+        });
+        match result {
+            Ok(Some(captured)) => (true, None, test.expected_output.clone(), captured),
+            Ok(None) => (false, None, test.expected_output.clone(), HashMap::new()),
+            Err(e) => (
+                false,
+                Some(e.to_string()),
+                test.expected_output.clone(),
+                HashMap::new(),
+            ),
+        }
+    } else if test.match_mode != MatchMode::Pattern {
+        match json_matches(test.match_mode, &test.expected_output, actual_output) {
+            Ok(matched) => (matched, None, test.expected_output.clone(), HashMap::new()),
+            Err(e) => (
+                false,
+                Some(e.to_string()),
+                test.expected_output.clone(),
+                HashMap::new(),
+            ),
+        }
+    } else if test.variables.is_empty() && test.constraints.is_empty() {
+        let expected = &test.expected_output;
+        if expected.is_empty() {
+            // %exit has already been checked above
+            let exit_ok = test.exit.is_some() || exit_code == 0;
+            (exit_ok, None, expected.clone(), HashMap::new())
+        } else {
+            (
+                output_equals(test, expected, actual_output),
+                None,
+                expected.clone(),
+                HashMap::new(),
+            )
+        }
+    } else if !test.variables.is_empty() {
+        let matcher = Matcher::new(&test.variables, &test.constraints, env_vars)
+            .ascii(test.ascii)
+            .anchored(!test.contains);
+        match matcher.matches(&test.expected_output, actual_output, prior_vars) {
+            Ok(match_result) => {
+                if match_result.matched {
+                    (
+                        true,
+                        None,
+                        test.expected_output.clone(),
+                        match_result.captured,
+                    )
+                } else {
+                    (false, None, test.expected_output.clone(), HashMap::new())
+                }
             }
-            results.push(result);
+            Err(e) => (
+                false,
+                Some(e.to_string()),
+                test.expected_output.clone(),
+                HashMap::new(),
+            ),
         }
-        FileResult {
-            file_path: file_path.to_path_buf(),
-            results,
-            parse_error: None,
+    } else {
+        // No variables but has constraints referencing prior vars
+        let matcher = Matcher::new(&test.variables, &test.constraints, env_vars).ascii(test.ascii);
+        let expected = &test.expected_output;
+        let output_matches = if expected.is_empty() {
+            test.exit.is_some() || exit_code == 0
+        } else {
+            output_equals(test, expected, actual_output)
+        };
+        if output_matches {
+            match matcher.check_constraints(actual_output, prior_vars, &HashMap::new()) {
+                Ok(()) => (true, None, expected.clone(), HashMap::new()),
+                Err(e) => (false, Some(e.to_string()), expected.clone(), HashMap::new()),
+            }
+        } else {
+            (false, None, expected.clone(), HashMap::new())
         }
+    }
+}
+
+/// A failed result for a test that could not be run at all
+fn error_result(
+    test: &TestCase,
+    suite_name: &str,
+    error: String,
+    start: Instant,
+) -> (TestResult, HashMap<String, Value>) {
+    (
+        TestResult {
+            test: test.clone(),
+            passed: false,
+            skipped: false,
+            skip_reason: None,
+            actual_output: None,
+            expected_output: test.expected_output.clone(),
+            error: Some(error),
+            warning: None,
+            elapsed: start.elapsed(),
+            suite: suite_name.to_string(),
+            attempts: 0,
+            mismatched_stream: None,
+            repeats: None,
+        },
+        HashMap::new(),
+    )
+}
+
+/// Whole-output comparison for tests without captures, honoring any
+/// `%numeric-tolerance` / `%numeric-rel-tolerance` on the test. Under
+/// `%contains` the expected output only has to appear somewhere in it, and
+/// under `%trim` trailing whitespace on each line is ignored.
+fn output_equals(test: &TestCase, expected: &str, actual: &str) -> bool {
+    let (expected, actual) = if test.trim {
+        (
+            Cow::Owned(trim_line_ends(expected)),
+            Cow::Owned(trim_line_ends(actual)),
+        )
+    } else {
+        (Cow::Borrowed(expected), Cow::Borrowed(actual))
     };
+    let (expected, actual) = (expected.as_ref(), actual.as_ref());
+
+    actual == expected
+        || (test.contains && actual.contains(expected))
+        || (test.unordered && same_lines_unordered(expected, actual))
+        || (test.numeric_tolerance.is_set()
+            && numbers_match_within(expected, actual, test.numeric_tolerance))
+}
+
+/// Strip trailing whitespace, including `\r`, from every line
+fn trim_line_ends(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether `expected` and `actual` have the same lines, counting repeats,
+/// in any order
+fn same_lines_unordered(expected: &str, actual: &str) -> bool {
+    let mut expected: Vec<&str> = expected.lines().collect();
+    let mut actual: Vec<&str> = actual.lines().collect();
+    expected.sort_unstable();
+    actual.sort_unstable();
+    expected == actual
+}
+
+fn run_corpus_file(
+    context: &FileContext,
+    filter: &TestFilter,
+    options: &RunOptions,
+    progress_tx: Option<&Sender<ProgressEvent>>,
+    ignore_interruption: bool,
+) -> FileResult {
+    let file_path = context.file_path;
+    let corpus = match parse_file(file_path) {
+        Ok(corpus) => corpus,
+        Err(e) => {
+            return FileResult {
+                file_path: file_path.to_path_buf(),
+                results: vec![],
+                parse_error: Some(e.to_string()),
+            };
+        }
+    };
+
+    run_corpus(
+        corpus,
+        context,
+        filter,
+        options,
+        progress_tx,
+        ignore_interruption,
+    )
+}
+
+/// Run an already-parsed corpus file as described by `context`, selecting
+/// tests with `filter`. With `ignore_interruption`, Ctrl-C doesn't stop the
+/// run, as for teardown. Each test runs `options.repeat` times (see
+/// [`Repeats`]).
+pub fn run_corpus(
+    mut corpus: CorpusFile,
+    context: &FileContext,
+    filter: &TestFilter,
+    options: &RunOptions,
+    progress_tx: Option<&Sender<ProgressEvent>>,
+    ignore_interruption: bool,
+) -> FileResult {
+    let FileContext {
+        file_path,
+        work_dir,
+        suite_name,
+        env_vars,
+    } = *context;
+    if let Some(seed) = options.shuffle_seed {
+        shuffle_tests(&mut corpus.tests, seed, suite_name, file_path);
+    }
+    // Helper to skip all tests in file
+    let skip_all_tests =
+        |corpus: &CorpusFile, reason: String, progress_tx: Option<&Sender<ProgressEvent>>| {
+            let file_stem = file_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string();
+            let mut results = Vec::new();
+            for test in &corpus.tests {
+                if let Some(tx) = progress_tx {
+                    let _ = tx.send(ProgressEvent::TestStart {
+                        suite: suite_name.to_string(),
+                        file: file_stem.clone(),
+                        name: test.name.clone(),
+                    });
+                }
+                let result = TestResult {
+                    test: test.clone(),
+                    passed: true,
+                    skipped: true,
+                    skip_reason: Some(reason.clone()),
+                    actual_output: None,
+                    expected_output: test.expected_output.clone(),
+                    error: None,
+                    warning: None,
+                    elapsed: Duration::ZERO,
+                    suite: suite_name.to_string(),
+                    attempts: 0,
+                    mismatched_stream: None,
+                    repeats: None,
+                };
+                if let Some(tx) = progress_tx {
+                    let _ = tx.send(ProgressEvent::TestComplete(Box::new(result.clone())));
+                }
+                results.push(result);
+            }
+            FileResult {
+                file_path: file_path.to_path_buf(),
+                results,
+                parse_error: None,
+            }
+        };
 
     // Check file-level platform restriction
     if !matches_platform(&corpus.file_platform) {
@@ -615,6 +1621,24 @@ fn run_corpus_file(
         return skip_all_tests(&corpus, reason, progress_tx);
     }
 
+    // Layer file-level env files, then file-level %env, over the built-in
+    // CCTR_* variables
+    let file_env_vars = match load_env_files(file_path, &corpus.file_env_files) {
+        Ok(loaded) => [env_vars, &loaded, &corpus.file_env].concat(),
+        Err(e) => {
+            return FileResult {
+                file_path: file_path.to_path_buf(),
+                results: vec![],
+                parse_error: Some(e),
+            };
+        }
+    };
+    let env_vars = file_env_vars.as_slice();
+    let test_context = FileContext {
+        env_vars,
+        ..*context
+    };
+
     // Handle file-level skip directive
     if let Some(skip) = &corpus.file_skip {
         if let Some(reason) = should_skip(skip, work_dir, env_vars, corpus.file_shell) {
@@ -624,13 +1648,6 @@ fn run_corpus_file(
 
     let mut results = Vec::new();
 
-    let file_matches = pattern.is_none_or(|pat| {
-        file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .is_some_and(|name| pat.is_match(name))
-    });
-
     let file_stem = file_path
         .file_stem()
         .and_then(|s| s.to_str())
@@ -641,16 +1658,19 @@ fn run_corpus_file(
     let mut require_failed: Option<String> = None;
     // Accumulate variables captured across tests within this file
     let mut persistent_vars: HashMap<String, Value> = HashMap::new();
+    // With %only on any selected test, the other selected tests are skipped
+    let focused = corpus
+        .tests
+        .iter()
+        .any(|t| t.only && filter.selects(file_path, t));
 
     for test in corpus.tests {
         if !ignore_interruption && is_interrupted() {
             break;
         }
 
-        if let Some(pat) = pattern {
-            if !file_matches && !pat.is_match(&test.name) {
-                continue;
-            }
+        if !filter.selects(file_path, &test) {
+            continue;
         }
 
         if let Some(tx) = progress_tx {
@@ -661,18 +1681,28 @@ fn run_corpus_file(
             });
         }
 
-        if let Some(ref failed_test) = require_failed {
+        let skip_reason = if focused && !test.only {
+            Some("not focused".to_string())
+        } else {
+            require_failed
+                .as_ref()
+                .map(|failed_test| format!("required test '{}' failed", failed_test))
+        };
+        if let Some(skip_reason) = skip_reason {
             let result = TestResult {
                 test: test.clone(),
                 passed: true,
                 skipped: true,
-                skip_reason: Some(format!("required test '{}' failed", failed_test)),
+                skip_reason: Some(skip_reason),
                 actual_output: None,
                 expected_output: test.expected_output.clone(),
                 error: None,
                 warning: None,
                 elapsed: Duration::ZERO,
                 suite: suite_name.to_string(),
+                attempts: 0,
+                mismatched_stream: None,
+                repeats: None,
             };
             if let Some(tx) = progress_tx {
                 let _ = tx.send(ProgressEvent::TestComplete(Box::new(result.clone())));
@@ -681,27 +1711,52 @@ fn run_corpus_file(
             continue;
         }
 
-        let streaming = if stream_output {
-            progress_tx.map(|tx| StreamingContext {
-                progress_tx: tx,
-                suite: suite_name.to_string(),
-                file: file_stem.clone(),
-                name: test.name.clone(),
-            })
-        } else {
-            None
+        let streaming = || {
+            if options.stream_output {
+                progress_tx.map(|tx| StreamingContext {
+                    progress_tx: tx,
+                    suite: suite_name.to_string(),
+                    file: file_stem.clone(),
+                    name: test.name.clone(),
+                })
+            } else {
+                None
+            }
+        };
+        let run = || {
+            run_test(
+                &test,
+                &test_context,
+                corpus.file_shell,
+                streaming(),
+                !ignore_interruption,
+                options,
+                &persistent_vars,
+            )
         };
 
-        let (result, captured) = run_test(
-            &test,
-            work_dir,
-            suite_name,
-            env_vars,
-            corpus.file_shell,
-            streaming,
-            !ignore_interruption,
-            &persistent_vars,
-        );
+        let (mut result, captured) = run();
+
+        // With --repeat, run the test again and report the first failing run,
+        // along with how many of the runs passed
+        if options.repeat > 1 && !result.skipped {
+            let mut repeats = Repeats {
+                runs: 1,
+                passed: u32::from(result.passed),
+            };
+            let mut elapsed = result.elapsed;
+            while repeats.runs < options.repeat && (ignore_interruption || !is_interrupted()) {
+                let (again, _) = run();
+                repeats.runs += 1;
+                repeats.passed += u32::from(again.passed);
+                elapsed += again.elapsed;
+                if result.passed && !again.passed {
+                    result = again;
+                }
+            }
+            result.elapsed = elapsed;
+            result.repeats = Some(repeats);
+        }
 
         if result.passed && !result.skipped {
             persistent_vars.extend(captured);
@@ -731,9 +1786,9 @@ fn run_corpus_file(
 
 pub fn run_suite(
     suite: &Suite,
-    pattern: Option<&Regex>,
+    filter: &TestFilter,
+    options: &RunOptions,
     progress_tx: Option<&Sender<ProgressEvent>>,
-    stream_output: bool,
 ) -> SuiteResult {
     let start = Instant::now();
     let mut file_results = Vec::new();
@@ -781,8 +1836,8 @@ pub fn run_suite(
                 suite,
                 work_dir,
                 &env_vars,
+                options,
                 progress_tx,
-                stream_output,
                 &mut file_results,
             );
             return SuiteResult {
@@ -804,13 +1859,15 @@ pub fn run_suite(
     if suite.has_setup {
         let setup_file = suite.path.join("_setup.txt");
         let file_result = run_corpus_file(
-            &setup_file,
-            work_dir,
-            &suite.name,
-            &env_vars,
-            None, // Setup always runs all tests regardless of pattern
+            &FileContext {
+                file_path: &setup_file,
+                work_dir,
+                suite_name: &suite.name,
+                env_vars: &env_vars,
+            },
+            &TestFilter::default(), // Setup always runs all tests regardless of filters
+            &options.in_file_order(),
             progress_tx,
-            stream_output,
             false, // Setup can be interrupted
         );
         setup_passed = file_result.passed();
@@ -830,13 +1887,15 @@ pub fn run_suite(
                 break;
             }
             let file_result = run_corpus_file(
-                &corpus_file,
-                work_dir,
-                &suite.name,
-                &env_vars,
-                pattern,
+                &FileContext {
+                    file_path: &corpus_file,
+                    work_dir,
+                    suite_name: &suite.name,
+                    env_vars: &env_vars,
+                },
+                filter,
+                options,
                 progress_tx,
-                stream_output,
                 false, // Main tests can be interrupted
             );
             file_results.push(file_result);
@@ -848,8 +1907,8 @@ pub fn run_suite(
         suite,
         work_dir,
         &env_vars,
+        options,
         progress_tx,
-        stream_output,
         &mut file_results,
     );
 
@@ -865,21 +1924,23 @@ fn run_teardown_if_exists(
     suite: &Suite,
     work_dir: &Path,
     env_vars: &[(String, String)],
+    options: &RunOptions,
     progress_tx: Option<&Sender<ProgressEvent>>,
-    stream_output: bool,
     file_results: &mut Vec<FileResult>,
 ) {
     if suite.has_teardown {
         IN_TEARDOWN.store(true, Ordering::SeqCst);
         let teardown_file = suite.path.join("_teardown.txt");
         let file_result = run_corpus_file(
-            &teardown_file,
-            work_dir,
-            &suite.name,
-            env_vars,
-            None, // Teardown always runs all tests regardless of pattern
+            &FileContext {
+                file_path: &teardown_file,
+                work_dir,
+                suite_name: &suite.name,
+                env_vars,
+            },
+            &TestFilter::default(), // Teardown always runs all tests regardless of filters
+            &options.in_file_order(),
             progress_tx,
-            stream_output,
             true, // CRITICAL: Teardown must ALWAYS run, even if interrupted
         );
         file_results.push(file_result);
@@ -907,10 +1968,37 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Parse `content` as a corpus file and run it directly in `work_dir`, with
+/// no temp dir, fixture or discovery. Only the given `env` is added to the
+/// test environment.
+pub fn run_corpus_str(
+    content: &str,
+    work_dir: &Path,
+    env: &[(String, String)],
+) -> Result<FileResult, ParseError> {
+    let path = PathBuf::from("<string>");
+    let corpus = parse_content(content, &path)?;
+    Ok(run_corpus(
+        corpus,
+        &FileContext {
+            file_path: &path,
+            work_dir,
+            suite_name: "string",
+            env_vars: env,
+        },
+        &TestFilter::default(),
+        &RunOptions::default(),
+        None,
+        true,
+    ))
+}
+
+/// Run a corpus read from stdin in a fresh temporary directory, as a suite
+/// named "stdin"
 pub fn run_from_stdin(
     content: &str,
+    options: &RunOptions,
     progress_tx: Option<&Sender<ProgressEvent>>,
-    stream_output: bool,
 ) -> SuiteResult {
     let start = Instant::now();
 
@@ -925,6 +2013,7 @@ pub fn run_from_stdin(
                 has_setup: false,
                 has_teardown: false,
                 single_file: None,
+                include_hidden: false,
             };
             return SuiteResult {
                 suite,
@@ -945,6 +2034,7 @@ pub fn run_from_stdin(
                 has_setup: false,
                 has_teardown: false,
                 single_file: None,
+                include_hidden: false,
             };
             return SuiteResult {
                 suite,
@@ -975,46 +2065,19 @@ pub fn run_from_stdin(
         ),
     ];
 
-    let mut results = Vec::new();
-    let mut persistent_vars: HashMap<String, Value> = HashMap::new();
-    for test in corpus.tests {
-        if let Some(tx) = progress_tx {
-            let _ = tx.send(ProgressEvent::TestStart {
-                suite: "stdin".to_string(),
-                file: "stdin".to_string(),
-                name: test.name.clone(),
-            });
-        }
-
-        let streaming = if stream_output {
-            progress_tx.map(|tx| StreamingContext {
-                progress_tx: tx,
-                suite: "stdin".to_string(),
-                file: "stdin".to_string(),
-                name: test.name.clone(),
-            })
-        } else {
-            None
-        };
-
-        let (result, captured) = run_test(
-            &test,
-            &work_dir,
-            "stdin",
-            &env_vars,
-            corpus.file_shell,
-            streaming,
-            true,
-            &persistent_vars,
-        );
-        if result.passed && !result.skipped {
-            persistent_vars.extend(captured);
-        }
-        if let Some(tx) = progress_tx {
-            let _ = tx.send(ProgressEvent::TestComplete(Box::new(result.clone())));
-        }
-        results.push(result);
-    }
+    let file_result = run_corpus(
+        corpus,
+        &FileContext {
+            file_path: &stdin_path,
+            work_dir: &work_dir,
+            suite_name: "stdin",
+            env_vars: &env_vars,
+        },
+        &TestFilter::default(),
+        options,
+        progress_tx,
+        false,
+    );
 
     let suite = Suite {
         name: "stdin".to_string(),
@@ -1023,15 +2086,12 @@ pub fn run_from_stdin(
         has_setup: false,
         has_teardown: false,
         single_file: None,
+        include_hidden: false,
     };
 
     SuiteResult {
         suite,
-        file_results: vec![FileResult {
-            file_path: stdin_path,
-            results,
-            parse_error: None,
-        }],
+        file_results: vec![file_result],
         setup_error: None,
         elapsed: start.elapsed(),
     }
@@ -1041,6 +2101,7 @@ pub fn run_from_stdin(
 mod tests {
     use super::*;
     use std::fs;
+    use std::sync::mpsc;
     use tempfile::TempDir;
 
     fn create_suite(dir: &Path, name: &str) -> Suite {
@@ -1062,7 +2123,7 @@ mod tests {
             "===\necho test\n===\necho hello\n---\nhello\n",
         );
 
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         assert!(result.passed());
         assert_eq!(result.total_tests(), 1);
         assert_eq!(result.passed_tests(), 1);
@@ -1077,7 +2138,7 @@ mod tests {
             "===\nfailing test\n===\necho wrong\n---\nexpected\n",
         );
 
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         assert!(!result.passed());
         assert_eq!(result.passed_tests(), 0);
     }
@@ -1091,7 +2152,7 @@ mod tests {
             "===\nexit only\n===\ntrue\n---\n",
         );
 
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         assert!(result.passed());
     }
 
@@ -1104,10 +2165,64 @@ mod tests {
             "===\nexit only fail\n===\nfalse\n---\n",
         );
 
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         assert!(!result.passed());
     }
 
+    #[test]
+    fn test_expected_exit_codes() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "exit_codes");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            r#"===
+in range
+%exit 1-3
+===
+exit 2
+---
+
+===
+outside range
+%exit 1-3
+===
+exit 5
+---
+
+===
+in list
+%exit 1,2,4
+===
+echo partial; exit 4
+---
+partial
+
+===
+nonzero
+%exit nonzero
+===
+false
+---
+
+===
+exact code but zero
+%exit 3
+===
+true
+---
+"#,
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let failed: Vec<&str> = result.file_results[0]
+            .results
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.test.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["outside range", "exact code but zero"]);
+    }
+
     #[test]
     fn test_env_vars() {
         let tmp = TempDir::new().unwrap();
@@ -1117,7 +2232,7 @@ mod tests {
             "===\nenv var test\n===\necho $CCTR_WORK_DIR\n---\n",
         );
 
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         // Just checks exit code 0 since expected is empty
         assert!(result.passed());
     }
@@ -1135,7 +2250,805 @@ mod tests {
         );
 
         let suite = Suite::new(suite_dir, tmp.path());
-        let result = run_suite(&suite, None, None, false);
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn test_env_file_directives() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "env_file");
+        fs::write(
+            suite.path.join("file.env"),
+            "# shared settings\nGREETING=hello\nexport TARGET=\"world\"\n",
+        )
+        .unwrap();
+        fs::write(suite.path.join("test.env"), "TARGET='there'\n").unwrap();
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "%env-file file.env\n\n===\nfile env\n===\necho $GREETING $TARGET\n---\nhello world\n\n===\ntest env overrides file env\n%env-file test.env\n===\necho $GREETING $TARGET\n---\nhello there\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed());
+        assert_eq!(result.passed_tests(), 2);
+    }
+
+    #[test]
+    fn test_env_directives() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "env");
+        fs::write(suite.path.join("test.env"), "FOO=from-file\n").unwrap();
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "%env FOO=bar\n%env TARGET=world\n\n===\nfile env\n===\necho $FOO $TARGET\n---\nbar world\n\n===\ntest env overrides file env\n%env FOO=baz\n===\necho $FOO $TARGET\n---\nbaz world\n\n===\n%env overrides %env-file\n%env-file test.env\n%env FOO=inline\n===\necho $FOO\n---\ninline\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed(), "{:?}", result.file_results[0].results);
+        assert_eq!(result.passed_tests(), 3);
+    }
+
+    #[test]
+    fn test_env_file_missing() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "env_file_missing");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nmissing env file\n%env-file nope.env\n===\necho hi\n---\nhi\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(!result.passed());
+        let error = result.file_results[0].results[0].error.as_deref().unwrap();
+        assert!(error.starts_with("failed to read env file"));
+        assert!(error.contains("nope.env"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_test_level_shell_override() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "shell_override");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "%shell bash\n\n===\nbash test\n===\necho ${BASH_VERSION:+bash}\n---\nbash\n\n===\nsh test\n%shell sh\n===\n[ \"$0\" = sh ] && echo sh\n---\nsh\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed(), "{:?}", result.file_results[0].results);
+        assert_eq!(result.passed_tests(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_on_matching_output() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "retry_on");
+        // Fails with a transient error on the first two attempts, then succeeds
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nflaky\n%retry 2\n%retry-on /connection reset/\n===\nn=$(cat count 2>/dev/null || echo 0); echo $((n+1)) > count; if [ $n -lt 2 ]; then echo connection reset; else echo ok; fi\n---\nok\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed(), "{:?}", result.file_results[0].results);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_reports_attempts() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "retry_attempts");
+        // Fails the first time only, using a marker file to remember the run
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nflaky once\n%retry 3\n===\nif [ -e marker ]; then echo ok; else touch marker; echo first try; fi\n---\nok\n\n===\nalways fails\n%retry 2\n===\necho nope\n---\nok\n\n===\nno retry needed\n%retry 2\n===\necho ok\n---\nok\n\n===\nskipped\n%retry 2\n%skip\n===\nfalse\n---\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed);
+        assert_eq!(results[0].attempts, 2);
+        assert!(!results[1].passed);
+        assert_eq!(results[1].attempts, 3);
+        assert_eq!(results[1].actual_output.as_deref(), Some("nope"));
+        assert!(results[2].passed);
+        assert_eq!(results[2].attempts, 1);
+        assert!(results[3].skipped);
+        assert_eq!(results[3].attempts, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_on_non_matching_output_fails_immediately() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "retry_on_no_match");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nreal bug\n%retry 2\n%retry-on /connection reset/\n===\nn=$(cat count 2>/dev/null || echo 0); echo $((n+1)) > count; if [ $n -lt 1 ]; then echo assertion failed; else echo ok; fi\n---\nok\n\n===\nattempts\n===\ncat count\n---\n1\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(!results[0].passed);
+        assert_eq!(results[0].attempts, 1);
+        assert_eq!(
+            results[0].actual_output.as_deref(),
+            Some("assertion failed")
+        );
+        assert!(results[1].passed, "expected exactly one attempt");
+    }
+
+    #[test]
+    fn test_tag_filters() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "tags");
+        create_test_file(
+            &suite.path.join("net.txt"),
+            "%tags Network\n\n===\nfetch\n===\necho fetch\n---\nfetch\n\n===\nslow fetch\n%tags SLOW\n===\necho slow fetch\n---\nslow fetch\n",
+        );
+        create_test_file(
+            &suite.path.join("local.txt"),
+            "===\nslow local\n%tags slow\n===\necho slow local\n---\nslow local\n\n===\nuntagged\n===\necho untagged\n---\nuntagged\n",
+        );
+        let run = |tags: &[&str], skip_tags: &[&str]| {
+            let filter = TestFilter {
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                skip_tags: skip_tags.iter().map(|t| t.to_string()).collect(),
+                ..Default::default()
+            };
+            let result = run_suite(&suite, &filter, &RunOptions::default(), None);
+            assert!(result.passed());
+            let mut names: Vec<_> = result
+                .file_results
+                .iter()
+                .flat_map(|f| &f.results)
+                .map(|r| r.test.name.clone())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(run(&[], &[]).len(), 4);
+        // File-level tags apply to every test in the file
+        assert_eq!(run(&["network"], &[]), ["fetch", "slow fetch"]);
+        // Tags match case-insensitively, and any requested tag selects a test
+        assert_eq!(run(&["slow"], &[]), ["slow fetch", "slow local"]);
+        assert_eq!(
+            run(&["slow", "network"], &[]),
+            ["fetch", "slow fetch", "slow local"]
+        );
+        assert_eq!(run(&[], &["slow"]), ["fetch", "untagged"]);
+        assert_eq!(run(&["network"], &["slow"]), ["fetch"]);
+    }
+
+    #[test]
+    fn test_exclude_filter() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "exclude");
+        create_test_file(
+            &suite.path.join("api.txt"),
+            "===\nget user\n===\necho get\n---\nget\n\n===\nget user slow\n===\necho slow\n---\nslow\n\n===\ndelete user\n===\necho delete\n---\ndelete\n",
+        );
+        create_test_file(
+            &suite.path.join("slow_import.txt"),
+            "===\nimport\n===\necho import\n---\nimport\n",
+        );
+        create_test_file(
+            &suite.path.join("_setup.txt"),
+            "===\nslow setup\n===\ntrue\n---\n",
+        );
+        let suite = Suite::new(suite.path, tmp.path());
+        let run = |pattern: Option<&str>, exclude: &[&str]| {
+            let filter = TestFilter {
+                pattern: pattern.map(|p| Regex::new(p).unwrap()),
+                exclude: exclude.iter().map(|e| e.to_string()).collect(),
+                ..Default::default()
+            };
+            let result = run_suite(&suite, &filter, &RunOptions::default(), None);
+            assert!(result.passed());
+            let mut names: Vec<_> = result
+                .file_results
+                .iter()
+                .flat_map(|f| &f.results)
+                .map(|r| r.test.name.clone())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Setup is never excluded; the file stem "slow_import" is
+        assert_eq!(
+            run(None, &["slow"]),
+            ["delete user", "get user", "slow setup"]
+        );
+        // Exclusions are applied after the pattern, and any of them excludes
+        assert_eq!(
+            run(Some("user"), &["slow"]),
+            ["delete user", "get user", "slow setup"]
+        );
+        assert_eq!(
+            run(Some("user"), &["slow", "delete"]),
+            ["get user", "slow setup"]
+        );
+    }
+
+    #[test]
+    fn test_command_with_args_quoting() {
+        let args = vec!["two words".to_string(), "it's".to_string()];
+        assert_eq!(
+            command_with_args("./run.sh\n", &args, Shell::Bash),
+            r"./run.sh 'two words' 'it'\''s'"
+        );
+        assert_eq!(
+            command_with_args("Write-Output", &args, Shell::PowerShell),
+            "Write-Output 'two words' 'it''s'"
+        );
+        assert_eq!(command_with_args("echo", &[], Shell::Sh), "echo");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_args_reach_command() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "args");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nappended args\n%args one \"two words\" '$HOME'\n===\nprintf '[%s]\\n'\n---\n[one]\n[two words]\n[$HOME]\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed(), "{:?}", result.file_results[0].results);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal_termination_reported() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "signal");
+        // The output matches, but the command is killed before it exits
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+killed
+===
+echo partial
+kill -KILL $$
+---
+partial
+
+===
+nonzero exit
+===
+exit 3
+---
+",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(!results[0].passed);
+        assert_eq!(
+            results[0].error.as_deref(),
+            Some("terminated by signal SIGKILL (9)")
+        );
+        assert_eq!(results[0].actual_output.as_deref(), Some("partial"));
+        assert!(!results[1].passed);
+        assert_eq!(results[1].error, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal_termination_with_expected_exit() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "crash");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+crash accepted
+%exit nonzero
+===
+echo before crash
+kill -SEGV $$
+---
+before crash
+
+===
+crash is not a listed code
+%exit 1-3
+===
+kill -SEGV $$
+---
+",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed, "{:?}", results[0].error);
+        assert!(!results[1].passed);
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("expected exit code 1-3, terminated by signal SIGSEGV (11)")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_fails_with_expected_nonzero_exit() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "timeout_nonzero");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+slow failure
+%timeout 1
+%exit nonzero
+===
+sleep 3; exit 1
+---
+",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(!results[0].passed);
+        assert_eq!(results[0].error.as_deref(), Some("timed out after 1s"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_command() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "timeout");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+too slow
+%timeout 1
+===
+sleep 5
+---
+",
+        );
+
+        let start = Instant::now();
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(!results[0].passed);
+        assert_eq!(results[0].error.as_deref(), Some("timed out after 1s"));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_default_timeout_applies_unless_test_has_its_own() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "default_timeout");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+hangs
+===
+sleep 10
+---
+
+===
+own timeout
+%timeout 3
+===
+sleep 1.5
+echo done
+---
+done
+",
+        );
+
+        let start = Instant::now();
+        let result = run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions {
+                default_timeout: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+            None,
+        );
+        let results = &result.file_results[0].results;
+        assert!(!results[0].passed);
+        assert_eq!(results[0].error.as_deref(), Some("timed out after 1s"));
+        assert!(results[1].passed);
+        assert!(start.elapsed() < Duration::from_secs(6));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_streaming_timeout_keeps_partial_output() {
+        let tmp = TempDir::new().unwrap();
+        let start = Instant::now();
+        let CommandOutput {
+            output,
+            termination: error,
+            ..
+        } = run_command_streaming(
+            "echo started; sleep 5",
+            tmp.path(),
+            &[],
+            Some(Shell::Bash),
+            Box::new(|_| {}),
+            false,
+            CommandOptions {
+                timeout: Some(Duration::from_millis(500)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(output, "started");
+        assert_eq!(
+            error,
+            Some(Termination::TimedOut(Duration::from_millis(500)))
+        );
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stdin_directive() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "stdin");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\ncat echoes stdin\n%stdin\nhello\n===\ncat\n---\nhello\n\n===\nlines end with a newline\n%stdin\none\ntwo\n===\nwc -l | tr -d ' '\n---\n2\n\n===\ncommand that ignores stdin\n%stdin\nunread\n===\ntrue\n---\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        assert!(result.passed(), "{:?}", result.file_results[0].results);
+        assert_eq!(result.passed_tests(), 3);
+    }
+
+    #[test]
+    fn test_only_directive_skips_unfocused_tests() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "only");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nfocused\n%only\n===\necho hi\n---\nhi\n\n===\nunfocused\n===\nfalse\n---\n",
+        );
+        create_test_file(
+            &suite.path.join("other.txt"),
+            "===\nno focus in this file\n===\ntrue\n---\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
         assert!(result.passed());
+        let results = &result
+            .file_results
+            .iter()
+            .find(|f| f.file_path.ends_with("test.txt"))
+            .unwrap()
+            .results;
+        assert!(!results[0].skipped);
+        assert!(results[1].skipped);
+        assert_eq!(results[1].skip_reason.as_deref(), Some("not focused"));
+        assert_eq!(result.total_tests(), 3);
+
+        // A pattern that excludes the focused test leaves nothing focused
+        let filter = TestFilter {
+            pattern: Some(Regex::new("unfocused").unwrap()),
+            ..TestFilter::default()
+        };
+        let result = run_suite(&suite, &filter, &RunOptions::default(), None);
+        assert!(!result.passed());
+    }
+
+    #[test]
+    fn test_unordered_directive() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "unordered");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nshuffled lines\n%unordered\n===\nprintf 'c\\na\\nb\\na\\n'\n---\na\na\nb\nc\n\n===\nrepeats must match\n%unordered\n===\nprintf 'a\\nb\\n'\n---\na\na\nb\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed, "{:?}", results[0]);
+        assert!(!results[1].passed);
+    }
+
+    #[test]
+    fn test_captures_persist_within_a_file_only() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "persist");
+        create_test_file(
+            &suite.path.join("a.txt"),
+            "===\ncapture token\n===\necho token 42\n---\ntoken {{ token }}\n\n===\nrelated output\n===\necho 43\n---\n{{ next }}\n---\nwhere\n* next == token + 1\n",
+        );
+        create_test_file(
+            &suite.path.join("b.txt"),
+            "===\nother file\n===\necho 43\n---\n{{ next }}\n---\nwhere\n* next == token + 1\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let a = &result.file_results[0].results;
+        assert!(a[1].passed, "{:?}", a[1]);
+        let b = &result.file_results[1].results;
+        assert!(!b[0].passed);
+        assert!(b[0].error.as_deref().unwrap().contains("token"));
+    }
+
+    #[test]
+    fn test_command_variables() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "command_vars");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\ntoo early\n===\necho {{ port }}\n---\n{{ other }}\n\n===\nstart\n===\necho 'port 8080 host a b'\n---\nport {{ port }} host {{ host }}\n\n===\nuse captures\n===\necho \"{{ port }}/{{host}}\" '{{ .Names }}'\n---\n8080/a b {{ .Names }}\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert_eq!(
+            results[0].error.as_deref(),
+            Some("command uses {{ port }}, but no earlier passing test in this file captured it")
+        );
+        assert!(results[1].passed, "{:?}", results[1]);
+        assert!(results[2].passed, "{:?}", results[2]);
+    }
+
+    #[test]
+    fn test_trim_directive() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "trim");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\ntrailing spaces forgiven\n%trim\n===\nprintf 'a  \\nb\\t\\r\\nc\\n'\n---\na\nb\nc\n\n===\ntrailing spaces matter\n===\nprintf 'a  \\nb\\n'\n---\na\nb\n\n===\ninner spaces still matter\n%trim\n===\nprintf 'a  b\\n'\n---\na b\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed, "{:?}", results[0]);
+        assert!(!results[1].passed);
+        assert!(!results[2].passed);
+    }
+
+    #[test]
+    fn test_same_lines_unordered() {
+        assert!(same_lines_unordered("a\nb\nc", "c\na\nb"));
+        assert!(!same_lines_unordered("a\na", "a"));
+        assert!(!same_lines_unordered("a\nb", "a\nc"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stderr_block() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "stderr");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nstreams match\n===\necho out; echo err >&2\n---\nout\n---\n%stderr\nerr\n\n===\nstderr must be empty\n===\necho out; echo oops >&2\n---\nout\n---\n%stderr\n\n===\nstdout differs\n===\necho wrong\n---\nout\n---\n%stderr\n",
+        );
+
+        for stream_output in [false, true] {
+            let (tx, _rx) = mpsc::channel();
+            let result = run_suite(
+                &suite,
+                &TestFilter::default(),
+                &RunOptions {
+                    stream_output,
+                    ..Default::default()
+                },
+                Some(&tx),
+            );
+            let results = &result.file_results[0].results;
+            assert!(results[0].passed, "{:?}", results[0]);
+            assert_eq!(results[0].mismatched_stream, None);
+
+            assert!(!results[1].passed);
+            assert_eq!(results[1].mismatched_stream, Some("stderr"));
+            assert_eq!(results[1].actual_output.as_deref(), Some("oops"));
+            assert_eq!(results[1].expected_output, "");
+
+            assert!(!results[2].passed);
+            assert_eq!(results[2].mismatched_stream, Some("stdout"));
+            assert_eq!(results[2].actual_output.as_deref(), Some("wrong"));
+        }
+    }
+
+    #[test]
+    fn test_not_directive() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "not");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===\nclean output\n%not ERROR\n===\necho built 3 files\n---\nbuilt {{ n }} files\n\n===\nforbidden substring\n%not ERROR\n===\necho built 3 files ERROR\n---\nbuilt 3 files ERROR\n",
+        );
+
+        let result = run_suite(&suite, &TestFilter::default(), &RunOptions::default(), None);
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed, "{:?}", results[0]);
+        assert!(!results[1].passed);
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("output unexpectedly contained: ERROR")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stdin_in_streaming_mode() {
+        let tmp = TempDir::new().unwrap();
+        let input = "x".repeat(1 << 20);
+        let CommandOutput {
+            output, exit_code, ..
+        } = run_command_streaming(
+            "wc -c | tr -d ' '",
+            tmp.path(),
+            &[],
+            Some(Shell::Bash),
+            Box::new(|_| {}),
+            false,
+            CommandOptions {
+                stdin: Some(&input),
+                ..Default::default()
+            },
+        );
+        assert_eq!(exit_code, 0);
+        assert_eq!(output, ((1 << 20) + 1).to_string());
+
+        // Exiting without reading a large input doesn't take cctr down
+        let CommandOutput { exit_code, .. } = run_command_streaming(
+            "true",
+            tmp.path(),
+            &[],
+            Some(Shell::Bash),
+            Box::new(|_| {}),
+            false,
+            CommandOptions {
+                stdin: Some(&input),
+                ..Default::default()
+            },
+        );
+        assert_eq!(exit_code, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_streaming_delivers_lines_incrementally() {
+        let tmp = TempDir::new().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        let CommandOutput {
+            output, exit_code, ..
+        } = run_command_streaming(
+            "echo first; sleep 0.5; echo second",
+            tmp.path(),
+            &[],
+            Some(Shell::Bash),
+            Box::new(move |line| {
+                let _ = tx.send((line.to_string(), start.elapsed()));
+            }),
+            false,
+            CommandOptions::default(),
+        );
+        let finished = start.elapsed();
+        let events: Vec<_> = rx.try_iter().collect();
+
+        assert_eq!(output, "first\nsecond");
+        assert_eq!(exit_code, 0);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, "first");
+        // The first line arrives while the command is still sleeping
+        assert!(
+            finished - events[0].1 >= Duration::from_millis(300),
+            "first line at {:?}, finished at {:?}",
+            events[0].1,
+            finished
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_streaming_handles_more_lines_than_channel_capacity() {
+        let tmp = TempDir::new().unwrap();
+        let count = STREAM_CHANNEL_CAPACITY * 20;
+        let CommandOutput {
+            output, exit_code, ..
+        } = run_command_streaming(
+            &format!("seq {}", count),
+            tmp.path(),
+            &[],
+            Some(Shell::Bash),
+            Box::new(|_| std::thread::sleep(Duration::from_micros(10))),
+            false,
+            CommandOptions::default(),
+        );
+        assert_eq!(exit_code, 0);
+        assert_eq!(output.lines().count(), count);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_repeat_counts_passing_runs() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "repeat");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            "===
+stable
+===
+echo ok
+---
+ok
+
+===
+fails every other run
+===
+n=$(cat n 2>/dev/null || echo 0); echo $((n+1)) > n
+if [ $((n % 2)) -eq 0 ]; then echo ok; else echo bad; fi
+---
+ok
+
+===
+skipped
+%skip
+===
+echo ok
+---
+ok
+",
+        );
+
+        let result = run_suite(
+            &suite,
+            &TestFilter::default(),
+            &RunOptions {
+                repeat: 4,
+                ..Default::default()
+            },
+            None,
+        );
+        let results = &result.file_results[0].results;
+        assert!(results[0].passed);
+        assert_eq!(results[0].repeats, Some(Repeats { runs: 4, passed: 4 }));
+        assert!(!results[1].passed);
+        assert_eq!(results[1].repeats, Some(Repeats { runs: 4, passed: 2 }));
+        assert!(results[1].repeats.unwrap().flaky());
+        assert_eq!(results[1].actual_output.as_deref(), Some("bad"));
+        assert_eq!(results[2].repeats, None);
+    }
+
+    #[test]
+    fn test_plan_suite_reports_skips_without_running() {
+        let tmp = TempDir::new().unwrap();
+        let suite = create_suite(tmp.path(), "plan");
+        let marker = tmp.path().join("ran");
+        create_test_file(
+            &suite.path.join("test.txt"),
+            &format!(
+                "===\nruns\n===\ntouch {0}\n---\n\n===\nconditional\n%skip if: touch {0}\n===\ntrue\n---\n\n===\nskipped\n%skip(later)\n===\ntrue\n---\n",
+                marker.display()
+            ),
+        );
+
+        let plan = plan_suite(&suite, &TestFilter::default(), None);
+        assert_eq!(plan.len(), 1);
+        let reasons: Vec<_> = plan[0].tests.iter().map(|(_, r)| r.clone()).collect();
+        assert_eq!(
+            reasons,
+            [
+                None,
+                Some(format!("if: touch {}", marker.display())),
+                Some("later".to_string())
+            ]
+        );
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_shuffle_seed_for_depends_on_seed_and_file() {
+        let seed = shuffle_seed_for(42, "suite/a.txt");
+        assert_eq!(seed, shuffle_seed_for(42, "suite/a.txt"));
+        assert_ne!(seed, shuffle_seed_for(43, "suite/a.txt"));
+        assert_ne!(seed, shuffle_seed_for(42, "suite/b.txt"));
     }
 }
@@ -1,4 +1,5 @@
 use crate::runner::TestResult;
+use crate::MatchMode;
 use regex::Regex;
 use std::path::Path;
 use std::sync::LazyLock;
@@ -18,9 +19,62 @@ pub fn update_corpus_file(file_path: &Path, results: &[&TestResult]) -> std::io:
         let actual = result.actual_output.as_ref().unwrap();
         let test = &result.test;
 
+        // Rewriting would replace every expected block with this platform's output
+        if !test.platform_outputs.is_empty() {
+            eprintln!(
+                "Not updating '{}': it has platform-specific expected output",
+                test.name
+            );
+            continue;
+        }
+
+        // Only the combined output is known to match the stdout block
+        if test.expected_stderr.is_some() {
+            eprintln!("Not updating '{}': it has a %stderr block", test.name);
+            continue;
+        }
+
+        // The expected output lives in the golden regex file
+        if let Some(path) = &test.expect_matches_file {
+            eprintln!(
+                "Not updating '{}': its expected output is the regex in {}",
+                test.name, path
+            );
+            continue;
+        }
+
+        // The output would be read back as a regex, so metacharacters break it
+        if test.match_mode == MatchMode::Regex {
+            eprintln!(
+                "Not updating '{}': its expected output is a %match regex pattern",
+                test.name
+            );
+            continue;
+        }
+
+        // The whole output would turn the substring check into an exact match
+        if test.contains {
+            eprintln!(
+                "Not updating '{}': its expected output is a %contains substring",
+                test.name
+            );
+            continue;
+        }
+
+        // Its lines are in the file that was %included, not this one
+        if test.file_path != file_path {
+            eprintln!(
+                "Not updating '{}': it is included from {}",
+                test.name,
+                test.file_path.display()
+            );
+            continue;
+        }
+
         let mut expected_start: Option<usize> = None;
         let mut expected_end: Option<usize> = None;
         let mut in_expected = false;
+        let mut in_fence = false;
 
         for (i, line) in lines.iter().enumerate() {
             let line_num = i + 1;
@@ -31,6 +85,18 @@ pub fn update_corpus_file(file_path: &Path, results: &[&TestResult]) -> std::io:
                 break;
             }
 
+            // Lines inside a %command ... %end fence belong to the command
+            if expected_start.is_none() {
+                if !in_fence && line.trim() == "%command" {
+                    in_fence = true;
+                    continue;
+                }
+                if in_fence {
+                    in_fence = line.trim() != "%end";
+                    continue;
+                }
+            }
+
             if SEPARATOR_PATTERN.is_match(line) && expected_start.is_none() {
                 expected_start = Some(i + 1);
                 in_expected = true;
@@ -0,0 +1,79 @@
+//! The interrupted flag is process-wide, so this lives in its own test binary
+use cctr::discover::discover_suites;
+use cctr::runner::{
+    count_not_executed, interrupted_message, run_suite, set_interrupted, RunOptions, TestFilter,
+};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[cfg(unix)]
+#[test]
+fn interrupted_run_stops_tests_but_runs_teardown() {
+    let tmp = TempDir::new().unwrap();
+    let suite_dir = tmp.path().join("slow");
+    fs::create_dir(&suite_dir).unwrap();
+    fs::write(
+        suite_dir.join("test.txt"),
+        "\
+===
+interrupted while running
+===
+sleep 5
+---
+
+===
+never started
+===
+echo one
+---
+one
+
+===
+also never started
+===
+echo two
+---
+two
+",
+    )
+    .unwrap();
+    let marker = tmp.path().join("teardown-ran");
+    fs::write(
+        suite_dir.join("_teardown.txt"),
+        format!("===\nteardown\n===\ntouch {}\n---\n", marker.display()),
+    )
+    .unwrap();
+
+    let suites = discover_suites(tmp.path(), false).unwrap();
+    let interrupter = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(300));
+        set_interrupted();
+    });
+    let results = vec![run_suite(
+        &suites[0],
+        &TestFilter::default(),
+        &RunOptions::default(),
+        None,
+    )];
+    interrupter.join().unwrap();
+
+    let file_results = &results[0].file_results;
+    assert_eq!(file_results.len(), 2);
+    assert_eq!(file_results[0].results.len(), 1);
+    assert!(!file_results[0].results[0].passed);
+    assert!(file_results[1].passed());
+    assert!(marker.exists());
+
+    let not_executed = count_not_executed(&suites, &TestFilter::default(), &results);
+    assert_eq!(not_executed, 2);
+    assert_eq!(
+        interrupted_message(not_executed),
+        "run interrupted; 2 tests not executed"
+    );
+    assert_eq!(
+        interrupted_message(1),
+        "run interrupted; 1 test not executed"
+    );
+}
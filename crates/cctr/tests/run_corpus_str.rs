@@ -0,0 +1,41 @@
+use cctr::run_corpus_str;
+use tempfile::TempDir;
+
+#[test]
+fn runs_corpus_string_in_work_dir() {
+    let tmp = TempDir::new().unwrap();
+    let content = "\
+===
+passing test
+===
+echo hello
+---
+hello
+
+===
+failing test
+===
+echo goodbye
+---
+hello
+";
+
+    let result = run_corpus_str(content, tmp.path(), &[]).unwrap();
+
+    assert!(result.parse_error.is_none());
+    assert_eq!(result.results.len(), 2);
+    assert_eq!(result.results[0].test.name, "passing test");
+    assert!(result.results[0].passed);
+    assert_eq!(result.results[1].test.name, "failing test");
+    assert!(!result.results[1].passed);
+    assert_eq!(result.results[1].actual_output.as_deref(), Some("goodbye"));
+    assert!(!result.passed());
+}
+
+#[test]
+fn returns_parse_error_for_invalid_corpus() {
+    let tmp = TempDir::new().unwrap();
+    let content = "===\nunterminated\n";
+
+    assert!(run_corpus_str(content, tmp.path(), &[]).is_err());
+}